@@ -0,0 +1,610 @@
+// Built-in styles for Protomaps basemap archives, like toolangi.pmtiles, in the same flavours as
+// https://github.com/protomaps/basemaps. Only the shapes are drawn, so there are no labels, and
+// places and points of interest are just dots.
+// See https://docs.protomaps.com/basemaps/layers for what's in each layer.
+
+use clap::ValueEnum;
+use serde_json::{Value, json};
+
+use crate::style::Style;
+
+// The layers every Protomaps basemap has, whichever version made it. Older ones also had
+// "natural" and "transit", which newer ones fold into landcover, landuse and roads.
+pub const SOURCE_LAYERS: [&str; 9] = [
+    "boundaries",
+    "buildings",
+    "earth",
+    "landcover",
+    "landuse",
+    "places",
+    "pois",
+    "roads",
+    "water",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Theme {
+    Light,
+    Dark,
+    White,
+    Grayscale,
+    Black,
+}
+
+// The colours a theme is made of.
+struct Flavor {
+    background: &'static str,
+    earth: &'static str,
+    park: &'static str,
+    wood: &'static str,
+    scrub: &'static str,
+    farmland: &'static str,
+    sand: &'static str,
+    glacier: &'static str,
+    urban: &'static str,
+    hospital: &'static str,
+    school: &'static str,
+    industrial: &'static str,
+    aerodrome: &'static str,
+    runway: &'static str,
+    water: &'static str,
+    buildings: &'static str,
+    tunnel: &'static str,
+    path: &'static str,
+    minor: &'static str,
+    minor_casing: &'static str,
+    major: &'static str,
+    major_casing: &'static str,
+    highway: &'static str,
+    highway_casing: &'static str,
+    rail: &'static str,
+    boundaries: &'static str,
+    pois: &'static str,
+    places: &'static str,
+}
+
+const LIGHT: Flavor = Flavor {
+    background: "#cccccc",
+    earth: "#e2dfda",
+    park: "#9cd3b4",
+    wood: "#a0d9a0",
+    scrub: "#99d2bb",
+    farmland: "#d8efd2",
+    sand: "#e2e0d7",
+    glacier: "#ffffff",
+    urban: "#e6e6e6",
+    hospital: "#e4dad9",
+    school: "#e4ded7",
+    industrial: "#d1dde1",
+    aerodrome: "#dadbdf",
+    runway: "#e9e9ed",
+    water: "#80deea",
+    buildings: "#cccccc",
+    tunnel: "#d5d5d5",
+    path: "#ebebeb",
+    minor: "#ffffff",
+    minor_casing: "#e0e0e0",
+    major: "#ffffff",
+    major_casing: "#e0e0e0",
+    highway: "#ffffff",
+    highway_casing: "#e0e0e0",
+    rail: "#a7b1b3",
+    boundaries: "#adadad",
+    pois: "#20834d",
+    places: "#5c5c5c",
+};
+
+const DARK: Flavor = Flavor {
+    background: "#34373d",
+    earth: "#1f1f1f",
+    park: "#232325",
+    wood: "#202121",
+    scrub: "#222323",
+    farmland: "#212322",
+    sand: "#212123",
+    glacier: "#1c1c1c",
+    urban: "#292929",
+    hospital: "#252424",
+    school: "#262323",
+    industrial: "#222222",
+    aerodrome: "#1e1e1e",
+    runway: "#333333",
+    water: "#31353f",
+    buildings: "#111111",
+    tunnel: "#292929",
+    path: "#262626",
+    minor: "#3c3c3c",
+    minor_casing: "#1f1f1f",
+    major: "#3c3c3c",
+    major_casing: "#1f1f1f",
+    highway: "#474747",
+    highway_casing: "#1f1f1f",
+    rail: "#000000",
+    boundaries: "#5b6374",
+    pois: "#4a8469",
+    places: "#7a7a7a",
+};
+
+const WHITE: Flavor = Flavor {
+    background: "#ffffff",
+    earth: "#ffffff",
+    park: "#f6f6f6",
+    wood: "#f6f6f6",
+    scrub: "#f6f6f6",
+    farmland: "#fafafa",
+    sand: "#fafafa",
+    glacier: "#ffffff",
+    urban: "#fafafa",
+    hospital: "#f6f6f6",
+    school: "#f6f6f6",
+    industrial: "#f6f6f6",
+    aerodrome: "#f6f6f6",
+    runway: "#efefef",
+    water: "#dcdcdc",
+    buildings: "#efefef",
+    tunnel: "#ebebeb",
+    path: "#efefef",
+    minor: "#ebebeb",
+    minor_casing: "#ffffff",
+    major: "#ebebeb",
+    major_casing: "#ffffff",
+    highway: "#ebebeb",
+    highway_casing: "#ffffff",
+    rail: "#d6d6d6",
+    boundaries: "#adadad",
+    pois: "#bbbbbb",
+    places: "#999999",
+};
+
+const GRAYSCALE: Flavor = Flavor {
+    background: "#a3a3a3",
+    earth: "#cccccc",
+    park: "#c2c2c2",
+    wood: "#c2c2c2",
+    scrub: "#c2c2c2",
+    farmland: "#c8c8c8",
+    sand: "#c8c8c8",
+    glacier: "#d9d9d9",
+    urban: "#c9c9c9",
+    hospital: "#c5c5c5",
+    school: "#c5c5c5",
+    industrial: "#c5c5c5",
+    aerodrome: "#c5c5c5",
+    runway: "#e0e0e0",
+    water: "#a2a2a2",
+    buildings: "#b4b4b4",
+    tunnel: "#d5d5d5",
+    path: "#e0e0e0",
+    minor: "#ebebeb",
+    minor_casing: "#c0c0c0",
+    major: "#f5f5f5",
+    major_casing: "#c0c0c0",
+    highway: "#ffffff",
+    highway_casing: "#b0b0b0",
+    rail: "#8a8a8a",
+    boundaries: "#5c5c5c",
+    pois: "#707070",
+    places: "#4d4d4d",
+};
+
+const BLACK: Flavor = Flavor {
+    background: "#2b2b2b",
+    earth: "#141414",
+    park: "#181818",
+    wood: "#181818",
+    scrub: "#181818",
+    farmland: "#161616",
+    sand: "#161616",
+    glacier: "#1f1f1f",
+    urban: "#171717",
+    hospital: "#181818",
+    school: "#181818",
+    industrial: "#181818",
+    aerodrome: "#181818",
+    runway: "#242424",
+    water: "#333333",
+    buildings: "#0a0a0a",
+    tunnel: "#1f1f1f",
+    path: "#222222",
+    minor: "#292929",
+    minor_casing: "#141414",
+    major: "#292929",
+    major_casing: "#141414",
+    highway: "#2e2e2e",
+    highway_casing: "#141414",
+    rail: "#3d3d3d",
+    boundaries: "#707070",
+    pois: "#525252",
+    places: "#8a8a8a",
+};
+
+impl Theme {
+    pub fn style(self) -> Style {
+        let style = json!({
+            "version": 8,
+            "layers": layers(self.flavor()),
+        });
+        Style::parse(&style.to_string()).expect("built-in themes are valid styles")
+    }
+
+    fn flavor(self) -> &'static Flavor {
+        match self {
+            Theme::Light => &LIGHT,
+            Theme::Dark => &DARK,
+            Theme::White => &WHITE,
+            Theme::Grayscale => &GRAYSCALE,
+            Theme::Black => &BLACK,
+        }
+    }
+}
+
+// Versions of the basemap before 4 call it pmap:kind.
+fn kind() -> Value {
+    json!(["coalesce", ["get", "kind"], ["get", "pmap:kind"]])
+}
+
+// A width that grows with the zoom, from nothing at the first stop, the way roads do in the
+// Protomaps styles.
+fn width(stops: &[(f64, f64)]) -> Value {
+    let mut expression = vec![
+        json!("interpolate"),
+        json!(["exponential", 1.6]),
+        json!(["zoom"]),
+    ];
+    for (zoom, width) in stops {
+        expression.push(json!(zoom));
+        expression.push(json!(width));
+    }
+    Value::Array(expression)
+}
+
+// Casings go a pixel either side of the road they're under.
+fn casing(stops: &[(f64, f64)]) -> Value {
+    json!(["+", width(stops), 2])
+}
+
+fn road(kinds: &[&str]) -> Value {
+    json!([
+        "all",
+        ["in", kind(), ["literal", kinds]],
+        ["!=", ["get", "is_tunnel"], true]
+    ])
+}
+
+// Railways are in roads, or in transit in older versions of the basemap.
+fn rail(id: &str, source_layer: &str, flavor: &Flavor) -> Value {
+    json!({
+        "id": id,
+        "type": "line",
+        "source-layer": source_layer,
+        "filter": ["==", kind(), "rail"],
+        "paint": {
+            "line-color": flavor.rail,
+            "line-width": width(&[(3.0, 0.0), (6.0, 0.15), (18.0, 9.0)]),
+            "line-dasharray": [0.3, 0.75]
+        }
+    })
+}
+
+fn layers(flavor: &Flavor) -> Value {
+    let path = [(12.0, 0.0), (15.0, 0.5), (18.0, 4.0)];
+    let minor = [(11.0, 0.0), (12.5, 0.5), (15.0, 2.0), (18.0, 11.0)];
+    let major = [(6.0, 0.0), (12.0, 1.6), (15.0, 3.0), (18.0, 13.0)];
+    let highway = [
+        (3.0, 0.0),
+        (6.0, 1.1),
+        (12.0, 1.6),
+        (15.0, 5.0),
+        (18.0, 15.0),
+    ];
+    let link = [(13.0, 0.0), (13.5, 1.0), (18.0, 11.0)];
+    let minor_kinds = ["minor_road", "medium_road"];
+    let major_kinds = ["major_road"];
+    let highway_kinds = ["highway"];
+
+    json!([
+        {
+            "id": "background",
+            "type": "background",
+            "paint": { "background-color": flavor.background }
+        },
+        {
+            "id": "earth",
+            "type": "fill",
+            "source-layer": "earth",
+            "paint": { "fill-color": flavor.earth }
+        },
+        {
+            "id": "landcover",
+            "type": "fill",
+            "source-layer": "landcover",
+            "paint": {
+                "fill-color": [
+                    "match", kind(),
+                    "forest", flavor.wood,
+                    "grassland", flavor.park,
+                    "scrub", flavor.scrub,
+                    "farmland", flavor.farmland,
+                    "barren", flavor.sand,
+                    "glacier", flavor.glacier,
+                    "urban_area", flavor.urban,
+                    flavor.earth
+                ],
+                "fill-opacity": ["interpolate", ["linear"], ["zoom"], 5, 1, 7, 0]
+            }
+        },
+        {
+            "id": "landuse",
+            "type": "fill",
+            "source-layer": "landuse",
+            "paint": {
+                "fill-color": [
+                    "match", kind(),
+                    [
+                        "park", "nature_reserve", "national_park", "protected_area", "garden",
+                        "golf_course", "grass", "grassland", "meadow", "playground", "pitch",
+                        "recreation_ground", "cemetery", "zoo"
+                    ], flavor.park,
+                    ["wood", "forest"], flavor.wood,
+                    ["scrub", "wetland"], flavor.scrub,
+                    "farmland", flavor.farmland,
+                    ["beach", "sand", "bare_rock", "dam"], flavor.sand,
+                    "glacier", flavor.glacier,
+                    ["hospital"], flavor.hospital,
+                    ["school", "university", "college", "kindergarten"], flavor.school,
+                    ["industrial", "commercial", "railway", "military"], flavor.industrial,
+                    "aerodrome", flavor.aerodrome,
+                    "runway", flavor.runway,
+                    "rgba(0, 0, 0, 0)"
+                ]
+            }
+        },
+        {
+            "id": "natural",
+            "type": "fill",
+            "source-layer": "natural",
+            "paint": {
+                "fill-color": [
+                    "match", kind(),
+                    ["wood", "forest"], flavor.wood,
+                    ["scrub", "wetland"], flavor.scrub,
+                    ["beach", "sand", "bare_rock"], flavor.sand,
+                    "glacier", flavor.glacier,
+                    flavor.park
+                ]
+            }
+        },
+        {
+            "id": "water",
+            "type": "fill",
+            "source-layer": "water",
+            "filter": ["==", ["geometry-type"], "Polygon"],
+            "paint": { "fill-color": flavor.water }
+        },
+        {
+            "id": "water_lines",
+            "type": "line",
+            "source-layer": "water",
+            "filter": ["==", ["geometry-type"], "LineString"],
+            "paint": {
+                "line-color": flavor.water,
+                "line-width": width(&[(9.0, 0.0), (12.0, 0.5), (18.0, 6.0)])
+            }
+        },
+        {
+            "id": "buildings",
+            "type": "fill",
+            "source-layer": "buildings",
+            "filter": ["==", ["geometry-type"], "Polygon"],
+            "paint": { "fill-color": flavor.buildings, "fill-opacity": 0.5 }
+        },
+        {
+            "id": "roads_tunnels",
+            "type": "line",
+            "source-layer": "roads",
+            "filter": ["==", ["get", "is_tunnel"], true],
+            "layout": { "line-cap": "butt" },
+            "paint": {
+                "line-color": flavor.tunnel,
+                "line-width": width(&minor),
+                "line-dasharray": [2, 1]
+            }
+        },
+        {
+            "id": "roads_minor_casing",
+            "type": "line",
+            "source-layer": "roads",
+            "minzoom": 13,
+            "filter": road(&minor_kinds),
+            "layout": { "line-cap": "round", "line-join": "round" },
+            "paint": { "line-color": flavor.minor_casing, "line-width": casing(&minor) }
+        },
+        {
+            "id": "roads_major_casing",
+            "type": "line",
+            "source-layer": "roads",
+            "minzoom": 12,
+            "filter": road(&major_kinds),
+            "layout": { "line-cap": "round", "line-join": "round" },
+            "paint": { "line-color": flavor.major_casing, "line-width": casing(&major) }
+        },
+        {
+            "id": "roads_highway_casing",
+            "type": "line",
+            "source-layer": "roads",
+            "minzoom": 8,
+            "filter": road(&highway_kinds),
+            "layout": { "line-cap": "round", "line-join": "round" },
+            "paint": { "line-color": flavor.highway_casing, "line-width": casing(&highway) }
+        },
+        {
+            "id": "roads_other",
+            "type": "line",
+            "source-layer": "roads",
+            "filter": road(&["path", "other", "aeroway"]),
+            "layout": { "line-cap": "round", "line-join": "round" },
+            "paint": {
+                "line-color": ["match", kind(), "aeroway", flavor.runway, flavor.path],
+                "line-width": width(&path)
+            }
+        },
+        {
+            "id": "roads_minor",
+            "type": "line",
+            "source-layer": "roads",
+            "filter": road(&minor_kinds),
+            "layout": { "line-cap": "round", "line-join": "round" },
+            "paint": { "line-color": flavor.minor, "line-width": width(&minor) }
+        },
+        {
+            "id": "roads_link",
+            "type": "line",
+            "source-layer": "roads",
+            "filter": ["all", road(&["major_road", "highway"]), ["==", ["get", "is_link"], true]],
+            "layout": { "line-cap": "round", "line-join": "round" },
+            "paint": { "line-color": flavor.major, "line-width": width(&link) }
+        },
+        {
+            "id": "roads_major",
+            "type": "line",
+            "source-layer": "roads",
+            "filter": ["all", road(&major_kinds), ["!=", ["get", "is_link"], true]],
+            "layout": { "line-cap": "round", "line-join": "round" },
+            "paint": { "line-color": flavor.major, "line-width": width(&major) }
+        },
+        {
+            "id": "roads_highway",
+            "type": "line",
+            "source-layer": "roads",
+            "filter": ["all", road(&highway_kinds), ["!=", ["get", "is_link"], true]],
+            "layout": { "line-cap": "round", "line-join": "round" },
+            "paint": { "line-color": flavor.highway, "line-width": width(&highway) }
+        },
+        rail("roads_rail", "roads", flavor),
+        rail("transit", "transit", flavor),
+        {
+            "id": "boundaries_country",
+            "type": "line",
+            "source-layer": "boundaries",
+            "filter": ["==", kind(), "country"],
+            "paint": { "line-color": flavor.boundaries, "line-width": 1.5 }
+        },
+        {
+            "id": "boundaries",
+            "type": "line",
+            "source-layer": "boundaries",
+            "filter": ["in", kind(), ["literal", ["region", "county"]]],
+            "paint": {
+                "line-color": flavor.boundaries,
+                "line-width": 1,
+                "line-dasharray": [3, 2]
+            }
+        },
+        {
+            "id": "pois",
+            "type": "circle",
+            "source-layer": "pois",
+            "minzoom": 14,
+            "paint": { "circle-color": flavor.pois, "circle-radius": 2.5 }
+        },
+        {
+            "id": "places",
+            "type": "symbol",
+            "source-layer": "places",
+            "filter": ["in", kind(), ["literal", ["locality", "neighbourhood"]]],
+            "paint": { "text-color": flavor.places }
+        }
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression::Context;
+    use crate::pmtiles::{PmTilesReader, TileCoord};
+    use crate::style::Paint;
+    use crate::tile_cache::DecodedTile;
+
+    #[test]
+    fn test_themes() {
+        for theme in Theme::value_variants() {
+            let style = theme.style();
+            // Including the layers older versions had.
+            for name in SOURCE_LAYERS.into_iter().chain(["natural", "transit"]) {
+                assert!(
+                    style
+                        .layers
+                        .iter()
+                        .any(|layer| layer.source_layer.as_deref() == Some(name)),
+                    "{theme:?} doesn't draw {name}"
+                );
+            }
+        }
+
+        let water = |theme: Theme| {
+            let style = theme.style();
+            let layer = style
+                .layers
+                .iter()
+                .find(|layer| layer.source_layer.as_deref() == Some("water"))
+                .unwrap();
+            let Paint::Fill(paint) = &layer.paint else {
+                panic!("water should be filled");
+            };
+            paint.color.evaluate(&Context {
+                zoom: 10.0,
+                feature: None,
+            })
+        };
+        assert_ne!(water(Theme::Light), water(Theme::Dark));
+        assert_ne!(water(Theme::White), water(Theme::Black));
+    }
+
+    #[test]
+    fn test_roads() {
+        let archive = PmTilesReader::open("toolangi.pmtiles").unwrap();
+        let coord = TileCoord {
+            x: 7406,
+            y: 5018,
+            z: 13,
+        };
+        let tile = DecodedTile::load(&archive, coord).unwrap();
+        let style = Theme::Light.style();
+
+        // Every road is drawn by exactly one layer, and those that have casings get one casing
+        // underneath. Tunnels, railways and the roads_other kinds go without.
+        let cased = ["roads_minor", "roads_link", "roads_major", "roads_highway"];
+        // Nothing in the basemap is hidden, so the parsed layers line up with the definitions.
+        let definitions = layers(Theme::Light.flavor());
+        let ids: Vec<&str> = definitions
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|layer| layer["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids.len(), style.layers.len());
+        let roads = tile
+            .layers
+            .iter()
+            .find(|layer| layer.name == "roads")
+            .unwrap();
+        for feature in &roads.features {
+            let context = Context {
+                zoom: 15.0,
+                feature: Some(feature),
+            };
+            let (casings, drawn_by): (Vec<&str>, Vec<&str>) = ids
+                .iter()
+                .zip(&style.layers)
+                .filter(|(_, layer)| layer.source_layer.as_deref() == Some("roads"))
+                .filter(|(_, layer)| layer.visible_at(15.0))
+                .filter(|(_, layer)| layer.passes_filter(&context))
+                .map(|(id, _)| *id)
+                .partition(|id| id.ends_with("_casing"));
+
+            assert_eq!(drawn_by.len(), 1, "{:?}", feature.properties);
+            let expected_casings = usize::from(cased.contains(&drawn_by[0]));
+            assert_eq!(casings.len(), expected_casings, "{:?}", feature.properties);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+// Subcommands which work on archives without opening the viewer.
+
+pub mod convert;
+pub mod extract;
+pub mod info;
+pub mod tile;
+pub mod verify;
@@ -0,0 +1,250 @@
+// `protography convert <input> <output>`: between MBTiles and PMTiles, going by the file extensions.
+
+use std::io;
+
+use crate::mbtiles::{MbTilesReader, MbTilesWriter, is_mbtiles, metadata_table};
+use crate::pmtiles::{
+    Compression, Metadata, ParseError, PmTilesReader, PmTilesWriter, TileCoord, TileId, TileType,
+};
+use crate::tile_source::{TileSource, detect_compression};
+
+pub fn run(input: &str, output: &str) -> Result<(), ParseError> {
+    let tile_count = match (is_mbtiles(input), is_mbtiles(output)) {
+        (true, false) => mbtiles_to_pmtiles(input, output)?,
+        (false, true) => pmtiles_to_mbtiles(input, output)?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "expected one .mbtiles and one .pmtiles file",
+            )
+            .into());
+        }
+    };
+
+    println!("wrote {tile_count} tiles to {output}");
+
+    Ok(())
+}
+
+fn mbtiles_to_pmtiles(input: &str, output: &str) -> Result<usize, ParseError> {
+    let reader = MbTilesReader::open(input)?;
+    let info = reader.info();
+
+    // The writer needs to know the tile compression up front, which we only find out from the
+    // first tile. MBTiles files can mix gzipped and plain tiles though, so any that don't match
+    // the first one get recompressed.
+    let mut writer: Option<(PmTilesWriter, Compression)> = None;
+    let mut tile_count = 0;
+    reader.for_each_tile(|coord, mut data| {
+        let compression = detect_compression(&data);
+        let (writer, tile_compression) = writer
+            .get_or_insert_with(|| (PmTilesWriter::new(info.tile_type, compression), compression));
+        if compression != *tile_compression {
+            let tile = compression.decompress(&data)?;
+            data = tile_compression.compress(&tile)?.into();
+        }
+        tile_count += 1;
+
+        // Identical tiles (eg. ocean) are only stored once.
+        writer.add_tile(coord, data)
+    })?;
+
+    let (mut writer, _) = writer.unwrap_or_else(|| {
+        let compression = Compression::None;
+        (PmTilesWriter::new(info.tile_type, compression), compression)
+    });
+    writer.set_metadata(Metadata::clone(&reader.metadata()));
+    writer.set_bounds(info.min_position, info.max_position);
+    writer.set_center(info.center_zoom, info.center_position);
+    writer.write_file(output)?;
+
+    Ok(tile_count)
+}
+
+fn pmtiles_to_mbtiles(input: &str, output: &str) -> Result<usize, ParseError> {
+    let reader = PmTilesReader::open(input)?;
+    let header = reader.header();
+
+    let mut writer = MbTilesWriter::create(output)?;
+    writer.set_metadata(&metadata_table(&header, &reader.metadata()))?;
+
+    // Vector tiles in MBTiles are expected to be gzipped.
+    let recompress =
+        header.tile_type == TileType::Mvt && header.tile_compression != Compression::GZip;
+
+    let mut tile_count = 0;
+    for entry in reader.tile_entries()? {
+        let mut data = reader.get_entry_data(&entry)?;
+        if recompress {
+            let tile = header.tile_compression.decompress(&data)?;
+            data = Compression::GZip.compress(&tile)?.into();
+        }
+
+        for id in entry.id..entry.id + entry.run_length {
+            writer.add_tile(TileCoord::try_from(TileId(id))?, data.clone())?;
+            tile_count += 1;
+        }
+    }
+    writer.finish()?;
+
+    Ok(tile_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = std::env::temp_dir();
+        let prefix = format!("protography-convert-{}", std::process::id());
+        let mbtiles = dir.join(format!("{prefix}.mbtiles"));
+        let pmtiles = dir.join(format!("{prefix}.pmtiles"));
+        let (mbtiles, pmtiles) = (mbtiles.to_str().unwrap(), pmtiles.to_str().unwrap());
+
+        run("toolangi.pmtiles", mbtiles).unwrap();
+        run(mbtiles, pmtiles).unwrap();
+
+        let original = PmTilesReader::open("toolangi.pmtiles").unwrap();
+        let from_mbtiles = MbTilesReader::open(mbtiles).unwrap();
+        let round_tripped = PmTilesReader::open(pmtiles).unwrap();
+
+        // Rows are numbered from the south in MBTiles.
+        let coord = TileCoord { x: 1, y: 0, z: 1 };
+        let row: u32 = rusqlite::Connection::open(mbtiles)
+            .unwrap()
+            .query_row(
+                "SELECT tile_row FROM tiles WHERE zoom_level = 1 AND tile_column = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(row, 0);
+        assert!(from_mbtiles.get_tile(coord).unwrap().is_none());
+
+        for entry in original.tile_entries().unwrap().iter().step_by(13) {
+            let coord = TileCoord::try_from(TileId(entry.id)).unwrap();
+            let tile = original.get_tile(coord).unwrap();
+            assert!(tile.is_some());
+            assert_eq!(from_mbtiles.get_tile(coord).unwrap(), tile);
+            assert_eq!(round_tripped.get_tile(coord).unwrap(), tile);
+        }
+
+        let (header, round_header) = (original.header(), round_tripped.header());
+        assert_eq!(
+            round_header.number_of_addressed_tiles,
+            header.number_of_addressed_tiles
+        );
+        assert_eq!(
+            round_header.number_of_tile_contents,
+            header.number_of_tile_contents
+        );
+        assert_eq!(round_header.min_position, header.min_position);
+        assert_eq!(round_header.center_zoom, header.center_zoom);
+
+        let (metadata, round_metadata) = (original.metadata(), round_tripped.metadata());
+        assert_eq!(round_metadata.name, metadata.name);
+        assert_eq!(round_metadata.layer_ids(), metadata.layer_ids());
+        assert_eq!(round_metadata.extra, metadata.extra);
+
+        std::fs::remove_file(mbtiles).unwrap();
+        std::fs::remove_file(pmtiles).unwrap();
+    }
+
+    #[test]
+    fn test_mixed_compression() {
+        let dir = std::env::temp_dir();
+        let prefix = format!("protography-mixed-{}", std::process::id());
+        let mbtiles = dir.join(format!("{prefix}.mbtiles"));
+        let pmtiles = dir.join(format!("{prefix}.pmtiles"));
+        let (mbtiles, pmtiles) = (mbtiles.to_str().unwrap(), pmtiles.to_str().unwrap());
+
+        // Every other tile is gzipped, starting with a plain one.
+        let mut writer = MbTilesWriter::create(mbtiles).unwrap();
+        writer
+            .set_metadata(&[(String::from("format"), String::from("png"))])
+            .unwrap();
+        let tiles: Vec<(TileCoord, Vec<u8>)> = (0..4)
+            .map(|x| {
+                (
+                    TileCoord { x, y: 0, z: 2 },
+                    format!("tile {x}").into_bytes(),
+                )
+            })
+            .collect();
+        for (i, (coord, tile)) in tiles.iter().enumerate() {
+            let data = if i % 2 == 1 {
+                Compression::GZip.compress(tile).unwrap()
+            } else {
+                tile.clone()
+            };
+            writer.add_tile(*coord, data.into()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        run(mbtiles, pmtiles).unwrap();
+
+        let reader = PmTilesReader::open(pmtiles).unwrap();
+        let compression = reader.header().tile_compression;
+        for (coord, tile) in &tiles {
+            let data = reader.get_tile(*coord).unwrap().unwrap();
+            assert_eq!(compression.decompress(&data).unwrap(), *tile);
+        }
+
+        std::fs::remove_file(mbtiles).unwrap();
+        std::fs::remove_file(pmtiles).unwrap();
+    }
+
+    #[test]
+    fn test_rows_outside_zoom_level() {
+        let dir = std::env::temp_dir();
+        let prefix = format!("protography-bad-rows-{}", std::process::id());
+        let mbtiles = dir.join(format!("{prefix}.mbtiles"));
+        let pmtiles = dir.join(format!("{prefix}.pmtiles"));
+        let (mbtiles, pmtiles) = (mbtiles.to_str().unwrap(), pmtiles.to_str().unwrap());
+
+        // Rows past the top of their zoom level, and a zoom level too deep to have tile ids.
+        for (zoom_level, tile_row) in [(2, 4), (1, u32::MAX), (40, 0)] {
+            let mut writer = MbTilesWriter::create(mbtiles).unwrap();
+            writer
+                .set_metadata(&[(String::from("bounds"), String::from("-180,-85,180,85"))])
+                .unwrap();
+            writer
+                .add_tile(TileCoord { x: 0, y: 0, z: 2 }, Bytes::from_static(b"tile"))
+                .unwrap();
+            writer.finish().unwrap();
+            rusqlite::Connection::open(mbtiles)
+                .unwrap()
+                .execute(
+                    "INSERT INTO map (zoom_level, tile_column, tile_row, tile_id) VALUES (?, 0, ?, '0')",
+                    rusqlite::params![zoom_level, tile_row],
+                )
+                .unwrap();
+
+            assert!(run(mbtiles, pmtiles).is_err());
+        }
+
+        // Without bounds in the metadata, they're worked out from the rows, which are just as
+        // bad.
+        let mut writer = MbTilesWriter::create(mbtiles).unwrap();
+        writer
+            .add_tile(TileCoord { x: 0, y: 0, z: 2 }, Bytes::from_static(b"tile"))
+            .unwrap();
+        writer.finish().unwrap();
+        rusqlite::Connection::open(mbtiles)
+            .unwrap()
+            .execute(
+                "INSERT INTO map (zoom_level, tile_column, tile_row, tile_id) VALUES (2, 1, 7, '0')",
+                [],
+            )
+            .unwrap();
+        assert!(matches!(
+            MbTilesReader::open(mbtiles),
+            Err(ParseError::TileOutOfRange(TileCoord { x: 1, y: 7, z: 2 }))
+        ));
+
+        std::fs::remove_file(mbtiles).unwrap();
+    }
+}
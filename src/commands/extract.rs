@@ -0,0 +1,218 @@
+// `protography extract <archive> <output>`: cut the tiles covering a region out into a new archive.
+
+use std::io;
+
+use crate::pmtiles::{Compression, Metadata, ParseError, PmTilesWriter, Position};
+use crate::region::Region;
+use crate::tile_source::{self, TileSource};
+
+pub fn run(
+    archive: &str,
+    output: &str,
+    region: &Region,
+    min_zoom: Option<u8>,
+    max_zoom: Option<u8>,
+) -> Result<(), ParseError> {
+    let source = tile_source::open(archive)?;
+
+    let (writer, tile_count) = extract(&*source, region, min_zoom, max_zoom)?;
+    writer.write_file(output)?;
+
+    println!("wrote {tile_count} tiles to {output}");
+
+    Ok(())
+}
+
+// Returns the new archive, ready to be written, and how many tiles went into it.
+pub fn extract(
+    source: &dyn TileSource,
+    region: &Region,
+    min_zoom: Option<u8>,
+    max_zoom: Option<u8>,
+) -> Result<(PmTilesWriter, usize), ParseError> {
+    let info = source.info();
+
+    let min_zoom = min_zoom.unwrap_or(info.min_zoom).max(info.min_zoom);
+    let max_zoom = max_zoom.unwrap_or(info.max_zoom).min(info.max_zoom);
+    if min_zoom > max_zoom {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the archive only has zoom levels {} to {}",
+                info.min_zoom, info.max_zoom
+            ),
+        )
+        .into());
+    }
+
+    // The new archive covers whichever is smaller of the region and the original archive.
+    let (region_min, region_max) = region.bounds();
+    let min = Position {
+        lat: region_min.lat.max(info.min_position.lat),
+        long: region_min.long.max(info.min_position.long),
+    };
+    let max = Position {
+        lat: region_max.lat.min(info.max_position.lat),
+        long: region_max.long.min(info.max_position.long),
+    };
+    if min.lat > max.lat || min.long > max.long {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the region doesn't overlap the archive's bounds",
+        )
+        .into());
+    }
+
+    // Tiles are copied as they are stored, without decompressing them. Tile directories can mix
+    // gzipped and plain tiles though, so any that don't match the first one get recompressed.
+    let mut writer: Option<(PmTilesWriter, Compression)> = None;
+    let mut tile_count = 0;
+    for zoom in min_zoom..=max_zoom {
+        for coord in region.tiles(zoom) {
+            let Some((mut data, compression)) = source.get_tile_data(coord)? else {
+                continue;
+            };
+
+            let (writer, tile_compression) = writer.get_or_insert_with(|| {
+                (PmTilesWriter::new(info.tile_type, compression), compression)
+            });
+            if compression != *tile_compression {
+                let tile = compression.decompress(&data)?;
+                data = tile_compression.compress(&tile)?.into();
+            }
+
+            writer.add_tile(coord, data)?;
+            tile_count += 1;
+        }
+    }
+    let Some((mut writer, _)) = writer else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the archive has no tiles in the region at zoom levels {min_zoom} to {max_zoom}"
+            ),
+        )
+        .into());
+    };
+    if let Some(compression) = info.internal_compression {
+        writer.set_internal_compression(compression);
+    }
+
+    let center = Position {
+        lat: (min.lat + max.lat) / 2.0,
+        long: (min.long + max.long) / 2.0,
+    };
+
+    writer.set_bounds(min, max);
+    writer.set_center(info.center_zoom.clamp(min_zoom, max_zoom), center);
+    writer.set_metadata(extract_metadata(&source.metadata(), min_zoom, max_zoom));
+
+    Ok((writer, tile_count))
+}
+
+fn extract_metadata(metadata: &Metadata, min_zoom: u8, max_zoom: u8) -> Metadata {
+    let mut metadata = metadata.clone();
+
+    // Drop the layers that only exist outside of the extracted zoom levels.
+    metadata.vector_layers.retain(|layer| {
+        layer.minzoom.is_none_or(|z| z <= max_zoom) && layer.maxzoom.is_none_or(|z| z >= min_zoom)
+    });
+    for layer in &mut metadata.vector_layers {
+        layer.minzoom = layer.minzoom.map(|z| z.max(min_zoom));
+        layer.maxzoom = layer.maxzoom.map(|z| z.min(max_zoom));
+    }
+
+    // These were counted over the whole of the original archive.
+    metadata.tilestats = None;
+
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::{PmTilesReader, TileCoord, TileType};
+    use crate::region::parse_bbox;
+
+    use bytes::Bytes;
+
+    #[test]
+    fn test_extract() {
+        let reader =
+            PmTilesReader::new(Bytes::from(std::fs::read("toolangi.pmtiles").unwrap())).unwrap();
+        let region = Region::from_geojson_file("toolangi.geojson").unwrap();
+
+        let (writer, tile_count) = extract(&reader, &region, None, Some(12)).unwrap();
+        let mut archive = Vec::new();
+        writer.write_to(&mut archive).unwrap();
+
+        let extracted = PmTilesReader::new(Bytes::from(archive)).unwrap();
+        let header = extracted.header();
+        assert_eq!((header.min_zoom, header.max_zoom), (0, 12));
+        assert_eq!(header.number_of_addressed_tiles, tile_count as u64);
+        assert!(header.min_position.long >= 145.38 && header.max_position.long <= 145.56);
+        assert_eq!(
+            extracted.metadata().layer_ids(),
+            reader.metadata().layer_ids()
+        );
+
+        for zoom in 0..=12 {
+            for coord in region.tiles(zoom) {
+                assert_eq!(
+                    extracted.get_tile(coord).unwrap(),
+                    reader.get_tile(coord).unwrap()
+                );
+            }
+        }
+        assert!(
+            extracted
+                .get_tile(TileCoord { x: 0, y: 0, z: 12 })
+                .unwrap()
+                .is_none()
+        );
+
+        // Nothing to extract outside of the archive's zoom levels.
+        let region = parse_bbox("145.4,-37.6,145.5,-37.5").unwrap();
+        assert!(extract(&reader, &region, Some(16), None).is_err());
+
+        // Nor outside of its bounds, or where it has no tiles.
+        let elsewhere = parse_bbox("10,50,11,51").unwrap();
+        assert_invalid_input(extract(&reader, &elsewhere, None, None));
+        let mut writer = PmTilesWriter::new(TileType::Png, Compression::None);
+        let world = parse_bbox("-180,-85,180,85").unwrap().bounds();
+        writer.set_bounds(world.0, world.1);
+        writer
+            .add_tile(TileCoord { x: 0, y: 0, z: 2 }, Bytes::from_static(b"tile"))
+            .unwrap();
+        let mut archive = Vec::new();
+        writer.write_to(&mut archive).unwrap();
+        let north_west_only = PmTilesReader::new(Bytes::from(archive)).unwrap();
+        let south_east = parse_bbox("100,-40,110,-30").unwrap();
+        assert_invalid_input(extract(&north_west_only, &south_east, None, None));
+
+        // The new archive's directories are compressed the same way as the original's.
+        let mut writer = PmTilesWriter::new(TileType::Png, Compression::None);
+        writer.set_internal_compression(Compression::None);
+        writer
+            .add_tile(TileCoord { x: 0, y: 0, z: 0 }, Bytes::from_static(b"tile"))
+            .unwrap();
+        let mut archive = Vec::new();
+        writer.write_to(&mut archive).unwrap();
+        let reader = PmTilesReader::new(Bytes::from(archive)).unwrap();
+
+        let region = parse_bbox("-180,-85,180,85").unwrap();
+        let (writer, _) = extract(&reader, &region, None, None).unwrap();
+        let mut archive = Vec::new();
+        writer.write_to(&mut archive).unwrap();
+        let extracted = PmTilesReader::new(Bytes::from(archive)).unwrap();
+        assert_eq!(extracted.header().internal_compression, Compression::None);
+    }
+
+    fn assert_invalid_input(result: Result<(PmTilesWriter, usize), ParseError>) {
+        match result {
+            Err(ParseError::IoError(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+            Err(e) => panic!("expected invalid input, got {e}"),
+            Ok(_) => panic!("expected invalid input"),
+        }
+    }
+}
@@ -0,0 +1,271 @@
+// `protography info <archive>`: a summary of the header, metadata and vector layers.
+
+use serde_json::json;
+
+use crate::mbtiles::is_mbtiles;
+use crate::pmtiles::{Clustered, Header, Metadata, ParseError, PmTilesReader};
+use crate::tile_source::{self, TileSetInfo, is_directory};
+
+pub fn run(archive: &str, as_json: bool) -> Result<(), ParseError> {
+    if as_json {
+        let summary = summary_json(archive)?;
+        println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+        return Ok(());
+    }
+
+    if has_header(archive) {
+        let reader = PmTilesReader::open(archive)?;
+        print_header(archive, &reader.header());
+        print_metadata(&reader.metadata());
+    } else {
+        let source = tile_source::open(archive)?;
+        print_tile_set(archive, &source.info(), &source.metadata());
+    }
+
+    Ok(())
+}
+
+// Only PMTiles archives have a header, other sources just have the parts of it they can fill in.
+fn has_header(archive: &str) -> bool {
+    !is_mbtiles(archive) && !is_directory(archive)
+}
+
+fn summary_json(archive: &str) -> Result<serde_json::Value, ParseError> {
+    if !has_header(archive) {
+        let source = tile_source::open(archive)?;
+        return Ok(json!({
+            "tile_set": tile_set_json(&source.info()),
+            "metadata": *source.metadata(),
+        }));
+    }
+
+    let reader = PmTilesReader::open(archive)?;
+    Ok(json!({
+        "header": header_json(&reader.header()),
+        "metadata": *reader.metadata(),
+    }))
+}
+
+fn tile_set_json(info: &TileSetInfo) -> serde_json::Value {
+    json!({
+        "tile_type": info.tile_type.to_string(),
+        "min_zoom": info.min_zoom,
+        "max_zoom": info.max_zoom,
+        "bounds": {
+            "min_lat": info.min_position.lat,
+            "min_lon": info.min_position.long,
+            "max_lat": info.max_position.lat,
+            "max_lon": info.max_position.long,
+        },
+        "center": {
+            "lat": info.center_position.lat,
+            "lon": info.center_position.long,
+            "zoom": info.center_zoom,
+        },
+    })
+}
+
+fn print_tile_set(archive: &str, info: &TileSetInfo, metadata: &Metadata) {
+    println!("archive:       {archive}");
+    println!("tile type:     {}", info.tile_type);
+    println!("zoom:          {} to {}", info.min_zoom, info.max_zoom);
+    println!(
+        "bounds:        {:.6}, {:.6} to {:.6}, {:.6} (lat, lon)",
+        info.min_position.lat,
+        info.min_position.long,
+        info.max_position.lat,
+        info.max_position.long
+    );
+    println!(
+        "center:        {:.6}, {:.6} at zoom {}",
+        info.center_position.lat, info.center_position.long, info.center_zoom
+    );
+    print_metadata(metadata);
+}
+
+fn header_json(header: &Header) -> serde_json::Value {
+    json!({
+        "tile_type": header.tile_type.to_string(),
+        "tile_compression": header.tile_compression.to_string(),
+        "internal_compression": header.internal_compression.to_string(),
+        "clustered": header.clustered == Clustered::Clustered,
+        "min_zoom": header.min_zoom,
+        "max_zoom": header.max_zoom,
+        "bounds": {
+            "min_lat": header.min_position.lat,
+            "min_lon": header.min_position.long,
+            "max_lat": header.max_position.lat,
+            "max_lon": header.max_position.long,
+        },
+        "center": {
+            "lat": header.center_position.lat,
+            "lon": header.center_position.long,
+            "zoom": header.center_zoom,
+        },
+        "addressed_tiles": header.number_of_addressed_tiles,
+        "tile_entries": header.number_of_tile_entires,
+        "tile_contents": header.number_of_tile_contents,
+        "root_directory": section_json(header.root_directory_offset, header.root_directory_length),
+        "metadata": section_json(header.metadata_offset, header.metadata_length),
+        "leaf_directories": section_json(
+            header.leaf_directories_offset,
+            header.leaf_directories_length
+        ),
+        "tile_data": section_json(header.tile_data_offset, header.tile_data_length),
+    })
+}
+
+fn section_json(offset: u64, length: u64) -> serde_json::Value {
+    json!({ "offset": offset, "length": length })
+}
+
+fn print_header(archive: &str, header: &Header) {
+    println!("archive:       {archive}");
+    println!("tile type:     {}", header.tile_type);
+    println!(
+        "compression:   tiles {}, directories and metadata {}",
+        header.tile_compression, header.internal_compression
+    );
+    println!(
+        "clustered:     {}",
+        if header.clustered == Clustered::Clustered {
+            "yes"
+        } else {
+            "no"
+        }
+    );
+    println!("zoom:          {} to {}", header.min_zoom, header.max_zoom);
+    println!(
+        "bounds:        {:.6}, {:.6} to {:.6}, {:.6} (lat, lon)",
+        header.min_position.lat,
+        header.min_position.long,
+        header.max_position.lat,
+        header.max_position.long
+    );
+    println!(
+        "center:        {:.6}, {:.6} at zoom {}",
+        header.center_position.lat, header.center_position.long, header.center_zoom
+    );
+    println!(
+        "tiles:         {} addressed, {} entries, {} unique contents",
+        header.number_of_addressed_tiles,
+        header.number_of_tile_entires,
+        header.number_of_tile_contents
+    );
+    println!(
+        "sections:      root directory {} B, metadata {} B, leaf directories {} B, tile data {} B",
+        header.root_directory_length,
+        header.metadata_length,
+        header.leaf_directories_length,
+        header.tile_data_length
+    );
+}
+
+// Layers like Protomaps' `earth` have a field per language, which would drown everything else.
+const MAX_LISTED_FIELDS: usize = 10;
+
+fn print_metadata(metadata: &Metadata) {
+    let fields = [
+        ("name", &metadata.name),
+        ("description", &metadata.description),
+        ("attribution", &metadata.attribution),
+    ];
+
+    println!();
+    println!("metadata:");
+    for (key, value) in fields {
+        if let Some(value) = value {
+            println!("  {key}: {value}");
+        }
+    }
+    for (key, value) in &metadata.extra {
+        match value {
+            serde_json::Value::String(s) => println!("  {key}: {s}"),
+            value => println!("  {key}: {value}"),
+        }
+    }
+    if metadata.tilestats.is_some() {
+        println!("  tilestats: present (see --json)");
+    }
+
+    println!();
+    println!("vector layers:");
+    if metadata.vector_layers.is_empty() {
+        println!("  (none listed)");
+    }
+    for layer in &metadata.vector_layers {
+        let zoom = match (layer.minzoom, layer.maxzoom) {
+            (Some(min), Some(max)) => format!("z{min}-{max}"),
+            (Some(min), None) => format!("z{min}+"),
+            (None, Some(max)) => format!("z0-{max}"),
+            (None, None) => String::from("all zooms"),
+        };
+        let fields: Vec<String> = layer
+            .fields
+            .iter()
+            .map(|(name, kind)| format!("{name} ({kind})"))
+            .collect();
+
+        println!("  {} [{}]", layer.id, zoom);
+        if let Some(description) = &layer.description {
+            println!("    {description}");
+        }
+        if fields.len() > MAX_LISTED_FIELDS {
+            println!(
+                "    fields: {}, and {} more",
+                fields[..MAX_LISTED_FIELDS].join(", "),
+                fields.len() - MAX_LISTED_FIELDS
+            );
+        } else if !fields.is_empty() {
+            println!("    fields: {}", fields.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_json() {
+        let summary = summary_json("toolangi.pmtiles").unwrap();
+        let header = &summary["header"];
+        assert_eq!(header["tile_type"], "mvt");
+        assert_eq!(header["tile_compression"], "gzip");
+        assert_eq!(header["clustered"], true);
+        assert_eq!(
+            (header["min_zoom"].as_u64(), header["max_zoom"].as_u64()),
+            (Some(0), Some(15))
+        );
+        assert!(
+            header["bounds"]["min_lon"].as_f64().unwrap()
+                < header["bounds"]["max_lon"].as_f64().unwrap()
+        );
+        assert_eq!(header["addressed_tiles"], 536);
+        assert_eq!(header["root_directory"]["offset"], 127);
+        assert_eq!(header["leaf_directories"]["length"], 0);
+        assert_eq!(summary["metadata"]["name"], "Protomaps Basemap");
+        assert!(
+            summary["metadata"]["vector_layers"]
+                .as_array()
+                .is_some_and(|l| !l.is_empty())
+        );
+
+        // MBTiles have no header, just what the tile set says about itself.
+        let mbtiles =
+            std::env::temp_dir().join(format!("protography-info-{}.mbtiles", std::process::id()));
+        let mbtiles = mbtiles.to_str().unwrap();
+        crate::commands::convert::run("toolangi.pmtiles", mbtiles).unwrap();
+
+        let summary = summary_json(mbtiles).unwrap();
+        assert!(summary.get("header").is_none());
+        let tile_set = &summary["tile_set"];
+        assert_eq!(tile_set["tile_type"], "mvt");
+        assert_eq!(tile_set["max_zoom"], 15);
+        assert_eq!(tile_set["bounds"], header["bounds"]);
+        assert_eq!(tile_set["center"]["zoom"], header["center"]["zoom"]);
+        assert_eq!(summary["metadata"]["name"], "Protomaps Basemap");
+
+        std::fs::remove_file(mbtiles).unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+// `protography tile <archive> <z/x/y>`: pull a single tile out of an archive for debugging.
+
+use geo_types::{Coord, Geometry, LineString, Polygon};
+use mvt_reader::feature::{Feature, Value};
+use serde_json::json;
+
+use std::fs;
+use std::io::{self, Write};
+
+use crate::pmtiles::{ParseError, TileCoord, TileId, fractional_xyz_to_lat_lon};
+use crate::tile_source;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TileOutput {
+    // A per-layer listing of features, their geometry and properties.
+    Dump,
+    // The decompressed tile bytes.
+    Raw,
+    // The features as a GeoJSON FeatureCollection in lon/lat.
+    GeoJson,
+}
+
+pub fn run(
+    archive: &str,
+    coord: TileCoord,
+    output: TileOutput,
+    path: Option<&str>,
+) -> Result<(), ParseError> {
+    let source = tile_source::open(archive)?;
+
+    let Some(bytes) = source.get_tile(coord)? else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no tile at {coord} in {archive}"),
+        )
+        .into());
+    };
+
+    let contents = match output {
+        TileOutput::Raw => bytes.to_vec(),
+        TileOutput::Dump => {
+            let tile = mvt_reader::Reader::new(bytes.to_vec()).map_err(ParseError::InvalidTile)?;
+            dump(coord, &tile)?.into_bytes()
+        }
+        TileOutput::GeoJson => {
+            let tile = mvt_reader::Reader::new(bytes.to_vec()).map_err(ParseError::InvalidTile)?;
+            let geojson = to_geojson(coord, &tile)?;
+            let mut json = serde_json::to_vec_pretty(&geojson).unwrap();
+            json.push(b'\n');
+            json
+        }
+    };
+
+    match path {
+        Some(path) => fs::write(path, contents)?,
+        None => io::stdout().write_all(&contents)?,
+    }
+
+    Ok(())
+}
+
+fn dump(coord: TileCoord, tile: &mvt_reader::Reader) -> Result<String, ParseError> {
+    let tile_id = TileId::try_from(coord)?;
+    let mut out = format!("tile {coord} (id {})\n", tile_id.0);
+
+    for layer in tile.get_layer_metadata().map_err(ParseError::InvalidTile)? {
+        let features = tile
+            .get_features(layer.layer_index)
+            .map_err(ParseError::InvalidTile)?;
+
+        out += &format!(
+            "\nlayer {} (version {}, extent {}, {} features)\n",
+            layer.name, layer.version, layer.extent, layer.feature_count
+        );
+
+        for feature in features {
+            let id = feature.id.map(|id| format!("#{id} ")).unwrap_or_default();
+            let properties: Vec<String> = sorted_properties(&feature)
+                .into_iter()
+                .map(|(key, value)| format!("{key}={}", property_to_json(value)))
+                .collect();
+
+            out += &format!(
+                "  {id}{} {}\n",
+                describe_geometry(&feature.geometry),
+                properties.join(" ")
+            );
+        }
+    }
+
+    Ok(out)
+}
+
+fn describe_geometry(geometry: &Geometry<f32>) -> String {
+    match geometry {
+        Geometry::Point(_) => String::from("Point"),
+        Geometry::MultiPoint(points) => format!("MultiPoint({} points)", points.0.len()),
+        Geometry::LineString(line) => format!("LineString({} points)", line.0.len()),
+        Geometry::MultiLineString(lines) => {
+            let points: usize = lines.iter().map(|l| l.0.len()).sum();
+            format!("MultiLineString({} lines, {points} points)", lines.0.len())
+        }
+        Geometry::Polygon(polygon) => {
+            format!("Polygon({} holes)", polygon.interiors().len())
+        }
+        Geometry::MultiPolygon(polygons) => format!("MultiPolygon({} polygons)", polygons.0.len()),
+        other => format!("{other:?}"),
+    }
+}
+
+fn sorted_properties(feature: &Feature) -> Vec<(&String, &Value)> {
+    let mut properties: Vec<(&String, &Value)> =
+        feature.properties.iter().flat_map(|p| p.iter()).collect();
+    properties.sort_by_key(|(key, _)| *key);
+    properties
+}
+
+fn property_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::String(s) => json!(s),
+        Value::Float(f) => json!(f),
+        Value::Double(f) => json!(f),
+        Value::Int(i) | Value::SInt(i) => json!(i),
+        Value::UInt(u) => json!(u),
+        Value::Bool(b) => json!(b),
+        Value::Null => serde_json::Value::Null,
+    }
+}
+
+fn to_geojson(
+    coord: TileCoord,
+    tile: &mvt_reader::Reader,
+) -> Result<serde_json::Value, ParseError> {
+    let mut features = Vec::new();
+
+    for layer in tile.get_layer_metadata().map_err(ParseError::InvalidTile)? {
+        // Tile coordinates run from 0 to the layer's extent across the tile.
+        let extent = f64::from(layer.extent);
+        let to_lon_lat = |c: &Coord<f32>| {
+            let position = fractional_xyz_to_lat_lon(
+                f64::from(coord.x) + f64::from(c.x) / extent,
+                f64::from(coord.y) + f64::from(c.y) / extent,
+                coord.z,
+            );
+            json!([position.long, position.lat])
+        };
+
+        for feature in tile
+            .get_features(layer.layer_index)
+            .map_err(ParseError::InvalidTile)?
+        {
+            let Some(geometry) = geometry_to_geojson(&feature.geometry, &to_lon_lat) else {
+                continue;
+            };
+
+            let properties: serde_json::Map<String, serde_json::Value> =
+                sorted_properties(&feature)
+                    .into_iter()
+                    .map(|(key, value)| (key.clone(), property_to_json(value)))
+                    .collect();
+
+            let mut geojson_feature = json!({
+                "type": "Feature",
+                // A foreign member, so that features keep track of which layer they came from.
+                "layer": layer.name,
+                "geometry": geometry,
+                "properties": properties,
+            });
+            if let Some(id) = feature.id {
+                geojson_feature["id"] = json!(id);
+            }
+
+            features.push(geojson_feature);
+        }
+    }
+
+    Ok(json!({
+        "type": "FeatureCollection",
+        "features": features,
+    }))
+}
+
+fn geometry_to_geojson(
+    geometry: &Geometry<f32>,
+    to_lon_lat: &dyn Fn(&Coord<f32>) -> serde_json::Value,
+) -> Option<serde_json::Value> {
+    let line = |line: &LineString<f32>| -> Vec<serde_json::Value> {
+        line.coords().map(to_lon_lat).collect()
+    };
+    let polygon = |polygon: &Polygon<f32>| -> Vec<Vec<serde_json::Value>> {
+        std::iter::once(polygon.exterior())
+            .chain(polygon.interiors())
+            .map(line)
+            .collect()
+    };
+
+    let geojson = match geometry {
+        Geometry::Point(point) => json!({
+            "type": "Point",
+            "coordinates": to_lon_lat(&point.0),
+        }),
+        Geometry::MultiPoint(points) => json!({
+            "type": "MultiPoint",
+            "coordinates": points.iter().map(|p| to_lon_lat(&p.0)).collect::<Vec<_>>(),
+        }),
+        Geometry::LineString(l) => json!({
+            "type": "LineString",
+            "coordinates": line(l),
+        }),
+        Geometry::MultiLineString(lines) => json!({
+            "type": "MultiLineString",
+            "coordinates": lines.iter().map(line).collect::<Vec<_>>(),
+        }),
+        Geometry::Polygon(p) => json!({
+            "type": "Polygon",
+            "coordinates": polygon(p),
+        }),
+        Geometry::MultiPolygon(polygons) => json!({
+            "type": "MultiPolygon",
+            "coordinates": polygons.iter().map(polygon).collect::<Vec<_>>(),
+        }),
+        // MVT only has points, lines and polygons.
+        _ => return None,
+    };
+
+    Some(geojson)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::{PmTilesReader, lat_lon_to_xyz};
+
+    #[test]
+    fn test_tile() {
+        let reader = PmTilesReader::open("toolangi.pmtiles").unwrap();
+        let pos = &reader.header().center_position;
+        let coord = lat_lon_to_xyz(pos.lat, pos.long, 11);
+        let path = std::env::temp_dir().join(format!("protography-tile-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        run("toolangi.pmtiles", coord, TileOutput::Raw, Some(path)).unwrap();
+        let raw = fs::read(path).unwrap();
+        assert_eq!(Some(&raw[..]), reader.get_tile(coord).unwrap().as_deref());
+
+        run("toolangi.pmtiles", coord, TileOutput::Dump, Some(path)).unwrap();
+        let dump = fs::read_to_string(path).unwrap();
+        assert!(dump.starts_with(&format!("tile {coord} (id ")));
+        assert!(dump.contains("\nlayer roads "));
+
+        run("toolangi.pmtiles", coord, TileOutput::GeoJson, Some(path)).unwrap();
+        let geojson: serde_json::Value = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+        assert_eq!(geojson["type"], "FeatureCollection");
+        let features = geojson["features"].as_array().unwrap();
+        assert!(!features.is_empty());
+
+        // Features are in lon/lat, within the tile give or take its buffer.
+        let north_west = fractional_xyz_to_lat_lon(f64::from(coord.x), f64::from(coord.y), coord.z);
+        let south_east =
+            fractional_xyz_to_lat_lon(f64::from(coord.x + 1), f64::from(coord.y + 1), coord.z);
+        let margin = (south_east.long - north_west.long) / 4.0;
+        let layers = reader.metadata().layer_ids();
+        for feature in features {
+            assert_eq!(feature["type"], "Feature");
+            assert!(layers.iter().any(|id| feature["layer"] == *id.as_str()));
+            let geometry = feature["geometry"].to_string();
+            let point = feature["geometry"]["coordinates"]
+                .as_array()
+                .map(|c| first_position(c))
+                .unwrap_or_else(|| panic!("no coordinates in {geometry}"));
+            assert!(point[0] > north_west.long - margin && point[0] < south_east.long + margin);
+            assert!(point[1] < north_west.lat + margin && point[1] > south_east.lat - margin);
+        }
+
+        let missing = TileCoord { x: 0, y: 0, z: 11 };
+        let Err(ParseError::IoError(e)) = run("toolangi.pmtiles", missing, TileOutput::Raw, None)
+        else {
+            panic!("expected a missing tile");
+        };
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    // Digs down through nested coordinate arrays to the first [lon, lat].
+    fn first_position(coordinates: &[serde_json::Value]) -> Vec<f64> {
+        match coordinates[0].as_array() {
+            Some(nested) => first_position(nested),
+            None => coordinates.iter().map(|c| c.as_f64().unwrap()).collect(),
+        }
+    }
+}
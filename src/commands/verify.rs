@@ -0,0 +1,323 @@
+// `protography verify <archive>`: walk every directory and tile, reporting anything malformed.
+
+use std::collections::HashSet;
+use std::io;
+use std::ops::Range;
+
+use crate::pmtiles::{
+    Clustered, HEADER_LENGTH, Header, INITIAL_FETCH_LENGTH, MAX_DIRECTORY_DEPTH, ParseError,
+    TileCoord, TileEntries, TileEntry, TileId, TileType, parse_header, parse_leaf_directory,
+    parse_metadata, parse_root_directory, section_offset,
+};
+use crate::range_reader::{self, RangeReader};
+
+pub fn run(archive: &str) -> Result<(), ParseError> {
+    let source = range_reader::open(archive)?;
+    let report = verify(&source)?;
+
+    for problem in &report.problems {
+        println!("{problem}");
+    }
+    println!(
+        "checked {} directories, {} entries and {} tiles",
+        report.directories, report.entries, report.contents
+    );
+
+    if !report.problems.is_empty() {
+        return Err(io::Error::other(format!(
+            "found {} problems in {archive}",
+            report.problems.len()
+        ))
+        .into());
+    }
+
+    println!("{archive} looks good");
+
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct Report {
+    // Each one starts with where the problem is, eg. "tile 13/7406/5018: ...".
+    pub problems: Vec<String>,
+    pub directories: u64,
+    pub entries: u64,
+    pub addressed_tiles: u64,
+    pub contents: u64,
+}
+
+// Only fails if there isn't a readable header; everything after that ends up in the report.
+pub fn verify(source: &dyn RangeReader) -> Result<Report, ParseError> {
+    let mut header_bytes = source.read_range(0, HEADER_LENGTH as u64)?;
+    if header_bytes.len() < HEADER_LENGTH {
+        return Err(ParseError::UnexpectedEof);
+    }
+    let header = parse_header(&mut header_bytes)?;
+
+    let mut verifier = Verifier {
+        source,
+        header,
+        report: Report::default(),
+        seen_offsets: HashSet::new(),
+        tile_data_end: 0,
+        reported_unclustered: false,
+    };
+    verifier.check_header();
+
+    if let Err(e) = parse_metadata(source, &verifier.header) {
+        verifier.problem("metadata", e);
+    }
+
+    match parse_root_directory(source, &verifier.header) {
+        Ok(root) => verifier.check_directory(&root, "root directory", 0, 0..u64::MAX),
+        Err(e) => verifier.problem("root directory", e),
+    }
+
+    verifier.check_counts();
+
+    Ok(verifier.report)
+}
+
+struct Verifier<'a> {
+    source: &'a dyn RangeReader,
+    header: Header,
+    report: Report,
+    // Tiles can share contents, which only need checking once.
+    seen_offsets: HashSet<u64>,
+    // Where the last new tile contents ended, for checking clustered archives are in order.
+    tile_data_end: u64,
+    reported_unclustered: bool,
+}
+
+impl Verifier<'_> {
+    fn problem(&mut self, location: &str, message: impl std::fmt::Display) {
+        self.report.problems.push(format!("{location}: {message}"));
+    }
+
+    fn check_header(&mut self) {
+        let Header {
+            root_directory_offset,
+            root_directory_length,
+            min_zoom,
+            max_zoom,
+            ..
+        } = self.header;
+
+        let root_directory_end = root_directory_offset.checked_add(root_directory_length);
+        if root_directory_end.is_none_or(|end| end > INITIAL_FETCH_LENGTH) {
+            let message =
+                format!("root directory isn't within the first {INITIAL_FETCH_LENGTH} bytes");
+            self.problem("header", message);
+        }
+        if min_zoom > max_zoom {
+            self.problem(
+                "header",
+                format!("min zoom {min_zoom} is above max zoom {max_zoom}"),
+            );
+        }
+    }
+
+    fn check_directory(&mut self, directory: &TileEntries, name: &str, depth: u8, ids: Range<u64>) {
+        self.report.directories += 1;
+
+        if directory.entries.is_empty() {
+            self.problem(name, "no entries");
+        }
+
+        for (i, entry) in directory.entries.iter().enumerate() {
+            let next_id = directory.entries.get(i + 1).map_or(ids.end, |next| next.id);
+
+            if !ids.contains(&entry.id) {
+                let message = format!("{} is outside of the directory", describe(entry.id));
+                self.problem(name, message);
+            }
+            if next_id <= entry.id {
+                let message = format!("entries out of order after {}", describe(entry.id));
+                self.problem(name, message);
+            } else if entry.id.saturating_add(entry.run_length) > next_id {
+                let message = format!(
+                    "run starting at {} overlaps the next entry",
+                    describe(entry.id)
+                );
+                self.problem(name, message);
+            }
+
+            if entry.run_length > 0 {
+                self.check_tile(entry);
+                continue;
+            }
+
+            let leaf_name = format!("leaf directory from {}", describe(entry.id));
+            if depth >= MAX_DIRECTORY_DEPTH {
+                self.problem(&leaf_name, "nested too deeply");
+                continue;
+            }
+
+            match parse_leaf_directory(self.source, &self.header, entry) {
+                Ok(leaf) => self.check_directory(&leaf, &leaf_name, depth + 1, entry.id..next_id),
+                Err(e) => self.problem(&leaf_name, e),
+            }
+        }
+    }
+
+    fn check_tile(&mut self, entry: &TileEntry) {
+        self.report.entries += 1;
+        self.report.addressed_tiles += entry.run_length;
+
+        let location = format!("tile {}", describe(entry.id));
+
+        match TileCoord::try_from(TileId(entry.id)) {
+            Ok(coord) if coord.z < self.header.min_zoom || coord.z > self.header.max_zoom => {
+                self.problem(&location, "outside of the header's zoom levels");
+            }
+            Ok(_) => {}
+            Err(e) => self.problem(&location, e),
+        }
+
+        if entry.offset.saturating_add(entry.length) > self.header.tile_data_length {
+            self.problem(&location, "outside of tile data section");
+            return;
+        }
+
+        if !self.seen_offsets.insert(entry.offset) {
+            return;
+        }
+        self.report.contents += 1;
+
+        // In a clustered archive each new tile's contents come straight after the last.
+        if self.header.clustered == Clustered::Clustered
+            && entry.offset < self.tile_data_end
+            && !self.reported_unclustered
+        {
+            self.reported_unclustered = true;
+            self.problem(
+                &location,
+                "tile data is out of order in a clustered archive",
+            );
+        }
+        self.tile_data_end = entry.offset + entry.length;
+
+        if let Err(e) = self.check_tile_contents(entry) {
+            self.problem(&location, e);
+        }
+    }
+
+    fn check_tile_contents(&self, entry: &TileEntry) -> Result<(), ParseError> {
+        let header = &self.header;
+
+        let offset = section_offset(header.tile_data_offset, entry.offset)?;
+        let data = self.source.read_range(offset, entry.length)?;
+        if (data.len() as u64) < entry.length {
+            return Err(ParseError::UnexpectedEof);
+        }
+
+        let tile = header.tile_compression.decompress(&data)?;
+
+        if header.tile_type == TileType::Mvt {
+            let reader = mvt_reader::Reader::new(tile).map_err(ParseError::InvalidTile)?;
+            for layer in reader
+                .get_layer_metadata()
+                .map_err(ParseError::InvalidTile)?
+            {
+                reader
+                    .get_features(layer.layer_index)
+                    .map_err(ParseError::InvalidTile)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Zero means the writer didn't count.
+    fn check_counts(&mut self) {
+        let counts = [
+            (
+                "addressed tiles",
+                self.header.number_of_addressed_tiles,
+                self.report.addressed_tiles,
+            ),
+            (
+                "tile entries",
+                self.header.number_of_tile_entires,
+                self.report.entries,
+            ),
+            (
+                "tile contents",
+                self.header.number_of_tile_contents,
+                self.report.contents,
+            ),
+        ];
+
+        for (name, expected, found) in counts {
+            if expected != 0 && expected != found {
+                let message = format!("{name} is {expected}, but the directories have {found}");
+                self.problem("header", message);
+            }
+        }
+    }
+}
+
+fn describe(id: u64) -> String {
+    match TileCoord::try_from(TileId(id)) {
+        Ok(coord) => coord.to_string(),
+        Err(_) => format!("tile id {id}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::{Compression, PmTilesWriter};
+
+    use bytes::Bytes;
+
+    #[test]
+    fn test_verify() {
+        let file = std::fs::read("toolangi.pmtiles").unwrap();
+        let report = verify(&Bytes::from(file.clone())).unwrap();
+        assert_eq!(report.problems, Vec::<String>::new());
+        assert_eq!(report.directories, 1);
+        assert!(report.contents > 0);
+
+        // Leaf directories get walked too.
+        let mut writer = PmTilesWriter::new(TileType::Png, Compression::None);
+        writer.set_internal_compression(Compression::None);
+        let coords = (0..256).flat_map(|x| (0..128).map(move |y| TileCoord { x, y: y * 2, z: 8 }));
+        for (i, coord) in coords.enumerate() {
+            writer
+                .add_tile(coord, Bytes::copy_from_slice(&(i as u64).to_le_bytes()))
+                .unwrap();
+        }
+        let mut archive = Vec::new();
+        writer.write_to(&mut archive).unwrap();
+        let report = verify(&Bytes::from(archive)).unwrap();
+        assert_eq!(report.problems, Vec::<String>::new());
+        assert!(report.directories > 1);
+        assert_eq!(report.entries, 256 * 128);
+
+        // Claim one more addressed tile than there is, and break the first tile's gzip header.
+        let mut corrupt = file;
+        let header = parse_header(&mut Bytes::from(corrupt.clone())).unwrap();
+        let addressed = header.number_of_addressed_tiles + 1;
+        corrupt[72..80].copy_from_slice(&addressed.to_le_bytes());
+        corrupt[header.tile_data_offset as usize] ^= 0xff;
+
+        let report = verify(&Bytes::from(corrupt)).unwrap();
+        assert_eq!(report.problems.len(), 2, "{:?}", report.problems);
+        assert!(report.problems[0].starts_with("tile 0/0/0: "));
+        assert!(report.problems[1].starts_with("header: addressed tiles"));
+
+        // Offsets so large that adding to them overflows.
+        let mut overflowing = std::fs::read("toolangi.pmtiles").unwrap();
+        overflowing[8..16].copy_from_slice(&(u64::MAX - 15).to_le_bytes());
+        let report = verify(&Bytes::from(overflowing.clone())).unwrap();
+        assert!(report.problems[0].starts_with("header: root directory"));
+        assert!(report.problems[1].starts_with("root directory: "));
+
+        overflowing[8..16].copy_from_slice(&(HEADER_LENGTH as u64).to_le_bytes());
+        overflowing[56..64].copy_from_slice(&(u64::MAX - 15).to_le_bytes());
+        let report = verify(&Bytes::from(overflowing)).unwrap();
+        assert!(report.problems[0].ends_with("unexpected end of data"));
+        assert!(report.problems[1].ends_with("malformed directory: offset overflow"));
+    }
+}
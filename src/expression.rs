@@ -0,0 +1,1532 @@
+// Expressions from the MapLibre style spec, for paint values and filters that change with the zoom
+// or with a feature's properties, eg. ["match", ["get", "kind"], "highway", 4, 1].
+// See https://maplibre.org/maplibre-style-spec/expressions/
+//
+// Anything that goes wrong while evaluating one, like a missing property or comparing a string
+// with a number, comes out as null, and whatever asked for the value falls back to its default.
+
+use geo_types::Geometry;
+use mvt_reader::feature::{Feature, Value as PropertyValue};
+use serde_json::Value as Json;
+use vello::peniko::Color;
+use vello::peniko::color::{HueDirection, Lab, Lch, parse_color};
+
+use std::cmp::Ordering;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Color(Color),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    // Objects can't be used for anything yet, so they're null.
+    pub fn from_json(json: &Json) -> Self {
+        match json {
+            Json::Bool(b) => Value::Bool(*b),
+            Json::Number(n) => n.as_f64().map_or(Value::Null, Value::Number),
+            Json::String(s) => Value::String(s.clone()),
+            Json::Array(values) => Value::Array(values.iter().map(Value::from_json).collect()),
+            Json::Null | Json::Object(_) => Value::Null,
+        }
+    }
+
+    fn from_property(value: &PropertyValue) -> Self {
+        match value {
+            PropertyValue::String(s) => Value::String(s.clone()),
+            PropertyValue::Float(n) => Value::Number(f64::from(*n)),
+            PropertyValue::Double(n) => Value::Number(*n),
+            PropertyValue::Int(n) | PropertyValue::SInt(n) => Value::Number(*n as f64),
+            PropertyValue::UInt(n) => Value::Number(*n as f64),
+            PropertyValue::Bool(b) => Value::Bool(*b),
+            PropertyValue::Null => Value::Null,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    // Strings are parsed as CSS colours.
+    pub fn to_color(&self) -> Option<Color> {
+        match self {
+            Value::Color(color) => Some(*color),
+            Value::String(s) => parse_color(s).ok().map(|color| color.to_alpha_color()),
+            _ => None,
+        }
+    }
+
+    fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Null | Value::Bool(false) => Some(0.0),
+            Value::Bool(true) => Some(1.0),
+            Value::Number(n) => Some(*n),
+            Value::String(s) if s.trim().is_empty() => Some(0.0),
+            Value::String(s) => s.trim().parse().ok(),
+            Value::Color(_) | Value::Array(_) => None,
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null | Value::Bool(false) => false,
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::String(s) => !s.is_empty(),
+            _ => true,
+        }
+    }
+
+    fn is_type(&self, kind: Type) -> bool {
+        matches!(
+            (self, kind),
+            (Value::String(_), Type::String)
+                | (Value::Number(_), Type::Number)
+                | (Value::Bool(_), Type::Boolean)
+                | (Value::Color(_), Type::Color)
+        )
+    }
+}
+
+// How to-string and concat write values out.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::String(s) => write!(f, "{s}"),
+            Value::Color(color) => {
+                let [r, g, b, a] = color.components;
+                let channel = |c: f32| (c * 255.0).round();
+                write!(f, "rgba({},{},{},{a})", channel(r), channel(g), channel(b))
+            }
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    match value {
+                        Value::String(s) => write!(f, "{s:?}")?,
+                        Value::Null => write!(f, "null")?,
+                        _ => write!(f, "{value}")?,
+                    }
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+// What an expression is evaluated against. Background layers don't have a feature.
+#[derive(Clone, Copy)]
+pub struct Context<'a> {
+    pub zoom: f64,
+    pub feature: Option<&'a Feature>,
+}
+
+// Point, LineString or Polygon, which multi-geometries count as too.
+pub fn geometry_type(geometry: &Geometry<f32>) -> Option<&'static str> {
+    match geometry {
+        Geometry::Point(_) | Geometry::MultiPoint(_) => Some("Point"),
+        Geometry::LineString(_) | Geometry::MultiLineString(_) => Some("LineString"),
+        Geometry::Polygon(_) | Geometry::MultiPolygon(_) => Some("Polygon"),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Type {
+    String,
+    Number,
+    Boolean,
+    Color,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MathOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+    Min,
+    Max,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Sqrt,
+}
+
+// How an interpolation gets from one stop to the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Curve {
+    Linear,
+    Exponential(f64),
+    // The x and y of the curve's two control points.
+    CubicBezier([f64; 4]),
+}
+
+// Which colour space colours are interpolated in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+    Rgb,
+    Lab,
+    Hcl,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    Literal(Value),
+    Get(String),
+    Has(String),
+    Id,
+    GeometryType,
+    Zoom,
+    Not(Box<Expression>),
+    All(Vec<Expression>),
+    Any(Vec<Expression>),
+    Compare(Comparison, Box<Expression>, Box<Expression>),
+    // Whether the first is in the second, an array or a string.
+    In(Box<Expression>, Box<Expression>),
+    // Conditions and their outputs, and what to use when none of the conditions are true.
+    Case(Vec<(Expression, Expression)>, Box<Expression>),
+    Match {
+        input: Box<Expression>,
+        branches: Vec<(Vec<Value>, Expression)>,
+        fallback: Box<Expression>,
+    },
+    Coalesce(Vec<Expression>),
+    // Stops are in increasing order, and `first` is used below the first of them.
+    Step {
+        input: Box<Expression>,
+        first: Box<Expression>,
+        stops: Vec<(f64, Expression)>,
+    },
+    Interpolate {
+        curve: Curve,
+        space: ColorSpace,
+        input: Box<Expression>,
+        stops: Vec<(f64, Expression)>,
+    },
+    Math(MathOp, Vec<Expression>),
+    // The first argument that's of the type.
+    Assert(Type, Vec<Expression>),
+    // The first argument that can be converted to the type.
+    Convert(Type, Vec<Expression>),
+    Concat(Vec<Expression>),
+    Downcase(Box<Expression>),
+    Upcase(Box<Expression>),
+}
+
+impl Expression {
+    pub fn parse(json: &Json) -> Result<Self, String> {
+        match json {
+            Json::Array(values) => match values.split_first() {
+                Some((Json::String(operator), args)) => Expression::parse_call(operator, args),
+                _ => Err(String::from(
+                    "arrays have to be wrapped in [\"literal\", ...]",
+                )),
+            },
+            Json::Object(_) => Err(String::from("objects aren't supported in expressions")),
+            _ => Ok(Expression::Literal(Value::from_json(json))),
+        }
+    }
+
+    fn parse_call(operator: &str, args: &[Json]) -> Result<Self, String> {
+        let arguments = |count: usize| {
+            if args.len() == count {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{operator:?} takes {count} arguments, not {}",
+                    args.len()
+                ))
+            }
+        };
+        let at_least = |count: usize| {
+            if args.len() >= count {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{operator:?} takes at least {count} arguments, not {}",
+                    args.len()
+                ))
+            }
+        };
+        let parse = |json: &Json| Expression::parse(json).map(Box::new);
+        let parse_all = || args.iter().map(Expression::parse).collect::<Result<_, _>>();
+        let name = |json: &Json| match json {
+            Json::String(name) => Ok(name.clone()),
+            _ => Err(format!("{operator:?} takes the name of a property")),
+        };
+
+        let expression = match operator {
+            "literal" => {
+                arguments(1)?;
+                Expression::Literal(Value::from_json(&args[0]))
+            }
+            "get" => {
+                arguments(1)?;
+                Expression::Get(name(&args[0])?)
+            }
+            "has" => {
+                arguments(1)?;
+                Expression::Has(name(&args[0])?)
+            }
+            "id" => {
+                arguments(0)?;
+                Expression::Id
+            }
+            "geometry-type" => {
+                arguments(0)?;
+                Expression::GeometryType
+            }
+            "zoom" => {
+                arguments(0)?;
+                Expression::Zoom
+            }
+            "!" => {
+                arguments(1)?;
+                Expression::Not(parse(&args[0])?)
+            }
+            "all" => Expression::All(parse_all()?),
+            "any" => Expression::Any(parse_all()?),
+            "==" | "!=" | "<" | "<=" | ">" | ">=" => {
+                arguments(2)?;
+                let comparison = match operator {
+                    "==" => Comparison::Equal,
+                    "!=" => Comparison::NotEqual,
+                    "<" => Comparison::Less,
+                    "<=" => Comparison::LessOrEqual,
+                    ">" => Comparison::Greater,
+                    _ => Comparison::GreaterOrEqual,
+                };
+                Expression::Compare(comparison, parse(&args[0])?, parse(&args[1])?)
+            }
+            "in" => {
+                arguments(2)?;
+                Expression::In(parse(&args[0])?, parse(&args[1])?)
+            }
+            "case" => {
+                if args.len().is_multiple_of(2) {
+                    return Err(String::from(
+                        "\"case\" takes pairs of conditions and outputs, then a fallback",
+                    ));
+                }
+                let (fallback, pairs) = args.split_last().expect("case has an odd length");
+                let branches = pairs
+                    .chunks(2)
+                    .map(|pair| Ok((Expression::parse(&pair[0])?, Expression::parse(&pair[1])?)))
+                    .collect::<Result<_, String>>()?;
+                Expression::Case(branches, parse(fallback)?)
+            }
+            "match" => {
+                if args.len() < 2 || !args.len().is_multiple_of(2) {
+                    return Err(String::from(
+                        "\"match\" takes an input, pairs of labels and outputs, then a fallback",
+                    ));
+                }
+                let (fallback, rest) = args.split_last().expect("match has an input");
+                let branches = rest[1..]
+                    .chunks(2)
+                    .map(|pair| Ok((parse_labels(&pair[0])?, Expression::parse(&pair[1])?)))
+                    .collect::<Result<_, String>>()?;
+                Expression::Match {
+                    input: parse(&rest[0])?,
+                    branches,
+                    fallback: parse(fallback)?,
+                }
+            }
+            "coalesce" => Expression::Coalesce(parse_all()?),
+            "step" => {
+                if args.len() < 2 || !args.len().is_multiple_of(2) {
+                    return Err(String::from(
+                        "\"step\" takes an input, an output, then pairs of stops and outputs",
+                    ));
+                }
+                Expression::Step {
+                    input: parse(&args[0])?,
+                    first: parse(&args[1])?,
+                    stops: parse_stops(&args[2..])?,
+                }
+            }
+            "interpolate" | "interpolate-lab" | "interpolate-hcl" => {
+                if args.len() < 4 || !args.len().is_multiple_of(2) {
+                    return Err(format!(
+                        "{operator:?} takes a curve, an input, then pairs of stops and outputs"
+                    ));
+                }
+                Expression::Interpolate {
+                    curve: parse_curve(&args[0])?,
+                    space: match operator {
+                        "interpolate-lab" => ColorSpace::Lab,
+                        "interpolate-hcl" => ColorSpace::Hcl,
+                        _ => ColorSpace::Rgb,
+                    },
+                    input: parse(&args[1])?,
+                    stops: parse_stops(&args[2..])?,
+                }
+            }
+            "+" | "*" | "min" | "max" => {
+                at_least(1)?;
+                let op = match operator {
+                    "+" => MathOp::Add,
+                    "*" => MathOp::Multiply,
+                    "min" => MathOp::Min,
+                    _ => MathOp::Max,
+                };
+                Expression::Math(op, parse_all()?)
+            }
+            "-" => {
+                if args.is_empty() || args.len() > 2 {
+                    return Err(String::from("\"-\" takes one or two arguments"));
+                }
+                Expression::Math(MathOp::Subtract, parse_all()?)
+            }
+            "/" | "%" | "^" => {
+                arguments(2)?;
+                let op = match operator {
+                    "/" => MathOp::Divide,
+                    "%" => MathOp::Remainder,
+                    _ => MathOp::Power,
+                };
+                Expression::Math(op, parse_all()?)
+            }
+            "abs" | "floor" | "ceil" | "round" | "sqrt" => {
+                arguments(1)?;
+                let op = match operator {
+                    "abs" => MathOp::Abs,
+                    "floor" => MathOp::Floor,
+                    "ceil" => MathOp::Ceil,
+                    "round" => MathOp::Round,
+                    _ => MathOp::Sqrt,
+                };
+                Expression::Math(op, parse_all()?)
+            }
+            "string" | "number" | "boolean" => {
+                at_least(1)?;
+                let kind = match operator {
+                    "string" => Type::String,
+                    "number" => Type::Number,
+                    _ => Type::Boolean,
+                };
+                Expression::Assert(kind, parse_all()?)
+            }
+            "to-string" => {
+                arguments(1)?;
+                Expression::Convert(Type::String, parse_all()?)
+            }
+            "to-number" | "to-boolean" | "to-color" => {
+                at_least(1)?;
+                let kind = match operator {
+                    "to-number" => Type::Number,
+                    "to-boolean" => Type::Boolean,
+                    _ => Type::Color,
+                };
+                Expression::Convert(kind, parse_all()?)
+            }
+            "concat" => Expression::Concat(parse_all()?),
+            "downcase" => {
+                arguments(1)?;
+                Expression::Downcase(parse(&args[0])?)
+            }
+            "upcase" => {
+                arguments(1)?;
+                Expression::Upcase(parse(&args[0])?)
+            }
+            _ => return Err(format!("unsupported expression {operator:?}")),
+        };
+
+        Ok(expression)
+    }
+
+    // Turns strings that could come out of the expression into colours up front, rather than
+    // parsing them again for every feature. For when the expression is known to be a colour.
+    pub fn expect_color(self) -> Self {
+        match self {
+            Expression::Literal(Value::String(s)) => match parse_color(&s) {
+                Ok(color) => Expression::Literal(Value::Color(color.to_alpha_color())),
+                Err(_) => Expression::Literal(Value::String(s)),
+            },
+            Expression::Case(branches, fallback) => Expression::Case(
+                branches
+                    .into_iter()
+                    .map(|(condition, output)| (condition, output.expect_color()))
+                    .collect(),
+                Box::new(fallback.expect_color()),
+            ),
+            Expression::Match {
+                input,
+                branches,
+                fallback,
+            } => Expression::Match {
+                input,
+                branches: branches
+                    .into_iter()
+                    .map(|(labels, output)| (labels, output.expect_color()))
+                    .collect(),
+                fallback: Box::new(fallback.expect_color()),
+            },
+            Expression::Coalesce(values) => {
+                Expression::Coalesce(values.into_iter().map(Expression::expect_color).collect())
+            }
+            Expression::Step {
+                input,
+                first,
+                stops,
+            } => Expression::Step {
+                input,
+                first: Box::new(first.expect_color()),
+                stops: expect_color_stops(stops),
+            },
+            Expression::Interpolate {
+                curve,
+                space,
+                input,
+                stops,
+            } => Expression::Interpolate {
+                curve,
+                space,
+                input,
+                stops: expect_color_stops(stops),
+            },
+            expression => expression,
+        }
+    }
+
+    pub fn evaluate(&self, context: &Context) -> Value {
+        let properties = context
+            .feature
+            .and_then(|feature| feature.properties.as_ref());
+
+        match self {
+            Expression::Literal(value) => value.clone(),
+            Expression::Get(name) => properties
+                .and_then(|properties| properties.get(name))
+                .map_or(Value::Null, Value::from_property),
+            Expression::Has(name) => {
+                Value::Bool(properties.is_some_and(|properties| properties.contains_key(name)))
+            }
+            Expression::Id => context
+                .feature
+                .and_then(|feature| feature.id)
+                .map_or(Value::Null, |id| Value::Number(id as f64)),
+            Expression::GeometryType => context
+                .feature
+                .and_then(|feature| geometry_type(&feature.geometry))
+                .map_or(Value::Null, |kind| Value::String(String::from(kind))),
+            Expression::Zoom => Value::Number(context.zoom),
+            Expression::Not(value) => match value.evaluate(context) {
+                Value::Bool(b) => Value::Bool(!b),
+                _ => Value::Null,
+            },
+            Expression::All(conditions) => {
+                for condition in conditions {
+                    match condition.evaluate(context) {
+                        Value::Bool(true) => {}
+                        Value::Bool(false) => return Value::Bool(false),
+                        _ => return Value::Null,
+                    }
+                }
+                Value::Bool(true)
+            }
+            Expression::Any(conditions) => {
+                for condition in conditions {
+                    match condition.evaluate(context) {
+                        Value::Bool(true) => return Value::Bool(true),
+                        Value::Bool(false) => {}
+                        _ => return Value::Null,
+                    }
+                }
+                Value::Bool(false)
+            }
+            Expression::Compare(comparison, left, right) => compare(
+                *comparison,
+                &left.evaluate(context),
+                &right.evaluate(context),
+            ),
+            Expression::In(needle, haystack) => {
+                match (needle.evaluate(context), haystack.evaluate(context)) {
+                    (
+                        needle @ (Value::String(_) | Value::Number(_) | Value::Bool(_)),
+                        Value::String(haystack),
+                    ) => Value::Bool(haystack.contains(&needle.to_string())),
+                    (needle, Value::Array(haystack)) => Value::Bool(haystack.contains(&needle)),
+                    _ => Value::Null,
+                }
+            }
+            Expression::Case(branches, fallback) => branches
+                .iter()
+                .find(|(condition, _)| condition.evaluate(context) == Value::Bool(true))
+                .map_or(&**fallback, |(_, output)| output)
+                .evaluate(context),
+            Expression::Match {
+                input,
+                branches,
+                fallback,
+            } => {
+                let input = input.evaluate(context);
+                branches
+                    .iter()
+                    .find(|(labels, _)| labels.contains(&input))
+                    .map_or(&**fallback, |(_, output)| output)
+                    .evaluate(context)
+            }
+            Expression::Coalesce(values) => values
+                .iter()
+                .map(|value| value.evaluate(context))
+                .find(|value| *value != Value::Null)
+                .unwrap_or(Value::Null),
+            Expression::Step {
+                input,
+                first,
+                stops,
+            } => {
+                let Some(input) = input.evaluate(context).as_number() else {
+                    return Value::Null;
+                };
+                stops
+                    .iter()
+                    .rev()
+                    .find(|(stop, _)| *stop <= input)
+                    .map_or(&**first, |(_, output)| output)
+                    .evaluate(context)
+            }
+            Expression::Interpolate {
+                curve,
+                space,
+                input,
+                stops,
+            } => {
+                let Some(input) = input.evaluate(context).as_number() else {
+                    return Value::Null;
+                };
+                // Past either end the output is the nearest stop's.
+                let next = stops.partition_point(|(stop, _)| *stop <= input);
+                if next == 0 {
+                    return stops[0].1.evaluate(context);
+                }
+                if next == stops.len() {
+                    return stops[next - 1].1.evaluate(context);
+                }
+
+                let (lower, lower_output) = &stops[next - 1];
+                let (upper, upper_output) = &stops[next];
+                let t = curve.factor(input, *lower, *upper);
+                interpolate(
+                    &lower_output.evaluate(context),
+                    &upper_output.evaluate(context),
+                    t,
+                    *space,
+                )
+            }
+            Expression::Math(op, args) => {
+                let numbers: Option<Vec<f64>> = args
+                    .iter()
+                    .map(|arg| arg.evaluate(context).as_number())
+                    .collect();
+                numbers.map_or(Value::Null, |numbers| Value::Number(op.apply(&numbers)))
+            }
+            Expression::Assert(kind, args) => args
+                .iter()
+                .map(|arg| arg.evaluate(context))
+                .find(|value| value.is_type(*kind))
+                .unwrap_or(Value::Null),
+            Expression::Convert(kind, args) => args
+                .iter()
+                .find_map(|arg| {
+                    let value = arg.evaluate(context);
+                    match kind {
+                        Type::String => Some(Value::String(value.to_string())),
+                        Type::Number => value.to_number().map(Value::Number),
+                        Type::Boolean => Some(Value::Bool(value.is_truthy())),
+                        Type::Color => value.to_color().map(Value::Color),
+                    }
+                })
+                .unwrap_or(Value::Null),
+            Expression::Concat(args) => Value::String(
+                args.iter()
+                    .map(|arg| arg.evaluate(context).to_string())
+                    .collect(),
+            ),
+            Expression::Downcase(value) => match value.evaluate(context) {
+                Value::String(s) => Value::String(s.to_lowercase()),
+                _ => Value::Null,
+            },
+            Expression::Upcase(value) => match value.evaluate(context) {
+                Value::String(s) => Value::String(s.to_uppercase()),
+                _ => Value::Null,
+            },
+        }
+    }
+}
+
+// A match label is a string or number, or an array of them.
+fn parse_labels(json: &Json) -> Result<Vec<Value>, String> {
+    let labels = match json {
+        Json::Array(labels) => labels.iter().map(Value::from_json).collect(),
+        label => vec![Value::from_json(label)],
+    };
+
+    if labels
+        .iter()
+        .any(|label| !matches!(label, Value::String(_) | Value::Number(_)))
+    {
+        return Err(String::from(
+            "\"match\" labels have to be strings or numbers",
+        ));
+    }
+
+    Ok(labels)
+}
+
+fn parse_stops(args: &[Json]) -> Result<Vec<(f64, Expression)>, String> {
+    let mut stops: Vec<(f64, Expression)> = Vec::new();
+
+    for pair in args.chunks(2) {
+        let Some(stop) = pair[0].as_f64() else {
+            return Err(String::from("stops have to be numbers"));
+        };
+        if stops.last().is_some_and(|(last, _)| *last >= stop) {
+            return Err(String::from("stops have to be in increasing order"));
+        }
+        stops.push((stop, Expression::parse(&pair[1])?));
+    }
+
+    Ok(stops)
+}
+
+fn parse_curve(json: &Json) -> Result<Curve, String> {
+    let invalid = || {
+        String::from(
+            "the curve has to be [\"linear\"], [\"exponential\", base] or \
+             [\"cubic-bezier\", x1, y1, x2, y2]",
+        )
+    };
+    let Some((name, values)) = json.as_array().and_then(|values| values.split_first()) else {
+        return Err(invalid());
+    };
+    let numbers: Option<Vec<f64>> = values.iter().map(Json::as_f64).collect();
+
+    match (name.as_str(), numbers.as_deref()) {
+        (Some("linear"), Some([])) => Ok(Curve::Linear),
+        (Some("exponential"), Some([base])) => Ok(Curve::Exponential(*base)),
+        (Some("cubic-bezier"), Some(&[x1, y1, x2, y2])) => Ok(Curve::CubicBezier([x1, y1, x2, y2])),
+        _ => Err(invalid()),
+    }
+}
+
+fn expect_color_stops(stops: Vec<(f64, Expression)>) -> Vec<(f64, Expression)> {
+    stops
+        .into_iter()
+        .map(|(stop, output)| (stop, output.expect_color()))
+        .collect()
+}
+
+// Equality works between any two values, but only numbers and strings can be ordered, and only
+// against their own kind.
+fn compare(comparison: Comparison, left: &Value, right: &Value) -> Value {
+    let ordering = match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+
+    let result = match (comparison, ordering) {
+        (Comparison::Equal, _) => left == right,
+        (Comparison::NotEqual, _) => left != right,
+        (Comparison::Less, Some(ordering)) => ordering == Ordering::Less,
+        (Comparison::LessOrEqual, Some(ordering)) => ordering != Ordering::Greater,
+        (Comparison::Greater, Some(ordering)) => ordering == Ordering::Greater,
+        (Comparison::GreaterOrEqual, Some(ordering)) => ordering != Ordering::Less,
+        (_, None) => return Value::Null,
+    };
+
+    Value::Bool(result)
+}
+
+fn interpolate(lower: &Value, upper: &Value, t: f64, space: ColorSpace) -> Value {
+    let lerp = |a: f64, b: f64| a + (b - a) * t;
+
+    match (lower, upper) {
+        (Value::Number(a), Value::Number(b)) => Value::Number(lerp(*a, *b)),
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| Some(Value::Number(lerp(a.as_number()?, b.as_number()?))))
+            .collect::<Option<_>>()
+            .map_or(Value::Null, Value::Array),
+        _ => match (lower.to_color(), upper.to_color()) {
+            (Some(a), Some(b)) => {
+                let t = t as f32;
+                Value::Color(match space {
+                    ColorSpace::Rgb => a.lerp_rect(b, t),
+                    ColorSpace::Lab => a.convert::<Lab>().lerp_rect(b.convert(), t).convert(),
+                    ColorSpace::Hcl => a
+                        .convert::<Lch>()
+                        .lerp(b.convert(), t, HueDirection::Shorter)
+                        .convert(),
+                })
+            }
+            _ => Value::Null,
+        },
+    }
+}
+
+impl MathOp {
+    // The number of arguments has already been checked.
+    fn apply(self, args: &[f64]) -> f64 {
+        match (self, args) {
+            (MathOp::Add, _) => args.iter().sum(),
+            (MathOp::Multiply, _) => args.iter().product(),
+            (MathOp::Min, _) => args.iter().copied().fold(f64::INFINITY, f64::min),
+            (MathOp::Max, _) => args.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            (MathOp::Subtract, [a]) => -a,
+            (MathOp::Subtract, [a, b]) => a - b,
+            (MathOp::Divide, [a, b]) => a / b,
+            (MathOp::Remainder, [a, b]) => a % b,
+            (MathOp::Power, [a, b]) => a.powf(*b),
+            (MathOp::Abs, [a]) => a.abs(),
+            (MathOp::Floor, [a]) => a.floor(),
+            (MathOp::Ceil, [a]) => a.ceil(),
+            // Halfway values round away from zero, same as the spec.
+            (MathOp::Round, [a]) => a.round(),
+            (MathOp::Sqrt, [a]) => a.sqrt(),
+            _ => f64::NAN,
+        }
+    }
+}
+
+impl Curve {
+    // How far `input` is from `lower` to `upper`, from 0 to 1, going by the curve.
+    fn factor(&self, input: f64, lower: f64, upper: f64) -> f64 {
+        let difference = upper - lower;
+        let progress = input - lower;
+        if difference == 0.0 {
+            return 0.0;
+        }
+
+        match *self {
+            Curve::Exponential(base) if base != 1.0 => {
+                (base.powf(progress) - 1.0) / (base.powf(difference) - 1.0)
+            }
+            Curve::Linear | Curve::Exponential(_) => progress / difference,
+            Curve::CubicBezier(points) => cubic_bezier(points, progress / difference),
+        }
+    }
+}
+
+// The y of the curve from (0, 0) to (1, 1) with the given control points, where it crosses x.
+// Newton's method usually gets there in a few steps, with bisection to fall back on.
+fn cubic_bezier([x1, y1, x2, y2]: [f64; 4], x: f64) -> f64 {
+    let coefficients = |p1: f64, p2: f64| {
+        let c = 3.0 * p1;
+        let b = 3.0 * (p2 - p1) - c;
+        (1.0 - c - b, b, c)
+    };
+    let (ax, bx, cx) = coefficients(x1, x2);
+    let (ay, by, cy) = coefficients(y1, y2);
+    let sample_x = |t: f64| ((ax * t + bx) * t + cx) * t;
+    let sample_y = |t: f64| ((ay * t + by) * t + cy) * t;
+
+    let mut t = x;
+    for _ in 0..8 {
+        let error = sample_x(t) - x;
+        if error.abs() < 1e-7 {
+            return sample_y(t);
+        }
+        let slope = (3.0 * ax * t + 2.0 * bx) * t + cx;
+        if slope.abs() < 1e-6 {
+            break;
+        }
+        t -= error / slope;
+    }
+
+    let (mut low, mut high) = (0.0, 1.0);
+    t = x.clamp(0.0, 1.0);
+    while high - low > 1e-7 {
+        if sample_x(t) < x {
+            low = t;
+        } else {
+            high = t;
+        }
+        t = (low + high) / 2.0;
+    }
+
+    sample_y(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use geo_types::{LineString, Point};
+    use serde_json::json;
+
+    use std::collections::HashMap;
+
+    fn road() -> Feature {
+        let properties = HashMap::from([
+            (
+                String::from("kind"),
+                PropertyValue::String(String::from("highway")),
+            ),
+            (
+                String::from("kind_detail"),
+                PropertyValue::String(String::from("motorway")),
+            ),
+            (String::from("min_zoom"), PropertyValue::Float(5.5)),
+            (String::from("lanes"), PropertyValue::UInt(4)),
+            (String::from("oneway"), PropertyValue::Bool(true)),
+            (
+                String::from("name"),
+                PropertyValue::String(String::from("Maroondah Highway")),
+            ),
+        ]);
+
+        Feature {
+            geometry: Geometry::LineString(LineString::from(vec![(0.0, 0.0), (10.0, 10.0)])),
+            id: Some(42),
+            properties: Some(properties),
+        }
+    }
+
+    // Evaluates the expression against the road, at a zoom.
+    fn eval_at(zoom: f64, json: Json) -> Value {
+        let road = road();
+        let expression = Expression::parse(&json).unwrap();
+        expression.evaluate(&Context {
+            zoom,
+            feature: Some(&road),
+        })
+    }
+
+    fn eval(json: Json) -> Value {
+        eval_at(10.0, json)
+    }
+
+    fn number(json: Json) -> f64 {
+        eval(json).as_number().unwrap()
+    }
+
+    fn parse_error(json: Json) -> String {
+        Expression::parse(&json).unwrap_err()
+    }
+
+    fn rgba(value: Value) -> [f32; 4] {
+        value
+            .to_color()
+            .unwrap()
+            .components
+            .map(|c| (c * 1000.0).round() / 1000.0)
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(eval(json!(3)), Value::Number(3.0));
+        assert_eq!(eval(json!("red")), Value::String(String::from("red")));
+        assert_eq!(eval(json!(true)), Value::Bool(true));
+        assert_eq!(eval(json!(null)), Value::Null);
+        assert_eq!(
+            eval(json!(["literal", [1, 2]])),
+            Value::Array(vec![Value::Number(1.0), Value::Number(2.0)])
+        );
+        assert_eq!(
+            parse_error(json!([1, 2])),
+            "arrays have to be wrapped in [\"literal\", ...]"
+        );
+        assert_eq!(
+            parse_error(json!({ "a": 1 })),
+            "objects aren't supported in expressions"
+        );
+    }
+
+    #[test]
+    fn test_feature_data() {
+        assert_eq!(
+            eval(json!(["get", "kind"])),
+            Value::String(String::from("highway"))
+        );
+        // Every kind of number in a tile comes out as an f64.
+        assert_eq!(eval(json!(["get", "min_zoom"])), Value::Number(5.5));
+        assert_eq!(eval(json!(["get", "lanes"])), Value::Number(4.0));
+        assert_eq!(eval(json!(["get", "oneway"])), Value::Bool(true));
+        assert_eq!(eval(json!(["get", "surface"])), Value::Null);
+
+        assert_eq!(eval(json!(["has", "kind"])), Value::Bool(true));
+        assert_eq!(eval(json!(["has", "surface"])), Value::Bool(false));
+        assert_eq!(eval(json!(["id"])), Value::Number(42.0));
+        assert_eq!(
+            eval(json!(["geometry-type"])),
+            Value::String(String::from("LineString"))
+        );
+        assert_eq!(eval_at(13.5, json!(["zoom"])), Value::Number(13.5));
+
+        // Without a feature, as for a background layer, there's only the zoom.
+        let context = Context {
+            zoom: 3.0,
+            feature: None,
+        };
+        let get = Expression::parse(&json!(["get", "kind"])).unwrap();
+        assert_eq!(get.evaluate(&context), Value::Null);
+        assert_eq!(
+            Expression::parse(&json!(["has", "kind"]))
+                .unwrap()
+                .evaluate(&context),
+            Value::Bool(false)
+        );
+
+        assert_eq!(
+            parse_error(json!(["get", "kind", {}])),
+            "\"get\" takes 1 arguments, not 2"
+        );
+        assert_eq!(
+            parse_error(json!(["get", 3])),
+            "\"get\" takes the name of a property"
+        );
+        assert_eq!(
+            parse_error(json!(["format", "x"])),
+            "unsupported expression \"format\""
+        );
+    }
+
+    #[test]
+    fn test_geometry_type() {
+        assert_eq!(
+            geometry_type(&Geometry::Point(Point::new(0.0, 0.0))),
+            Some("Point")
+        );
+        let multi_line = geo_types::MultiLineString(vec![LineString::from(vec![(0.0, 0.0)])]);
+        assert_eq!(
+            geometry_type(&Geometry::MultiLineString(multi_line)),
+            Some("LineString")
+        );
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(
+            eval(json!(["==", ["get", "kind"], "highway"])),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            eval(json!(["!=", ["get", "kind"], "highway"])),
+            Value::Bool(false)
+        );
+        assert_eq!(eval(json!(["==", ["get", "lanes"], 4])), Value::Bool(true));
+        // Different types are never equal, rather than being an error.
+        assert_eq!(
+            eval(json!(["==", ["get", "lanes"], "4"])),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            eval(json!(["!=", ["get", "surface"], 4])),
+            Value::Bool(true)
+        );
+
+        assert_eq!(eval(json!(["<", ["get", "lanes"], 5])), Value::Bool(true));
+        assert_eq!(eval(json!(["<=", ["get", "lanes"], 4])), Value::Bool(true));
+        assert_eq!(eval(json!([">", ["get", "lanes"], 4])), Value::Bool(false));
+        assert_eq!(eval(json!([">=", ["get", "lanes"], 4])), Value::Bool(true));
+        assert_eq!(eval(json!(["<", "apple", "banana"])), Value::Bool(true));
+        // But they can't be ordered.
+        assert_eq!(eval(json!(["<", ["get", "lanes"], "5"])), Value::Null);
+        assert_eq!(eval(json!([">", ["get", "surface"], 1])), Value::Null);
+
+        assert_eq!(
+            parse_error(json!(["==", 1])),
+            "\"==\" takes 2 arguments, not 1"
+        );
+    }
+
+    #[test]
+    fn test_logic() {
+        assert_eq!(eval(json!(["!", ["has", "surface"]])), Value::Bool(true));
+        assert_eq!(eval(json!(["!", "yes"])), Value::Null);
+        assert_eq!(
+            eval(json!([
+                "all",
+                ["==", ["get", "kind"], "highway"],
+                ["get", "oneway"]
+            ])),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            eval(json!([
+                "all",
+                ["==", ["get", "kind"], "path"],
+                ["get", "surface"]
+            ])),
+            Value::Bool(false)
+        );
+        assert_eq!(eval(json!(["all"])), Value::Bool(true));
+        assert_eq!(eval(json!(["any"])), Value::Bool(false));
+        assert_eq!(
+            eval(json!([
+                "any",
+                ["==", ["get", "kind"], "path"],
+                ["get", "oneway"]
+            ])),
+            Value::Bool(true)
+        );
+        // Anything that isn't a boolean is an error, unless it's never reached.
+        assert_eq!(eval(json!(["any", ["get", "surface"], true])), Value::Null);
+        assert_eq!(
+            eval(json!(["any", true, ["get", "surface"]])),
+            Value::Bool(true)
+        );
+
+        assert_eq!(
+            eval(json!([
+                "in",
+                ["get", "kind"],
+                ["literal", ["highway", "major_road"]]
+            ])),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            eval(json!(["in", ["get", "lanes"], ["literal", [1, 2]]])),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            eval(json!(["in", "Highway", ["get", "name"]])),
+            Value::Bool(true)
+        );
+        assert_eq!(eval(json!(["in", "x", 3])), Value::Null);
+    }
+
+    #[test]
+    fn test_case_and_coalesce() {
+        // From the spec: ["case", condition, output, ..., fallback].
+        let case = json!([
+            "case",
+            ["==", ["get", "kind"], "path"],
+            1,
+            ["==", ["get", "kind"], "highway"],
+            2,
+            3
+        ]);
+        assert_eq!(number(case), 2.0);
+        assert_eq!(number(json!(["case", ["has", "surface"], 1, 0])), 0.0);
+        // Conditions that aren't true or false don't count.
+        assert_eq!(number(json!(["case", ["get", "lanes"], 1, 0])), 0.0);
+        assert_eq!(
+            parse_error(json!(["case", true, 1])),
+            "\"case\" takes pairs of conditions and outputs, then a fallback"
+        );
+
+        assert_eq!(
+            eval(json!([
+                "coalesce",
+                ["get", "name:en"],
+                ["get", "name"],
+                "unnamed"
+            ])),
+            Value::String(String::from("Maroondah Highway"))
+        );
+        assert_eq!(
+            eval(json!(["coalesce", ["get", "surface"], ["get", "width"]])),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_match() {
+        // From the spec: ["match", input, label, output, ..., fallback].
+        let width = json!([
+            "match",
+            ["get", "kind_detail"],
+            ["motorway", "trunk"],
+            4,
+            "primary",
+            3,
+            1
+        ]);
+        assert_eq!(number(width), 4.0);
+        assert_eq!(
+            number(json!([
+                "match",
+                ["get", "kind"],
+                "path",
+                0.5,
+                "minor_road",
+                1,
+                2
+            ])),
+            2.0
+        );
+        assert_eq!(
+            number(json!(["match", ["get", "lanes"], [1, 2], 1, 4, 3, 0])),
+            3.0
+        );
+        // Labels have to be the same type as the input to match.
+        assert_eq!(number(json!(["match", ["get", "lanes"], "4", 1, 0])), 0.0);
+        assert_eq!(
+            number(json!(["match", ["get", "surface"], "paved", 1, 0])),
+            0.0
+        );
+
+        assert_eq!(
+            parse_error(json!(["match", ["get", "kind"], "path", 1])),
+            "\"match\" takes an input, pairs of labels and outputs, then a fallback"
+        );
+        assert_eq!(
+            parse_error(json!(["match", ["get", "kind"], [true], 1, 0])),
+            "\"match\" labels have to be strings or numbers"
+        );
+    }
+
+    #[test]
+    fn test_step() {
+        // From the spec: ["step", input, output below the first stop, stop, output, ...].
+        let step = json!(["step", ["zoom"], 1, 10, 2, 14, 3]);
+        assert_eq!(eval_at(5.0, step.clone()), Value::Number(1.0));
+        assert_eq!(eval_at(10.0, step.clone()), Value::Number(2.0));
+        assert_eq!(eval_at(13.99, step.clone()), Value::Number(2.0));
+        assert_eq!(eval_at(14.0, step.clone()), Value::Number(3.0));
+        assert_eq!(eval_at(20.0, step), Value::Number(3.0));
+
+        assert_eq!(
+            eval(json!(["step", ["get", "lanes"], "narrow", 3, "wide"])),
+            Value::String(String::from("wide"))
+        );
+        assert_eq!(eval(json!(["step", ["get", "kind"], 1, 3, 2])), Value::Null);
+        assert_eq!(number(json!(["step", ["zoom"], 7])), 7.0);
+
+        assert_eq!(
+            parse_error(json!(["step", ["zoom"], 1, 10])),
+            "\"step\" takes an input, an output, then pairs of stops and outputs"
+        );
+        assert_eq!(
+            parse_error(json!(["step", ["zoom"], 1, 10, 2, 5, 3])),
+            "stops have to be in increasing order"
+        );
+        assert_eq!(
+            parse_error(json!(["step", ["zoom"], 1, "10", 2])),
+            "stops have to be numbers"
+        );
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let linear = json!(["interpolate", ["linear"], ["zoom"], 10, 1, 14, 5]);
+        assert_eq!(eval_at(8.0, linear.clone()), Value::Number(1.0));
+        assert_eq!(eval_at(10.0, linear.clone()), Value::Number(1.0));
+        assert_eq!(eval_at(11.0, linear.clone()), Value::Number(2.0));
+        assert_eq!(eval_at(13.5, linear.clone()), Value::Number(4.5));
+        assert_eq!(eval_at(16.0, linear), Value::Number(5.0));
+
+        // Three stops, interpolating between whichever two the input is between.
+        let three = json!(["interpolate", ["linear"], ["zoom"], 0, 0, 10, 100, 20, 0]);
+        assert_eq!(eval_at(15.0, three), Value::Number(50.0));
+
+        // From the spec, a base of 2 doubles the output for each zoom level.
+        let exponential = json!(["interpolate", ["exponential", 2], ["zoom"], 10, 1, 12, 4]);
+        assert_eq!(eval_at(11.0, exponential.clone()), Value::Number(2.0));
+        // A base of 1 is linear.
+        let base_one = json!(["interpolate", ["exponential", 1], ["zoom"], 10, 1, 12, 4]);
+        assert_eq!(eval_at(11.0, base_one), Value::Number(2.5));
+
+        // An ease-in-out curve is slow at the ends and symmetric about the middle.
+        let eased = json!([
+            "interpolate",
+            ["cubic-bezier", 0.42, 0, 0.58, 1],
+            ["zoom"],
+            10,
+            0,
+            20,
+            1
+        ]);
+        let at = |zoom| eval_at(zoom, eased.clone()).as_number().unwrap();
+        assert!((at(15.0) - 0.5).abs() < 1e-6);
+        assert!(at(11.0) < 0.1);
+        assert!((at(12.0) + at(18.0) - 1.0).abs() < 1e-6);
+        // And a linear bezier is linear.
+        let straight = [1.0 / 3.0, 1.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0];
+        assert!((cubic_bezier(straight, 0.3) - 0.3).abs() < 1e-6);
+
+        // Arrays are interpolated a number at a time.
+        let arrays = json!([
+            "interpolate",
+            ["linear"],
+            ["zoom"],
+            10,
+            ["literal", [2, 0]],
+            20,
+            ["literal", [4, 10]]
+        ]);
+        assert_eq!(
+            eval_at(15.0, arrays),
+            Value::Array(vec![Value::Number(3.0), Value::Number(5.0)])
+        );
+
+        // Interpolating on a property.
+        assert_eq!(
+            number(json!([
+                "interpolate",
+                ["linear"],
+                ["get", "lanes"],
+                2,
+                1,
+                6,
+                3
+            ])),
+            2.0
+        );
+        assert_eq!(
+            eval(json!([
+                "interpolate",
+                ["linear"],
+                ["get", "surface"],
+                2,
+                1,
+                6,
+                3
+            ])),
+            Value::Null
+        );
+        // Outputs that can't be interpolated between are an error.
+        assert_eq!(
+            eval_at(
+                15.0,
+                json!(["interpolate", ["linear"], ["zoom"], 10, "a", 20, "b"])
+            ),
+            Value::Null
+        );
+
+        assert_eq!(
+            parse_error(json!(["interpolate", ["linear"], ["zoom"], 10])),
+            "\"interpolate\" takes a curve, an input, then pairs of stops and outputs"
+        );
+        assert!(
+            parse_error(json!(["interpolate", ["smooth"], ["zoom"], 10, 1]))
+                .starts_with("the curve has to be")
+        );
+        assert!(
+            parse_error(json!(["interpolate", ["exponential"], ["zoom"], 10, 1]))
+                .starts_with("the curve has to be")
+        );
+        assert!(
+            parse_error(json!(["interpolate", [], ["zoom"], 0, 1, 10, 2]))
+                .starts_with("the curve has to be")
+        );
+    }
+
+    #[test]
+    fn test_interpolate_colors() {
+        let rgb = json!([
+            "interpolate",
+            ["linear"],
+            ["zoom"],
+            10,
+            "#000000",
+            20,
+            "#ffffff"
+        ]);
+        assert_eq!(rgba(eval_at(15.0, rgb)), [0.5, 0.5, 0.5, 1.0]);
+
+        let red_to_blue = |operator| {
+            json!([
+                operator,
+                ["linear"],
+                ["zoom"],
+                0,
+                "rgb(255, 0, 0)",
+                10,
+                "rgb(0, 0, 255)"
+            ])
+        };
+        assert_eq!(
+            rgba(eval_at(5.0, red_to_blue("interpolate"))),
+            [0.5, 0.0, 0.5, 1.0]
+        );
+        // Going through Lab or HCL doesn't get as dark in the middle, and HCL goes round the hue
+        // wheel through purple rather than straight across.
+        let lab = rgba(eval_at(5.0, red_to_blue("interpolate-lab")));
+        let hcl = rgba(eval_at(5.0, red_to_blue("interpolate-hcl")));
+        assert!(lab[0] > 0.5 && lab[2] > 0.5);
+        assert!(hcl[0] > 0.5 && hcl[2] > 0.5 && hcl[1] < 0.1);
+        assert_ne!(lab, hcl);
+        assert_eq!(
+            rgba(eval_at(10.0, red_to_blue("interpolate-hcl"))),
+            [0.0, 0.0, 1.0, 1.0]
+        );
+
+        // Alpha is interpolated too.
+        let fade = json!([
+            "interpolate",
+            ["linear"],
+            ["zoom"],
+            0,
+            "rgba(255, 255, 255, 0)",
+            10,
+            "rgba(255, 255, 255, 1)"
+        ]);
+        assert_eq!(rgba(eval_at(2.5, fade)), [1.0, 1.0, 1.0, 0.25]);
+    }
+
+    #[test]
+    fn test_expect_color() {
+        let json = json!([
+            "match",
+            ["get", "kind"],
+            "highway",
+            "#ff0000",
+            ["case", ["has", "name"], "blue", "not a colour"]
+        ]);
+        let expression = Expression::parse(&json).unwrap().expect_color();
+
+        let Expression::Match {
+            branches, fallback, ..
+        } = &expression
+        else {
+            panic!("not a match expression");
+        };
+        // Labels are left alone, but outputs are parsed.
+        assert_eq!(branches[0].0, [Value::String(String::from("highway"))]);
+        assert!(matches!(
+            branches[0].1,
+            Expression::Literal(Value::Color(_))
+        ));
+        let Expression::Case(cases, not_a_colour) = &**fallback else {
+            panic!("not a case expression");
+        };
+        assert!(matches!(cases[0].1, Expression::Literal(Value::Color(_))));
+        assert!(matches!(
+            **not_a_colour,
+            Expression::Literal(Value::String(_))
+        ));
+
+        let road = road();
+        let context = Context {
+            zoom: 10.0,
+            feature: Some(&road),
+        };
+        assert_eq!(rgba(expression.evaluate(&context)), [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_math() {
+        assert_eq!(number(json!(["+", 1, 2, 3])), 6.0);
+        assert_eq!(number(json!(["-", 10, 4])), 6.0);
+        assert_eq!(number(json!(["-", 3])), -3.0);
+        assert_eq!(number(json!(["*", ["get", "lanes"], 1.5])), 6.0);
+        assert_eq!(number(json!(["/", 10, 4])), 2.5);
+        assert_eq!(number(json!(["%", 10, 4])), 2.0);
+        assert_eq!(number(json!(["^", 2, ["zoom"]])), 1024.0);
+        assert_eq!(number(json!(["min", 3, 1, 2])), 1.0);
+        assert_eq!(number(json!(["max", 3, 1, 2])), 3.0);
+        assert_eq!(number(json!(["abs", -2])), 2.0);
+        assert_eq!(number(json!(["floor", 2.7])), 2.0);
+        assert_eq!(number(json!(["ceil", 2.1])), 3.0);
+        assert_eq!(number(json!(["round", 2.5])), 3.0);
+        assert_eq!(number(json!(["round", -2.5])), -3.0);
+        assert_eq!(number(json!(["sqrt", 16])), 4.0);
+
+        assert_eq!(eval(json!(["+", 1, ["get", "kind"]])), Value::Null);
+        assert_eq!(
+            parse_error(json!(["-", 1, 2, 3])),
+            "\"-\" takes one or two arguments"
+        );
+        assert_eq!(
+            parse_error(json!(["/", 1])),
+            "\"/\" takes 2 arguments, not 1"
+        );
+        assert_eq!(
+            parse_error(json!(["max"])),
+            "\"max\" takes at least 1 arguments, not 0"
+        );
+    }
+
+    #[test]
+    fn test_types() {
+        assert_eq!(
+            eval(json!(["string", ["get", "lanes"], ["get", "kind"]])),
+            Value::String(String::from("highway"))
+        );
+        assert_eq!(eval(json!(["number", ["get", "kind"]])), Value::Null);
+        assert_eq!(
+            eval(json!(["boolean", ["get", "oneway"]])),
+            Value::Bool(true)
+        );
+
+        assert_eq!(
+            eval(json!(["to-string", ["get", "lanes"]])),
+            Value::String(String::from("4"))
+        );
+        assert_eq!(
+            eval(json!(["to-string", ["get", "min_zoom"]])),
+            Value::String(String::from("5.5"))
+        );
+        assert_eq!(
+            eval(json!(["to-string", ["get", "surface"]])),
+            Value::String(String::new())
+        );
+        assert_eq!(
+            eval(json!(["to-string", ["to-color", "red"]])),
+            Value::String(String::from("rgba(255,0,0,1)"))
+        );
+        assert_eq!(
+            eval(json!(["to-string", ["literal", [1, "a", null]]])),
+            Value::String(String::from("[1,\"a\",null]"))
+        );
+
+        assert_eq!(number(json!(["to-number", " 12.5 "])), 12.5);
+        assert_eq!(number(json!(["to-number", ["get", "surface"]])), 0.0);
+        assert_eq!(number(json!(["to-number", ["get", "oneway"]])), 1.0);
+        assert_eq!(number(json!(["to-number", "wide", "3"])), 3.0);
+        assert_eq!(eval(json!(["to-number", "wide"])), Value::Null);
+
+        assert_eq!(eval(json!(["to-boolean", ""])), Value::Bool(false));
+        assert_eq!(eval(json!(["to-boolean", 0])), Value::Bool(false));
+        assert_eq!(eval(json!(["to-boolean", "false"])), Value::Bool(true));
+        assert_eq!(
+            eval(json!(["to-boolean", ["get", "kind"]])),
+            Value::Bool(true)
+        );
+
+        assert_eq!(
+            rgba(eval(json!(["to-color", "nope", "#00ff0080"]))),
+            [0.0, 1.0, 0.0, 0.502]
+        );
+        assert_eq!(eval(json!(["to-color", "nope"])), Value::Null);
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(
+            eval(json!(["concat", ["get", "kind"], "/", ["get", "lanes"]])),
+            Value::String(String::from("highway/4"))
+        );
+        assert_eq!(
+            eval(json!(["upcase", ["get", "kind"]])),
+            Value::String(String::from("HIGHWAY"))
+        );
+        assert_eq!(
+            eval(json!(["downcase", ["get", "name"]])),
+            Value::String(String::from("maroondah highway"))
+        );
+        assert_eq!(eval(json!(["upcase", 3])), Value::Null);
+    }
+}
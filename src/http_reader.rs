@@ -0,0 +1,377 @@
+// Reads archives over HTTP with `Range:` requests, which is how PMTiles is meant to be served.
+
+use bytes::Bytes;
+
+use std::io;
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::range_reader::{RangeReader, SourceChanged};
+
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+pub struct HttpRangeReader {
+    agent: ureq::Agent,
+    url: String,
+    // The ETag of the archive we started reading. Every later request must match it, otherwise we
+    // would be mixing directories and tiles from two different versions of the archive.
+    etag: Mutex<Option<String>>,
+}
+
+impl HttpRangeReader {
+    pub fn new(url: impl Into<String>) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(Duration::from_secs(30)))
+            .build()
+            .into();
+
+        HttpRangeReader {
+            agent,
+            url: url.into(),
+            etag: Mutex::new(None),
+        }
+    }
+}
+
+impl RangeReader for HttpRangeReader {
+    fn read_range(&self, offset: u64, length: u64) -> io::Result<Bytes> {
+        if length == 0 {
+            return Ok(Bytes::new());
+        }
+
+        let last = offset.checked_add(length - 1).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("range of {length} bytes at {offset} is past the largest offset"),
+            )
+        })?;
+        let mut request = self
+            .agent
+            .get(&self.url)
+            .header("Range", format!("bytes={offset}-{last}"));
+
+        let known_etag = self.etag.lock().unwrap().clone();
+        if let Some(etag) = &known_etag {
+            request = request.header("If-Match", etag);
+        }
+
+        let mut response = request.call().map_err(ureq::Error::into_io)?;
+
+        let etag = response
+            .headers()
+            .get("ETag")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+
+        match response.status().as_u16() {
+            206 | 200 => {}
+            // The range starts past the end of the archive.
+            416 => return Ok(Bytes::new()),
+            412 => return Err(io::Error::other(SourceChanged)),
+            status => {
+                return Err(io::Error::other(format!(
+                    "unexpected HTTP status {status} from {}",
+                    self.url
+                )));
+            }
+        }
+
+        // Servers are allowed to ignore If-Match, so check the ETag ourselves too.
+        match (&known_etag, etag) {
+            (Some(known), Some(etag)) if *known != etag => {
+                return Err(io::Error::other(SourceChanged));
+            }
+            (None, Some(etag)) => *self.etag.lock().unwrap() = Some(etag),
+            _ => {}
+        }
+
+        // A plain 200 means the server ignored the range and is sending the whole archive. That's
+        // fine if the range covered all of it anyway, but downloading the whole archive for every
+        // directory and tile isn't.
+        let full_body = response.status().as_u16() == 200;
+        let limit = if full_body {
+            length.saturating_add(1)
+        } else {
+            // Caches and proxies can answer with a different window than the one we asked for,
+            // and those bytes must not be taken as the ones at `offset`.
+            let content_range = response
+                .headers()
+                .get("Content-Range")
+                .and_then(|v| v.to_str().ok());
+            let (start, end, size) =
+                content_range.and_then(parse_content_range).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("bad Content-Range {content_range:?} from {}", self.url),
+                    )
+                })?;
+            // The range is only allowed to come back short where the archive ends.
+            let cut_short = end < last && size != Some(end + 1);
+            if start != offset || end > last || cut_short {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "asked {} for bytes {offset}-{last}, got {start}-{end}",
+                        self.url
+                    ),
+                ));
+            }
+
+            end - start + 1
+        };
+        let mut body = Vec::new();
+        response
+            .body_mut()
+            .with_config()
+            .limit(u64::MAX)
+            .reader()
+            .take(limit)
+            .read_to_end(&mut body)?;
+
+        if full_body && (offset > 0 || body.len() as u64 > length) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} doesn't support range requests", self.url),
+            ));
+        }
+
+        let bytes = Bytes::from(body);
+        Ok(bytes)
+    }
+
+    fn invalidate(&self) {
+        *self.etag.lock().unwrap() = None;
+    }
+}
+
+// Parses "bytes 0-99/1234" into the first and last byte and the total size, which may be "*".
+fn parse_content_range(value: &str) -> Option<(u64, u64, Option<u64>)> {
+    let (range, size) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end): (u64, u64) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    let size = match size.trim() {
+        "*" => None,
+        size => Some(size.parse().ok()?),
+    };
+
+    (start <= end).then_some((start, end, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::{ParseError, PmTilesReader, TileCoord, lat_lon_to_xyz};
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // A stand-in for a static file server: supports single `Range:` requests and `If-Match`.
+    struct TestServer {
+        url: String,
+        etag: Arc<Mutex<String>>,
+        requests: Arc<AtomicUsize>,
+        behaviour: Arc<Mutex<Behaviour>>,
+    }
+
+    #[derive(Clone, Copy)]
+    enum Behaviour {
+        Normal,
+        // Answers every request with the whole file, like a server without range support.
+        IgnoreRanges,
+        // Answers with the window one byte further on, like a confused cache.
+        ShiftRanges,
+        // The file is replaced before every request.
+        KeepChanging,
+    }
+
+    impl TestServer {
+        fn start(file: Vec<u8>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/archive.pmtiles", listener.local_addr().unwrap());
+            let etag = Arc::new(Mutex::new(String::from("\"v1\"")));
+            let requests = Arc::new(AtomicUsize::new(0));
+            let behaviour = Arc::new(Mutex::new(Behaviour::Normal));
+
+            let server_etag = etag.clone();
+            let server_requests = requests.clone();
+            let server_behaviour = behaviour.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut range = None;
+                    let mut if_match = None;
+
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        let line = line.trim_end();
+                        if line.is_empty() {
+                            break;
+                        }
+
+                        let lower = line.to_ascii_lowercase();
+                        if let Some(value) = lower.strip_prefix("range: bytes=") {
+                            let (start, end) = value.split_once('-').unwrap();
+                            range = Some((
+                                start.parse::<usize>().unwrap(),
+                                end.parse::<usize>().unwrap(),
+                            ));
+                        } else if lower.starts_with("if-match:") {
+                            if_match = Some(line["if-match:".len()..].trim().to_owned());
+                        }
+                    }
+
+                    let request = server_requests.fetch_add(1, Ordering::SeqCst);
+                    let behaviour = *server_behaviour.lock().unwrap();
+                    match behaviour {
+                        Behaviour::IgnoreRanges => range = None,
+                        Behaviour::ShiftRanges => {
+                            range = range.map(|(start, end)| (start + 1, end + 1))
+                        }
+                        Behaviour::KeepChanging => {
+                            *server_etag.lock().unwrap() = format!("\"changed {request}\"");
+                        }
+                        Behaviour::Normal => {}
+                    }
+                    let etag = server_etag.lock().unwrap().clone();
+
+                    let (status, body): (&str, &[u8]) = match (range, if_match) {
+                        (_, Some(if_match)) if if_match != etag => ("412 Precondition Failed", &[]),
+                        (Some((start, _)), _) if start >= file.len() => {
+                            ("416 Range Not Satisfiable", &[])
+                        }
+                        (Some((start, end)), _) => (
+                            "206 Partial Content",
+                            &file[start..(end + 1).min(file.len())],
+                        ),
+                        (None, _) => ("200 OK", &file),
+                    };
+                    let content_range = match range {
+                        Some((start, _)) if status.starts_with("206") => format!(
+                            "Content-Range: bytes {start}-{}/{}\r\n",
+                            start + body.len() - 1,
+                            file.len()
+                        ),
+                        _ => String::new(),
+                    };
+
+                    write!(
+                        stream,
+                        "HTTP/1.1 {status}\r\nETag: {etag}\r\n{content_range}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .unwrap();
+                    stream.write_all(body).unwrap();
+                }
+            });
+
+            TestServer {
+                url,
+                etag,
+                requests,
+                behaviour,
+            }
+        }
+    }
+
+    #[test]
+    fn test_http_reader() {
+        let file = std::fs::read("toolangi.pmtiles").unwrap();
+        let file_size = file.len();
+        let server = TestServer::start(file.clone());
+
+        let reader = PmTilesReader::new(HttpRangeReader::new(&server.url)).unwrap();
+
+        // Header, root directory and metadata all come from the one initial request.
+        assert_eq!(server.requests.load(Ordering::SeqCst), 1);
+
+        let header = reader.header();
+        let pos = &header.center_position;
+        let coord = lat_lon_to_xyz(pos.lat, pos.long, 11);
+
+        let local = PmTilesReader::new(Bytes::from(file)).unwrap();
+        let tile = reader.get_tile(coord).unwrap();
+        assert!(tile.is_some());
+        assert_eq!(tile, local.get_tile(coord).unwrap());
+
+        assert!(
+            reader
+                .get_tile(TileCoord { x: 0, y: 0, z: 11 })
+                .unwrap()
+                .is_none()
+        );
+
+        // Reading past the end gives back nothing rather than failing.
+        let source = HttpRangeReader::new(&server.url);
+        assert!(source.read_range(u64::MAX / 2, 10).unwrap().is_empty());
+        assert_eq!(
+            source.read_range(u64::MAX - 5, 10).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        // The archive is replaced on the server: the reader notices, starts over from the header
+        // and retries the tile.
+        *server.etag.lock().unwrap() = String::from("\"v2\"");
+        let requests = server.requests.load(Ordering::SeqCst);
+        assert_eq!(reader.get_tile(coord).unwrap(), tile);
+        assert_eq!(server.requests.load(Ordering::SeqCst), requests + 3);
+
+        // If the ETag changes again before the retry, give up rather than loop.
+        let flaky = HttpRangeReader::new(&server.url);
+        flaky.read_range(0, 10).unwrap();
+        *server.etag.lock().unwrap() = String::from("\"v3\"");
+        assert!(matches!(
+            flaky.read_range(0, 10).map_err(ParseError::from),
+            Err(ParseError::ArchiveChanged)
+        ));
+
+        // Without range support, reads that cover the whole archive still work, but nothing else
+        // does.
+        *server.behaviour.lock().unwrap() = Behaviour::IgnoreRanges;
+        let whole = HttpRangeReader::new(&server.url);
+        let size = file_size as u64;
+        assert_eq!(whole.read_range(0, size).unwrap().len(), file_size);
+        assert_eq!(
+            whole.read_range(0, 10).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+        assert_eq!(
+            whole.read_range(10, size).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+
+        // Bytes from somewhere other than where we asked are rejected, even when there are as
+        // many of them.
+        *server.behaviour.lock().unwrap() = Behaviour::ShiftRanges;
+        let shifted = HttpRangeReader::new(&server.url);
+        assert_eq!(
+            shifted.read_range(0, 10).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_archive_keeps_changing() {
+        let server = TestServer::start(std::fs::read("toolangi.pmtiles").unwrap());
+        let reader = PmTilesReader::new(HttpRangeReader::new(&server.url)).unwrap();
+        let pos = &reader.header().center_position;
+        let coord = lat_lon_to_xyz(pos.lat, pos.long, 11);
+
+        // The tile request finds the archive replaced, and so does the retry after starting over
+        // from the header.
+        *server.behaviour.lock().unwrap() = Behaviour::KeepChanging;
+        let requests = server.requests.load(Ordering::SeqCst);
+        assert!(matches!(
+            reader.get_tile(coord),
+            Err(ParseError::ArchiveChanged)
+        ));
+        assert_eq!(server.requests.load(Ordering::SeqCst), requests + 3);
+    }
+}
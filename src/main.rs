@@ -1,48 +1,324 @@
+mod basemap;
+mod commands;
+mod expression;
+mod http_reader;
 mod map_renderer;
+mod mbtiles;
+mod navigation;
 mod pmtiles;
+mod range_reader;
+mod region;
 mod simple_vello;
+mod style;
+mod tile_cache;
+mod tile_loader;
+mod tile_source;
 
 use pmtiles::*;
 use vello::util::RenderContext;
 use winit::event_loop::EventLoop;
 
-use std::env;
-use std::fs;
-use std::time::Instant;
+use clap::{ArgGroup, Parser, Subcommand};
 
-use bytes::Bytes;
+use std::io;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 
-use crate::map_renderer::Camera;
+use crate::basemap::Theme;
+use crate::commands::tile::TileOutput;
 use crate::map_renderer::MapRenderer;
+use crate::navigation::{Navigation, StartView};
+use crate::region::Region;
+use crate::style::Style;
+use crate::tile_loader::{LoadedTile, TileLoader};
+use crate::tile_source::TileSource;
+
+#[derive(Parser)]
+#[command(about = "Vector map explorations with Vello and Protomaps")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Open an archive in the map viewer
+    View {
+        /// PMTiles archive (path or http(s) URL), MBTiles file, or {z}/{x}/{y} tile directory
+        archive: String,
+        /// Latitude to centre the map on (defaults to the middle of the archive)
+        #[arg(long, requires = "lon", value_parser = parse_latitude, allow_hyphen_values = true)]
+        lat: Option<f64>,
+        /// Longitude to centre the map on
+        #[arg(long, requires = "lat", value_parser = parse_longitude, allow_hyphen_values = true)]
+        lon: Option<f64>,
+        /// Zoom level to start at, which can be fractional (defaults to fitting the whole archive
+        /// in the window)
+        #[arg(long, value_parser = parse_zoom)]
+        zoom: Option<f64>,
+        /// Which way is up, in degrees clockwise from north
+        #[arg(
+            long,
+            default_value_t = 0.0,
+            value_parser = parse_bearing,
+            allow_hyphen_values = true
+        )]
+        bearing: f64,
+        /// Fit this area in the window, as min_lon,min_lat,max_lon,max_lat
+        #[arg(
+            long,
+            value_parser = region::parse_bbox,
+            allow_hyphen_values = true,
+            conflicts_with_all = ["lat", "lon", "zoom"]
+        )]
+        bbox: Option<Region>,
+        /// Window width, in logical pixels
+        #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u32).range(1..))]
+        width: u32,
+        /// Window height, in logical pixels
+        #[arg(long, default_value_t = 1024, value_parser = clap::value_parser!(u32).range(1..))]
+        height: u32,
+        /// MapLibre style JSON to draw the map with (defaults to the light theme for Protomaps
+        /// basemaps, and a plain style showing every layer for anything else)
+        #[arg(long)]
+        style: Option<String>,
+        /// Draw a Protomaps basemap with one of the built-in themes
+        #[arg(long, value_enum, conflicts_with = "style")]
+        theme: Option<Theme>,
+        /// How much memory decoded tiles can take up, in megabytes. Tiles on screen are kept
+        /// regardless
+        #[arg(long, default_value_t = 256)]
+        cache_mb: usize,
+    },
+    /// Print a summary of an archive's header, metadata and layers
+    Info {
+        /// PMTiles archive (path or http(s) URL), MBTiles file, or {z}/{x}/{y} tile directory
+        archive: String,
+        /// Print the summary as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Extract a single tile, either decoded or as raw bytes
+    Tile {
+        /// PMTiles archive (path or http(s) URL), MBTiles file, or {z}/{x}/{y} tile directory
+        archive: String,
+        /// Tile coordinates, as z/x/y
+        tile: TileCoord,
+        /// Output the decompressed tile bytes instead of decoding them
+        #[arg(long, conflicts_with = "geojson")]
+        raw: bool,
+        /// Output the tile's features as GeoJSON, in lon/lat
+        #[arg(long)]
+        geojson: bool,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Copy the tiles covering a bounding box or polygon into a new archive
+    #[command(group(ArgGroup::new("area").required(true).args(["bbox", "region"])))]
+    Extract {
+        /// PMTiles archive (path or http(s) URL), MBTiles file, or {z}/{x}/{y} tile directory
+        archive: String,
+        /// Where to write the new archive
+        output: String,
+        /// The area to extract, as min_lon,min_lat,max_lon,max_lat
+        #[arg(long, value_parser = region::parse_bbox, allow_hyphen_values = true)]
+        bbox: Option<Region>,
+        /// A GeoJSON file with the polygons to extract, eg. toolangi.geojson
+        #[arg(long)]
+        region: Option<String>,
+        /// Lowest zoom level to include (defaults to the archive's)
+        #[arg(long)]
+        minzoom: Option<u8>,
+        /// Highest zoom level to include (defaults to the archive's)
+        #[arg(long)]
+        maxzoom: Option<u8>,
+    },
+    /// Convert between MBTiles and PMTiles, going by the file extensions
+    Convert {
+        /// The .mbtiles or .pmtiles file to read
+        input: String,
+        /// The .pmtiles or .mbtiles file to write
+        output: String,
+    },
+    /// Check that an archive is well formed, down to every tile
+    Verify {
+        /// Path or http(s) URL of a PMTiles archive
+        archive: String,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
 
-fn test_pmtiles() -> mvt_reader::Reader {
-    let args: Vec<String> = env::args().collect();
-    let path = &args[1];
+    let result = match cli.command {
+        Command::View {
+            archive,
+            lat,
+            lon,
+            zoom,
+            bearing,
+            bbox,
+            width,
+            height,
+            style,
+            theme,
+            cache_mb,
+        } => {
+            let center = lat.zip(lon).map(|(lat, long)| Position { lat, long });
+            let options = ViewOptions {
+                center,
+                zoom,
+                bearing,
+                bbox,
+                window_size: (width, height),
+                style,
+                theme,
+                // Budgets too big to count in bytes might as well be unlimited.
+                cache_budget: cache_mb.saturating_mul(1024 * 1024),
+            };
+            view(&archive, options)
+        }
+        Command::Info { archive, json } => commands::info::run(&archive, json),
+        Command::Tile {
+            archive,
+            tile,
+            raw,
+            geojson,
+            output,
+        } => {
+            let format = match (raw, geojson) {
+                (true, _) => TileOutput::Raw,
+                (_, true) => TileOutput::GeoJson,
+                _ => TileOutput::Dump,
+            };
+            commands::tile::run(&archive, tile, format, output.as_deref())
+        }
+        Command::Extract {
+            archive,
+            output,
+            bbox,
+            region,
+            minzoom,
+            maxzoom,
+        } => {
+            let region = match region {
+                Some(path) => Region::from_geojson_file(path),
+                None => Ok(bbox.expect("clap requires --bbox or --region")),
+            };
+            region.and_then(|region| {
+                commands::extract::run(&archive, &output, &region, minzoom, maxzoom)
+            })
+        }
+        Command::Convert { input, output } => commands::convert::run(&input, &output),
+        Command::Verify { archive } => commands::verify::run(&archive),
+    };
 
-    let file = fs::read(path).unwrap();
-    let mut bytes = Bytes::from(file.clone());
+    if let Err(e) = result {
+        eprintln!("error: {e}");
+        process::exit(1);
+    }
+}
 
-    let header = parse_header(&mut bytes);
-    let header = header.unwrap();
+fn parse_latitude(s: &str) -> Result<f64, String> {
+    let lat: f64 = s.parse().map_err(|_| format!("invalid latitude {s:?}"))?;
+    // Web Mercator stops short of the poles.
+    if !(-85.0511..=85.0511).contains(&lat) {
+        return Err(String::from(
+            "latitude must be between -85.0511 and 85.0511",
+        ));
+    }
 
-    println!("{:#?}", header);
+    Ok(lat)
+}
 
-    let root_directory_entries = parse_root_directory(&file, &header).unwrap();
+fn parse_longitude(s: &str) -> Result<f64, String> {
+    let lon: f64 = s.parse().map_err(|_| format!("invalid longitude {s:?}"))?;
+    if !(-180.0..=180.0).contains(&lon) {
+        return Err(String::from("longitude must be between -180 and 180"));
+    }
 
-    let pos = &header.center_position;
-    let zoom = 11;
-    let coord = pmtiles::lat_lon_to_xyz(pos.lat, pos.long, zoom);
-    let tile_id = TileId::try_from(coord).unwrap();
+    Ok(lon)
+}
 
-    let tile = root_directory_entries.find_tile(tile_id).unwrap();
+fn parse_zoom(s: &str) -> Result<f64, String> {
+    let zoom: f64 = s.parse().map_err(|_| format!("invalid zoom {s:?}"))?;
+    if !(navigation::MIN_ZOOM..=navigation::MAX_ZOOM).contains(&zoom) {
+        return Err(format!(
+            "zoom must be between {} and {}",
+            navigation::MIN_ZOOM,
+            navigation::MAX_ZOOM
+        ));
+    }
 
-    tile_to_mvt_reader(&header, tile, &file)
+    Ok(zoom)
 }
 
-fn main() {
-    println!("loading pmtiles data");
-    let tile = test_pmtiles();
-    println!("loaded pmtiles data");
+// Any angle will do, but it's kept between 0 and 360.
+fn parse_bearing(s: &str) -> Result<f64, String> {
+    let bearing: f64 = s.parse().map_err(|_| format!("invalid bearing {s:?}"))?;
+    if !bearing.is_finite() {
+        return Err(format!("invalid bearing {s:?}"));
+    }
+
+    Ok(bearing.rem_euclid(360.0))
+}
+
+// Whether the archive looks like a Protomaps basemap, which the built-in themes can draw.
+fn is_basemap(layer_ids: &[String]) -> bool {
+    basemap::SOURCE_LAYERS
+        .iter()
+        .all(|name| layer_ids.iter().any(|id| id == name))
+}
+
+struct ViewOptions {
+    center: Option<Position>,
+    zoom: Option<f64>,
+    bearing: f64,
+    bbox: Option<Region>,
+    window_size: (u32, u32),
+    style: Option<String>,
+    theme: Option<Theme>,
+    cache_budget: usize,
+}
+
+fn view(archive: &str, options: ViewOptions) -> Result<(), ParseError> {
+    let source: Arc<dyn TileSource> = tile_source::open(archive)?.into();
+    let info = source.info();
+    let layer_ids = source.metadata().layer_ids();
+    let style = match (&options.style, options.theme) {
+        (Some(path), _) => Style::from_file(path)?,
+        (None, Some(theme)) => theme.style(),
+        (None, None) if is_basemap(&layer_ids) => Theme::Light.style(),
+        (None, None) => Style::fallback(&layer_ids),
+    };
+
+    let start = StartView {
+        fit: match &options.bbox {
+            Some(bbox) => bbox.bounds(),
+            None => (info.min_position, info.max_position),
+        },
+        center: options.center,
+        zoom: options.zoom,
+        bearing: options.bearing,
+    };
+    // A first guess, until the window is open and it's known how many pixels it really has.
+    let (width, height) = options.window_size;
+    let camera = start.camera(width, height);
+
+    // Create a winit event loop, which the tile loader hands finished tiles to
+    let event_loop = EventLoop::<LoadedTile>::with_user_event()
+        .build()
+        .map_err(|e| io::Error::other(format!("couldn't open a window: {e}")))?;
+    let proxy = event_loop.create_proxy();
+    let threads = thread::available_parallelism().map_or(4, |n| n.get().min(8));
+    let loader = TileLoader::new(source.clone(), threads, move |tile| {
+        // Only fails once the event loop has gone, when nobody wants the tile anyway.
+        let _ = proxy.send_event(tile);
+    });
+    let map_renderer = MapRenderer::new(source, loader, options.cache_budget, style);
 
     println!("setting up vello app");
     // Setup a bunch of state:
@@ -51,22 +327,20 @@ fn main() {
         renderers: vec![],
         state: simple_vello::RenderState::Suspended(None),
         scene: vello::Scene::new(),
-        map_renderer: MapRenderer::new(tile),
-        camera: Camera {
-            x: 0.0,
-            y: 0.0,
-            width: 1,
-            height: 1,
-        },
+        map_renderer,
+        camera,
+        navigation: Navigation::default(),
+        start: Some(start),
+        window_size: options.window_size,
         last_frame_time: Instant::now(),
     };
     println!("set up vello app");
 
     println!("starting event loop");
 
-    // Create and run a winit event loop
-    let event_loop = EventLoop::new().unwrap();
     event_loop
         .run_app(&mut app)
         .expect("Couldn't run event loop");
+
+    Ok(())
 }
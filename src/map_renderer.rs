@@ -1,145 +1,684 @@
 use geo_types::{Geometry, LineString, Polygon};
-use mvt_reader::Reader as MvtTile;
-use mvt_reader::feature::Feature;
 use vello::Scene;
-use vello::kurbo::{Affine, BezPath, Stroke};
-use vello::peniko::Color;
+use vello::kurbo::{Affine, BezPath, Circle, Point, Rect, Stroke};
+use vello::peniko::Fill;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::expression::Context;
+use crate::pmtiles::{Position, TileCoord, TileId, fractional_lat_lon_to_xyz};
+use crate::style::{Paint, Style};
+use crate::tile_cache::{DecodedTile, TileCache};
+use crate::tile_loader::{LoadedTile, TileLoader};
+use crate::tile_source::TileSource;
 
 pub const TILE_SIZE: f32 = 512.0;
 
+// How many levels below the source's lowest zoom its tiles are still drawn. Any further out and
+// there are too many of them, too small to see.
+const MAX_UNDERZOOM: f64 = 2.0;
+
+// Symbols are drawn as dots until there's text rendering.
+const SYMBOL_DOT_RADIUS: f64 = 3.0;
+
 pub struct RenderTargetInfo {
     pub width: u32,
     pub height: u32,
 }
 
+// What part of the map is in the window. `x` and `y` are the middle of the window, as a fraction
+// of the way across and down the world, so they stay put as the zoom changes.
 pub struct Camera {
     pub x: f64,
     pub y: f64,
+    // Fractional, eg. 11.5 draws z11 tiles at one and a half times their size.
+    pub zoom: f64,
+    // The direction at the top of the window, in degrees clockwise from north.
+    pub bearing: f64,
     pub width: u32,
     pub height: u32,
 }
 
+impl Camera {
+    pub fn looking_at(position: &Position, zoom: f64) -> Self {
+        let (x, y) = fractional_lat_lon_to_xyz(position.lat, position.long, 0);
+
+        Camera {
+            x,
+            y,
+            zoom,
+            bearing: 0.0,
+            width: 1,
+            height: 1,
+        }
+    }
+
+    // Centres the area between `min` and `max` in the window, zoomed in as far as it fits. Only
+    // the width and height of the window are taken into account, not the bearing.
+    pub fn fit(&mut self, min: &Position, max: &Position, min_zoom: f64, max_zoom: f64) {
+        let (left, top) = fractional_lat_lon_to_xyz(max.lat, min.long, 0);
+        let (right, bottom) = fractional_lat_lon_to_xyz(min.lat, max.long, 0);
+
+        self.x = (left + right) / 2.0;
+        self.y = (top + bottom) / 2.0;
+
+        let tile_size = f64::from(TILE_SIZE);
+        let zoom_x = (f64::from(self.width) / ((right - left) * tile_size)).log2();
+        let zoom_y = (f64::from(self.height) / ((bottom - top) * tile_size)).log2();
+        // A single point zooms in as far as it can.
+        let zoom = zoom_x.min(zoom_y);
+        self.zoom = if zoom.is_nan() { max_zoom } else { zoom }.clamp(min_zoom, max_zoom);
+    }
+
+    // How many pixels across the whole world is.
+    fn world_size(&self) -> f64 {
+        f64::from(TILE_SIZE) * self.zoom.exp2()
+    }
+
+    // Moves the map by a number of pixels, in the window's directions rather than the map's. The
+    // world doesn't wrap around, so there's no going past its edges.
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        let world_size = self.world_size();
+        let (sin, cos) = self.bearing.to_radians().sin_cos();
+        let (dx, dy) = (dx * cos - dy * sin, dx * sin + dy * cos);
+
+        self.x = (self.x + dx / world_size).clamp(0.0, 1.0);
+        self.y = (self.y + dy / world_size).clamp(0.0, 1.0);
+    }
+
+    // Zooms in (or out, for a negative `levels`) while keeping the same point of the map under
+    // `anchor`, a position in the window.
+    pub fn zoom_around(&mut self, levels: f64, anchor: (f64, f64), min_zoom: f64, max_zoom: f64) {
+        // A NaN would get straight through the clamp below, and the camera would never recover.
+        if !levels.is_finite() {
+            return;
+        }
+
+        let offset_x = anchor.0 - f64::from(self.width) / 2.0;
+        let offset_y = anchor.1 - f64::from(self.height) / 2.0;
+
+        self.pan(offset_x, offset_y);
+        self.zoom = (self.zoom + levels).clamp(min_zoom, max_zoom);
+        self.pan(-offset_x, -offset_y);
+    }
+
+    // The zoom level to draw tiles from. Between levels the tiles from the level below are scaled
+    // up, and outside of the source's levels its highest (or lowest) level is stretched (or
+    // shrunk) to fit.
+    pub fn tile_zoom(&self, min_zoom: u8, max_zoom: u8) -> u8 {
+        let zoom = self.zoom.floor().clamp(0.0, f64::from(u8::MAX)) as u8;
+        zoom.clamp(min_zoom, max_zoom)
+    }
+
+    // Every tile at `zoom` at least partly in the window, starting from the middle so that those
+    // get loaded first. When the map is rotated, that's every tile in a box around the window.
+    pub fn visible_tiles(&self, zoom: u8) -> Vec<TileCoord> {
+        let tiles_across = (1u64 << zoom) as f64;
+        let tile_size = self.world_size() / tiles_across;
+        let (center_x, center_y) = (self.x * tiles_across, self.y * tiles_across);
+
+        let (sin, cos) = self.bearing.to_radians().sin_cos();
+        let (width, height) = (f64::from(self.width), f64::from(self.height));
+        let half_width = (cos.abs() * width + sin.abs() * height) / 2.0 / tile_size;
+        let half_height = (sin.abs() * width + cos.abs() * height) / 2.0 / tile_size;
+
+        let last = tiles_across as i64 - 1;
+        let first_tile = |edge: f64| (edge.floor() as i64).max(0);
+        let last_tile = |edge: f64| (edge.ceil() as i64 - 1).min(last);
+        let xs = first_tile(center_x - half_width)..=last_tile(center_x + half_width);
+        let ys = first_tile(center_y - half_height)..=last_tile(center_y + half_height);
+
+        let mut tiles: Vec<TileCoord> = ys
+            .flat_map(|y| {
+                xs.clone().map(move |x| TileCoord {
+                    x: x as u32,
+                    y: y as u32,
+                    z: zoom,
+                })
+            })
+            .collect();
+
+        let distance = |coord: &TileCoord| {
+            let dx = f64::from(coord.x) + 0.5 - center_x;
+            let dy = f64::from(coord.y) + 0.5 - center_y;
+            dx * dx + dy * dy
+        };
+        tiles.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+
+        tiles
+    }
+
+    // Moves, scales and rotates a tile's contents, which are drawn from (0, 0) to (TILE_SIZE,
+    // TILE_SIZE), to where the tile is in the window.
+    pub fn tile_transform(&self, coord: TileCoord) -> Affine {
+        let world_size = self.world_size();
+        let tile_size = world_size / (1u64 << coord.z) as f64;
+        let middle = (f64::from(self.width) / 2.0, f64::from(self.height) / 2.0);
+
+        Affine::translate(middle)
+            * Affine::rotate(-self.bearing.to_radians())
+            * Affine::translate((
+                f64::from(coord.x) * tile_size - self.x * world_size,
+                f64::from(coord.y) * tile_size - self.y * world_size,
+            ))
+            * Affine::scale(tile_size / f64::from(TILE_SIZE))
+    }
+}
+
+// The tile `levels` zoom levels above `coord` which covers it.
+fn ancestor(coord: TileCoord, levels: u8) -> TileCoord {
+    TileCoord {
+        x: coord.x >> levels,
+        y: coord.y >> levels,
+        z: coord.z - levels,
+    }
+}
+
+// A tile ready to draw, and the square of the window it's drawn in. That's the tile's own square,
+// unless it's a lower zoom tile standing in for one which is still loading.
+struct ReadyTile {
+    coord: TileCoord,
+    area: TileCoord,
+    tile: Arc<DecodedTile>,
+}
+
 pub struct MapRenderer {
-    tile: MvtTile,
+    source: Arc<dyn TileSource>,
+    cache: TileCache,
+    loader: TileLoader,
+    // Tiles asked for from the loader which haven't turned up yet.
+    loading: HashSet<TileCoord>,
+    style: Style,
+    min_zoom: u8,
+    max_zoom: u8,
 }
 
 impl MapRenderer {
-    pub fn new(tile: MvtTile) -> Self {
-        MapRenderer { tile }
+    // Tiles are drawn once `loader` has fetched them and they've been passed to `tile_loaded`.
+    pub fn new(
+        source: Arc<dyn TileSource>,
+        loader: TileLoader,
+        cache_budget: usize,
+        style: Style,
+    ) -> Self {
+        let info = source.info();
+
+        MapRenderer {
+            style,
+            min_zoom: info.min_zoom,
+            max_zoom: info.max_zoom,
+            source,
+            cache: TileCache::new(cache_budget),
+            loader,
+            loading: HashSet::new(),
+        }
+    }
+
+    pub fn cache(&self) -> &TileCache {
+        &self.cache
     }
 
-    // TODO: this should be a from?
-    fn path_from_line(line: &LineString<f32>, target_info: &RenderTargetInfo) -> BezPath {
-        let mut path = BezPath::new();
+    pub fn source(&self) -> &dyn TileSource {
+        &*self.source
+    }
 
-        if let Some(first) = line.points().next() {
-            // TODO: this transformation should be a transformation
-            let first = first / 4096.0 * TILE_SIZE as f32;
-            path.move_to((first.x(), first.y()));
+    pub fn tile_loaded(&mut self, loaded: LoadedTile) {
+        self.loading.remove(&loaded.coord);
+        let Ok(id) = TileId::try_from(loaded.coord) else {
+            return;
+        };
 
-            for next in line.points().skip(1) {
-                let next = next / 4096.0 * TILE_SIZE as f32;
-                path.line_to((next.x(), next.y()));
+        // A broken tile is cached as an empty one, so it isn't asked for again every frame.
+        let tile = loaded.tile.unwrap_or_else(|e| {
+            eprintln!("tile {}: {e}", loaded.coord);
+            DecodedTile::default()
+        });
+        self.cache.insert(id, Arc::new(tile));
+    }
+
+    // Returns the visible tiles which are ready to draw, and has the loader fetch the rest. Tiles
+    // that were loading but aren't visible any more are cancelled. Until a tile turns up, the
+    // closest tile above it that's already cached is drawn in its place.
+    fn ready_tiles(&mut self, visible: &[TileCoord]) -> Vec<ReadyTile> {
+        let mut ready = Vec::new();
+        let mut missing = Vec::new();
+        let mut pinned = Vec::new();
+
+        for &area in visible {
+            let Ok(id) = TileId::try_from(area) else {
+                continue;
+            };
+            pinned.push(id);
+
+            if !self.loading.contains(&area)
+                && let Some(tile) = self.cache.get(id)
+            {
+                ready.push(ReadyTile {
+                    coord: area,
+                    area,
+                    tile,
+                });
+                continue;
+            }
+            missing.push(area);
+
+            let min_zoom = self.min_zoom.min(area.z);
+            let parent = (1..=area.z - min_zoom).find_map(|levels| {
+                let coord = ancestor(area, levels);
+                let id = TileId::try_from(coord).ok()?;
+                Some((coord, id, self.cache.peek(id)?))
+            });
+            if let Some((coord, id, tile)) = parent {
+                pinned.push(id);
+                ready.push(ReadyTile { coord, area, tile });
             }
         }
 
-        path
+        self.cache.set_visible(pinned);
+        self.loading = missing.iter().copied().collect();
+        self.loader.request(missing);
+
+        ready
     }
 
-    fn draw_line(
+    pub fn render_to_scene(
         &mut self,
         scene: &mut Scene,
         target_info: &RenderTargetInfo,
-        transform: Affine,
-        line: &LineString<f32>,
+        camera: &Camera,
     ) {
-        // TODO: refactor to use BezPath Kurbo primitive
-        let my_stroke = Stroke::new(6.0);
-        let my_color = Color::new([0.7, 0.6, 1.0, 1.0]);
+        let tile_size = f64::from(TILE_SIZE);
+        let zoom = camera.tile_zoom(self.min_zoom, self.max_zoom);
+        let visible = if camera.zoom < f64::from(zoom) - MAX_UNDERZOOM {
+            Vec::new()
+        } else {
+            camera.visible_tiles(zoom)
+        };
+        let ready = self.ready_tiles(&visible);
 
-        let path = MapRenderer::path_from_line(line, target_info);
+        // Each style layer is drawn across every tile before moving on to the next, so that a
+        // road near the edge of one tile isn't covered up by the next tile's landuse.
+        for layer in &self.style.layers {
+            if !layer.visible_at(camera.zoom) {
+                continue;
+            }
+            if let Paint::Background(background) = &layer.paint {
+                let context = Context {
+                    zoom: camera.zoom,
+                    feature: None,
+                };
+                let color = background.color.with_opacity(&background.opacity, &context);
+                let window = Rect::new(
+                    0.0,
+                    0.0,
+                    f64::from(target_info.width),
+                    f64::from(target_info.height),
+                );
+                scene.fill(Fill::NonZero, Affine::IDENTITY, color, None, &window);
+                continue;
+            }
+
+            for ready in &ready {
+                let features: Vec<Context> = ready
+                    .tile
+                    .layers
+                    .iter()
+                    .filter(|tile_layer| layer.draws(&tile_layer.name))
+                    .flat_map(|tile_layer| &tile_layer.features)
+                    .map(|feature| Context {
+                        zoom: camera.zoom,
+                        feature: Some(feature),
+                    })
+                    .filter(|context| layer.passes_filter(context))
+                    .collect();
+                if features.is_empty() {
+                    continue;
+                }
 
-        scene.stroke(&my_stroke, transform, my_color, None, &path);
+                // Tiles have a buffer of features from their neighbours, which would otherwise get
+                // drawn twice. Parent tiles standing in for others only fill in the missing ones.
+                let clip = Rect::new(0.0, 0.0, tile_size, tile_size);
+                scene.push_clip_layer(camera.tile_transform(ready.area), &clip);
+                let transform = camera.tile_transform(ready.coord);
+                for context in &features {
+                    draw_feature(scene, transform, &layer.paint, context);
+                }
+                scene.pop_layer();
+            }
+        }
     }
+}
 
-    fn draw_polygon(
-        &mut self,
-        scene: &mut Scene,
-        target_info: &RenderTargetInfo,
-        transform: Affine,
-        polygon: &Polygon<f32>,
-    ) {
-        let stroke = Stroke::new(1.0);
-        let stroke_color = Color::new([0.0, 0.5, 0.0, 1.0]);
-        let fill_color = Color::new([0.2, 7.0, 0.5, 0.5]);
-
-        let path = MapRenderer::path_from_line(polygon.exterior(), target_info);
-
-        scene.fill(
-            vello::peniko::Fill::NonZero,
-            transform,
-            fill_color,
-            None,
-            &path,
-        );
+// Paths are transformed before they're stroked, so that lines stay the same width however much
+// the tile is scaled.
+fn draw_feature(scene: &mut Scene, transform: Affine, paint: &Paint, context: &Context) {
+    let Some(feature) = context.feature else {
+        return;
+    };
+    let geometry = &feature.geometry;
 
-        scene.stroke(&stroke, transform, stroke_color, None, &path);
+    match paint {
+        Paint::Background(_) => {}
+        Paint::Fill(fill) => {
+            let color = fill.color.with_opacity(&fill.opacity, context);
+            let outline_color = fill
+                .outline_color
+                .as_ref()
+                .map(|outline_color| outline_color.with_opacity(&fill.opacity, context));
 
-        // TODO(render internal areas to, alternate rings with Fill:EvenOdd)
-    }
+            for polygon in polygons(geometry) {
+                let path = transform * path_from_polygon(polygon);
+                // Holes are wound the other way to the outside, but even-odd doesn't care.
+                scene.fill(Fill::EvenOdd, Affine::IDENTITY, color, None, &path);
+                if let Some(outline_color) = outline_color {
+                    scene.stroke(
+                        &Stroke::new(1.0),
+                        Affine::IDENTITY,
+                        outline_color,
+                        None,
+                        &path,
+                    );
+                }
+            }
+        }
+        Paint::Line(line) => {
+            let color = line.color.with_opacity(&line.opacity, context);
+            let width = line.width.evaluate(context);
+            let dashes = line.dashes.evaluate(context);
+            let mut stroke = Stroke::new(width).with_caps(line.cap).with_join(line.join);
+            if dashes.iter().all(|length| *length >= 0.0) && dashes.iter().sum::<f64>() > 0.0 {
+                stroke = stroke.with_dashes(0.0, dashes.iter().map(|length| length * width));
+            }
 
-    fn draw_feature(
-        &mut self,
-        scene: &mut Scene,
-        target_info: &RenderTargetInfo,
-        transform: Affine,
-        feature: &Feature,
-    ) {
-        match &feature.geometry {
-            Geometry::MultiLineString(multi_line) => multi_line
-                .iter()
-                .for_each(|l| self.draw_line(scene, target_info, transform, l)),
-            Geometry::LineString(line) => self.draw_line(scene, target_info, transform, line),
-            Geometry::Polygon(_) => println!("got polygon"),
-            Geometry::MultiPolygon(multi_polygon) => {
-                multi_polygon
-                    .iter()
-                    .for_each(|p| self.draw_polygon(scene, target_info, transform, p));
+            // Line layers outline polygons too.
+            let rings = polygons(geometry)
+                .into_iter()
+                .flat_map(|polygon| std::iter::once(polygon.exterior()).chain(polygon.interiors()));
+            for string in lines(geometry).into_iter().chain(rings) {
+                let path = transform * path_from_line(string);
+                scene.stroke(&stroke, Affine::IDENTITY, color, None, &path);
+            }
+        }
+        Paint::Circle(circle) => {
+            let color = circle.color.with_opacity(&circle.opacity, context);
+            let radius = circle.radius.evaluate(context);
+            let stroke_color = circle
+                .stroke_color
+                .with_opacity(&circle.stroke_opacity, context);
+            let stroke_width = circle.stroke_width.evaluate(context);
+
+            for point in points(geometry) {
+                let dot = Circle::new(transform * point, radius);
+                scene.fill(Fill::NonZero, Affine::IDENTITY, color, None, &dot);
+                if stroke_width > 0.0 {
+                    let stroke = Stroke::new(stroke_width);
+                    scene.stroke(&stroke, Affine::IDENTITY, stroke_color, None, &dot);
+                }
+            }
+        }
+        // TODO: labels and icons, once there's text rendering and sprites.
+        Paint::Symbol(symbol) => {
+            let color = symbol.color.with_opacity(&symbol.opacity, context);
+            for point in points(geometry) {
+                let dot = Circle::new(transform * point, SYMBOL_DOT_RADIUS);
+                scene.fill(Fill::NonZero, Affine::IDENTITY, color, None, &dot);
             }
-            Geometry::GeometryCollection(_) => println!("got geometry collection"),
-            _ => println!("Other geoemetry value"),
         }
     }
+}
 
-    pub fn render_to_scene(
-        &mut self,
-        scene: &mut Scene,
-        target_info: &RenderTargetInfo,
-        transform: Affine,
-    ) {
-        let layer_names = self.tile.get_layer_names().unwrap(); // FIXME
+fn lines(geometry: &Geometry<f32>) -> Vec<&LineString<f32>> {
+    match geometry {
+        Geometry::LineString(line) => vec![line],
+        Geometry::MultiLineString(lines) => lines.iter().collect(),
+        _ => Vec::new(),
+    }
+}
 
-        let road_layer_id = layer_names.iter().position(|x| x == "roads");
+fn polygons(geometry: &Geometry<f32>) -> Vec<&Polygon<f32>> {
+    match geometry {
+        Geometry::Polygon(polygon) => vec![polygon],
+        Geometry::MultiPolygon(polygons) => polygons.iter().collect(),
+        _ => Vec::new(),
+    }
+}
 
-        let Some(road_layer_id) = road_layer_id else {
-            return;
+// In the tile's pixels, from 0 to TILE_SIZE.
+fn points(geometry: &Geometry<f32>) -> Vec<Point> {
+    let points = match geometry {
+        Geometry::Point(point) => vec![*point],
+        Geometry::MultiPoint(points) => points.0.clone(),
+        _ => Vec::new(),
+    };
+
+    points
+        .into_iter()
+        .map(|point| {
+            let point = point / 4096.0 * TILE_SIZE;
+            Point::new(f64::from(point.x()), f64::from(point.y()))
+        })
+        .collect()
+}
+
+fn path_from_line(line: &LineString<f32>) -> BezPath {
+    let mut path = BezPath::new();
+
+    if let Some(first) = line.points().next() {
+        // TODO: this transformation should be a transformation
+        let first = first / 4096.0 * TILE_SIZE;
+        path.move_to((first.x(), first.y()));
+
+        for next in line.points().skip(1) {
+            let next = next / 4096.0 * TILE_SIZE;
+            path.line_to((next.x(), next.y()));
+        }
+    }
+
+    path
+}
+
+fn path_from_polygon(polygon: &Polygon<f32>) -> BezPath {
+    let mut path = BezPath::new();
+
+    for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+        path.extend(path_from_line(ring));
+        path.close_path();
+    }
+
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::PmTilesReader;
+
+    #[test]
+    fn test_visible_tiles() {
+        // A 512 pixel window exactly over tile 1/1 at z2, nudged 10 pixels to the right.
+        let mut camera = Camera {
+            x: (1.5 * 512.0 + 10.0) / 2048.0,
+            y: 1.5 / 4.0,
+            zoom: 2.0,
+            bearing: 0.0,
+            width: 512,
+            height: 512,
         };
+        let coord = |x, y| TileCoord { x, y, z: 2 };
+        assert_eq!(camera.visible_tiles(2), vec![coord(1, 1), coord(2, 1)]);
+        assert_eq!(
+            camera.tile_transform(coord(2, 1)),
+            Affine::translate((502.0, 0.0))
+        );
 
-        let landuse_layer_id = layer_names.iter().position(|x| x == "landuse");
-        let Some(landuse_layer_id) = landuse_layer_id else {
-            return;
+        // Halfway to z3 the same tiles are drawn bigger, so fewer fit.
+        camera.zoom = 2.5;
+        camera.x = 1.5 / 4.0;
+        assert_eq!(camera.tile_zoom(0, 14), 2);
+        assert_eq!(camera.visible_tiles(2), vec![coord(1, 1)]);
+        // Its middle stays in the middle of the window, but it's sqrt(2) times bigger.
+        let transform = camera.tile_transform(coord(1, 1));
+        let middle = transform * Point::new(256.0, 256.0);
+        let corner = transform * Point::new(512.0, 512.0);
+        assert!((middle - Point::new(256.0, 256.0)).hypot() < 1e-9);
+        assert!((corner.x - middle.x - 256.0 * 2f64.sqrt()).abs() < 1e-9);
+
+        // Past the source's levels the nearest one gets stretched.
+        camera.zoom = 17.2;
+        assert_eq!(camera.tile_zoom(0, 14), 14);
+        camera.zoom = 1.0;
+        assert_eq!(camera.tile_zoom(3, 14), 3);
+
+        // Only tiles within the world are visible.
+        camera.x = 0.0;
+        camera.y = 0.0;
+        camera.zoom = 2.0;
+        assert_eq!(camera.visible_tiles(2), vec![coord(0, 0)]);
+        camera.pan(-100.0, 100.0);
+        assert_eq!((camera.x, camera.y), (0.0, 100.0 / 2048.0));
+
+        // Zooming in on the corner of tile 1/1 keeps it under the cursor.
+        camera.x = 1.5 / 4.0;
+        camera.y = 1.5 / 4.0;
+        camera.zoom_around(1.0, (0.0, 0.0), 0.0, 20.0);
+        assert_eq!(camera.zoom, 3.0);
+        let corner = camera.tile_transform(coord(1, 1)) * Point::ZERO;
+        assert!(corner.to_vec2().hypot() < 1e-9);
+        camera.zoom_around(30.0, (0.0, 0.0), 0.0, 20.0);
+        assert_eq!(camera.zoom, 20.0);
+        camera.zoom_around(f64::NAN, (0.0, 0.0), 0.0, 20.0);
+        camera.zoom_around(f64::NEG_INFINITY, (0.0, 0.0), 0.0, 20.0);
+        assert_eq!(camera.zoom, 20.0);
+
+        camera.zoom = 0.0;
+        camera.width = 2000;
+        camera.height = 100;
+        assert_eq!(
+            camera.visible_tiles(0),
+            vec![TileCoord { x: 0, y: 0, z: 0 }]
+        );
+
+        // Looking at Toolangi puts the tile with Toolangi in it in the middle of the window.
+        let toolangi = Position {
+            lat: -37.53,
+            long: 145.47,
         };
+        let mut camera = Camera::looking_at(&toolangi, 13.0);
+        camera.width = 1024;
+        camera.height = 768;
+        let tiles = camera.visible_tiles(13);
+        assert_eq!(tiles[0], crate::pmtiles::lat_lon_to_xyz(-37.53, 145.47, 13));
+        assert!(tiles.len() >= 6 && tiles.len() <= 12);
+    }
 
-        // FIXME: remove unwrap
-        let landuse_features = self.tile.get_features(landuse_layer_id).unwrap();
-        for feature in landuse_features {
-            self.draw_feature(scene, target_info, transform, &feature);
-        }
+    #[test]
+    fn test_bearing() {
+        // Looking east from the middle of tile 1/1 at z2.
+        let mut camera = Camera {
+            x: 1.5 / 4.0,
+            y: 1.5 / 4.0,
+            zoom: 2.0,
+            bearing: 90.0,
+            width: 1024,
+            height: 256,
+        };
 
-        // FIXME: remove unwrap
-        let road_features = self.tile.get_features(road_layer_id).unwrap();
-        for feature in road_features {
-            self.draw_feature(scene, target_info, transform, &feature);
-        }
+        // The tile's east edge is at the top of the window, and north is on the left.
+        let transform = camera.tile_transform(TileCoord { x: 1, y: 1, z: 2 });
+        let east = transform * Point::new(512.0, 256.0);
+        let north = transform * Point::new(256.0, 0.0);
+        assert!((east - Point::new(512.0, -128.0)).hypot() < 1e-9);
+        assert!((north - Point::new(256.0, 128.0)).hypot() < 1e-9);
+
+        // The window is taller than it is wide as far as the map's concerned.
+        let tiles = camera.visible_tiles(2);
+        assert_eq!(tiles.len(), 3);
+        assert!(tiles.iter().all(|coord| coord.x == 1));
+
+        // Dragging up moves east.
+        camera.pan(0.0, -512.0);
+        assert!((camera.x - 2.5 / 4.0).abs() < 1e-12);
+        assert!((camera.y - 1.5 / 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_fit() {
+        let mut camera = Camera::looking_at(
+            &Position {
+                lat: 0.0,
+                long: 0.0,
+            },
+            0.0,
+        );
+        camera.width = 1024;
+        camera.height = 768;
+
+        // Toolangi's bounding box is taller than it is wide once it's projected, so it fills the
+        // height.
+        let min = Position {
+            lat: -37.606632,
+            long: 145.389401,
+        };
+        let max = Position {
+            lat: -37.426958,
+            long: 145.558488,
+        };
+        camera.fit(&min, &max, 0.0, 22.0);
+        let (_, top) = fractional_lat_lon_to_xyz(max.lat, max.long, 0);
+        let (_, bottom) = fractional_lat_lon_to_xyz(min.lat, max.long, 0);
+        let zoom = (768.0 / ((bottom - top) * 512.0)).log2();
+        assert!((camera.zoom - zoom).abs() < 1e-9);
+        let center = crate::pmtiles::fractional_xyz_to_lat_lon(camera.x, camera.y, 0);
+        assert!((center.long - 145.4739445).abs() < 1e-6);
+        assert!(center.lat > min.lat && center.lat < max.lat);
+
+        camera.fit(&min, &min, 0.0, 22.0);
+        assert_eq!(camera.zoom, 22.0);
+    }
+
+    #[test]
+    fn test_parent_fallback() {
+        let source: Arc<dyn TileSource> =
+            Arc::new(PmTilesReader::open("toolangi.pmtiles").unwrap());
+        let loader = TileLoader::new(source.clone(), 1, |_| {});
+        let mut renderer = MapRenderer::new(source, loader, 64 * 1024 * 1024, Style::fallback(&[]));
+
+        let child = TileCoord {
+            x: 7406,
+            y: 5018,
+            z: 13,
+        };
+        let grandparent = ancestor(child, 2);
+        assert_eq!(
+            grandparent,
+            TileCoord {
+                x: 1851,
+                y: 1254,
+                z: 11
+            }
+        );
+
+        // Nothing to draw until something has loaded.
+        assert!(renderer.ready_tiles(&[child]).is_empty());
+        assert!(renderer.loading.contains(&child));
+
+        renderer.tile_loaded(LoadedTile {
+            coord: grandparent,
+            tile: Ok(DecodedTile::default()),
+        });
+        let ready = renderer.ready_tiles(&[child]);
+        assert_eq!(ready.len(), 1);
+        assert_eq!((ready[0].coord, ready[0].area), (grandparent, child));
+
+        // Once the tile itself turns up it replaces its grandparent.
+        renderer.tile_loaded(LoadedTile {
+            coord: child,
+            tile: Ok(DecodedTile::default()),
+        });
+        let ready = renderer.ready_tiles(&[child]);
+        assert_eq!(ready.len(), 1);
+        assert_eq!((ready[0].coord, ready[0].area), (child, child));
+        assert!(renderer.loading.is_empty());
     }
 }
@@ -0,0 +1,374 @@
+// MBTiles archives: SQLite databases with a `metadata` table of name/value pairs and a `tiles`
+// table. Rows are numbered TMS-style, counting up from the south, whereas PMTiles (and everything
+// else here) counts down from the north.
+
+use bytes::Bytes;
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::pmtiles::{
+    Compression, Header, MAX_Z, Metadata, ParseError, Position, TileCoord, TileType, tile_bounds,
+};
+use crate::tile_source::{TileSetInfo, TileSource, detect_compression, tile_type_from_name};
+
+pub fn is_mbtiles(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("mbtiles"))
+}
+
+// Flipping is its own inverse. The database can hold any numbers at all, so they're checked here
+// rather than trusted.
+fn flip_y(coord: TileCoord) -> Result<u32, ParseError> {
+    if coord.z >= MAX_Z {
+        return Err(ParseError::TooHighZIndex);
+    }
+    let tiles_across = 1u64 << coord.z;
+    if u64::from(coord.y) >= tiles_across {
+        return Err(ParseError::TileOutOfRange(coord));
+    }
+
+    Ok((tiles_across - 1 - u64::from(coord.y)) as u32)
+}
+
+pub struct MbTilesReader {
+    // Connections can't be shared between threads, but the reader should be.
+    connection: Mutex<Connection>,
+    info: TileSetInfo,
+    metadata: Arc<Metadata>,
+}
+
+impl MbTilesReader {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ParseError> {
+        // Opening a path that doesn't exist would otherwise fail with a less helpful "unable to
+        // open database file".
+        fs::metadata(path.as_ref())?;
+
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+        let rows = connection
+            .prepare("SELECT name, value FROM metadata")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        let table = parse_metadata_table(rows);
+
+        // The minzoom and maxzoom rows are optional, so ask the tiles instead.
+        let (min_zoom, max_zoom): (Option<u8>, Option<u8>) = connection.query_row(
+            "SELECT MIN(zoom_level), MAX(zoom_level) FROM tiles",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let (min_zoom, max_zoom) = (min_zoom.unwrap_or(0), max_zoom.unwrap_or(0));
+
+        let (min_position, max_position) = match table.bounds {
+            Some(bounds) => bounds,
+            None => {
+                let tms_coords: Vec<TileCoord> = connection
+                    .prepare("SELECT tile_column, tile_row FROM tiles WHERE zoom_level = ?")?
+                    .query_map([min_zoom], |row| {
+                        Ok(TileCoord {
+                            x: row.get(0)?,
+                            y: row.get(1)?,
+                            z: min_zoom,
+                        })
+                    })?
+                    .collect::<Result<_, _>>()?;
+                let coords: Vec<TileCoord> = tms_coords
+                    .into_iter()
+                    .map(|coord| {
+                        Ok(TileCoord {
+                            y: flip_y(coord)?,
+                            ..coord
+                        })
+                    })
+                    .collect::<Result<_, ParseError>>()?;
+                tile_bounds(&coords)
+            }
+        };
+        let (center_zoom, center_position) = table.center.unwrap_or_else(|| {
+            let center = Position {
+                lat: (min_position.lat + max_position.lat) / 2.0,
+                long: (min_position.long + max_position.long) / 2.0,
+            };
+            (min_zoom, center)
+        });
+
+        let info = TileSetInfo {
+            tile_type: table.tile_type,
+            min_zoom,
+            max_zoom,
+            min_position,
+            max_position,
+            center_zoom,
+            center_position,
+            internal_compression: None,
+        };
+
+        Ok(MbTilesReader {
+            connection: Mutex::new(connection),
+            info,
+            metadata: Arc::new(table.metadata),
+        })
+    }
+
+    // Calls `f` with every tile, as stored, in no particular order.
+    pub fn for_each_tile(
+        &self,
+        mut f: impl FnMut(TileCoord, Bytes) -> Result<(), ParseError>,
+    ) -> Result<(), ParseError> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement =
+            connection.prepare("SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles")?;
+        let mut rows = statement.query([])?;
+
+        while let Some(row) = rows.next()? {
+            let z: u8 = row.get(0)?;
+            let x: u32 = row.get(1)?;
+            let tms_y: u32 = row.get(2)?;
+            let data: Vec<u8> = row.get(3)?;
+
+            let y = flip_y(TileCoord { x, y: tms_y, z })?;
+            let coord = TileCoord { x, y, z };
+
+            f(coord, Bytes::from(data))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TileSource for MbTilesReader {
+    fn info(&self) -> TileSetInfo {
+        self.info.clone()
+    }
+
+    fn metadata(&self) -> Arc<Metadata> {
+        self.metadata.clone()
+    }
+
+    fn get_tile_data(&self, coord: TileCoord) -> Result<Option<(Bytes, Compression)>, ParseError> {
+        let connection = self.connection.lock().unwrap();
+        let data: Option<Vec<u8>> = connection
+            .query_row(
+                "SELECT tile_data FROM tiles WHERE zoom_level = ? AND tile_column = ? AND tile_row = ?",
+                params![coord.z, coord.x, flip_y(coord)?],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(data.map(|data| {
+            let compression = detect_compression(&data);
+            (Bytes::from(data), compression)
+        }))
+    }
+}
+
+// The metadata table sorted into what goes in a PMTiles header and what goes in its JSON metadata.
+struct MetadataTable {
+    tile_type: TileType,
+    bounds: Option<(Position, Position)>,
+    center: Option<(u8, Position)>,
+    metadata: Metadata,
+}
+
+fn parse_metadata_table(rows: Vec<(String, String)>) -> MetadataTable {
+    let mut info = MetadataTable {
+        tile_type: TileType::Unknown,
+        bounds: None,
+        center: None,
+        metadata: Metadata::default(),
+    };
+
+    for (name, value) in rows {
+        match name.as_str() {
+            "name" => info.metadata.name = Some(value),
+            "description" => info.metadata.description = Some(value),
+            "attribution" => info.metadata.attribution = Some(value),
+            "format" => info.tile_type = tile_type_from_name(&value),
+            // PMTiles works these out from the tiles themselves.
+            "minzoom" | "maxzoom" => {}
+            // The rest are only taken apart if they're well formed, otherwise they're kept as is
+            // below.
+            "bounds" if let Some([west, south, east, north]) = parse_numbers(&value) => {
+                let min = Position {
+                    lat: south,
+                    long: west,
+                };
+                let max = Position {
+                    lat: north,
+                    long: east,
+                };
+                info.bounds = Some((min, max));
+            }
+            "center" if let Some([long, lat, zoom]) = parse_numbers(&value) => {
+                info.center = Some((zoom as u8, Position { lat, long }));
+            }
+            // Vector tilesets keep their layers in a JSON blob.
+            "json" if let Ok(json) = serde_json::from_str::<Metadata>(&value) => {
+                info.metadata.vector_layers = json.vector_layers;
+                info.metadata.tilestats = json.tilestats;
+                info.metadata.extra.extend(json.extra);
+            }
+            _ => {
+                info.metadata.extra.insert(name, value.into());
+            }
+        }
+    }
+
+    info
+}
+
+fn parse_numbers<const N: usize>(value: &str) -> Option<[f64; N]> {
+    let numbers: Vec<f64> = value
+        .split(',')
+        .map(|v| v.trim().parse().ok())
+        .collect::<Option<_>>()?;
+
+    numbers.try_into().ok()
+}
+
+// The inverse of parse_metadata_table.
+pub fn metadata_table(header: &Header, metadata: &Metadata) -> Vec<(String, String)> {
+    let mut rows: BTreeMap<String, String> = BTreeMap::new();
+
+    // Anything PMTiles-specific goes in as is, with JSON values as JSON text.
+    for (name, value) in &metadata.extra {
+        let value = match value {
+            serde_json::Value::String(s) => s.clone(),
+            value => value.to_string(),
+        };
+        rows.insert(name.clone(), value);
+    }
+
+    let fields = [
+        ("name", &metadata.name),
+        ("description", &metadata.description),
+        ("attribution", &metadata.attribution),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            rows.insert(name.to_owned(), value.clone());
+        }
+    }
+
+    let format = match header.tile_type {
+        TileType::Mvt => Some("pbf"),
+        TileType::Png => Some("png"),
+        TileType::Jpeg => Some("jpg"),
+        TileType::WebP => Some("webp"),
+        TileType::Avif => Some("avif"),
+        TileType::Unknown => None,
+    };
+    if let Some(format) = format {
+        rows.insert(String::from("format"), format.to_owned());
+    }
+
+    let (min, max, center) = (
+        &header.min_position,
+        &header.max_position,
+        &header.center_position,
+    );
+    rows.insert(
+        String::from("bounds"),
+        format!("{},{},{},{}", min.long, min.lat, max.long, max.lat),
+    );
+    rows.insert(
+        String::from("center"),
+        format!("{},{},{}", center.long, center.lat, header.center_zoom),
+    );
+    rows.insert(String::from("minzoom"), header.min_zoom.to_string());
+    rows.insert(String::from("maxzoom"), header.max_zoom.to_string());
+
+    if !metadata.vector_layers.is_empty() || metadata.tilestats.is_some() {
+        let json = Metadata {
+            vector_layers: metadata.vector_layers.clone(),
+            tilestats: metadata.tilestats.clone(),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&json).expect("metadata always serializes");
+        rows.insert(String::from("json"), json);
+    }
+
+    rows.into_iter().collect()
+}
+
+// Writes the deduplicated layout used by mbutil and friends: each distinct tile is stored once in
+// `images`, and `tiles` is a view joining it to the `map` of coordinates.
+pub struct MbTilesWriter {
+    connection: Connection,
+    tile_ids: HashMap<Bytes, usize>,
+}
+
+impl MbTilesWriter {
+    // Replaces any existing file, like PmTilesWriter::write_file does.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, ParseError> {
+        match fs::remove_file(path.as_ref()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "
+            CREATE TABLE metadata (name TEXT, value TEXT);
+            CREATE UNIQUE INDEX name ON metadata (name);
+            CREATE TABLE map (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_id TEXT);
+            CREATE UNIQUE INDEX map_index ON map (zoom_level, tile_column, tile_row);
+            CREATE TABLE images (tile_data BLOB, tile_id TEXT);
+            CREATE UNIQUE INDEX images_id ON images (tile_id);
+            CREATE VIEW tiles AS
+                SELECT map.zoom_level AS zoom_level, map.tile_column AS tile_column,
+                    map.tile_row AS tile_row, images.tile_data AS tile_data
+                FROM map JOIN images ON images.tile_id = map.tile_id;
+            BEGIN;
+            ",
+        )?;
+
+        Ok(MbTilesWriter {
+            connection,
+            tile_ids: HashMap::new(),
+        })
+    }
+
+    pub fn set_metadata(&mut self, rows: &[(String, String)]) -> Result<(), ParseError> {
+        let mut statement = self
+            .connection
+            .prepare_cached("INSERT OR REPLACE INTO metadata (name, value) VALUES (?, ?)")?;
+        for (name, value) in rows {
+            statement.execute(params![name, value])?;
+        }
+
+        Ok(())
+    }
+
+    // Like PmTilesWriter::add_tile, adding the same coordinates twice replaces the first tile.
+    pub fn add_tile(&mut self, coord: TileCoord, data: Bytes) -> Result<(), ParseError> {
+        let next_id = self.tile_ids.len();
+        let tile_id = *self.tile_ids.entry(data.clone()).or_insert(next_id);
+
+        if tile_id == next_id {
+            self.connection
+                .prepare_cached("INSERT INTO images (tile_id, tile_data) VALUES (?, ?)")?
+                .execute(params![tile_id.to_string(), &data[..]])?;
+        }
+
+        self.connection
+            .prepare_cached(
+                "INSERT OR REPLACE INTO map (zoom_level, tile_column, tile_row, tile_id) VALUES (?, ?, ?, ?)",
+            )?
+            .execute(params![coord.z, coord.x, flip_y(coord)?, tile_id.to_string()])?;
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), ParseError> {
+        self.connection.execute_batch("COMMIT;")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+// Moving the camera around with the mouse and keyboard: drag to pan (and let go mid-drag to send
+// the map gliding), scroll or pinch to zoom around the cursor, double-click to zoom in, and arrow
+// keys and +/- for the keyboard. Positions are in window pixels, like winit's.
+
+use std::time::{Duration, Instant};
+
+use crate::map_renderer::Camera;
+use crate::pmtiles::{Position, fractional_lat_lon_to_xyz};
+
+pub const MIN_ZOOM: f64 = 0.0;
+// Well past what most archives have, for looking closely at overzoomed tiles.
+pub const MAX_ZOOM: f64 = 22.0;
+
+const KEY_PAN_PIXELS: f64 = 100.0;
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+const DOUBLE_CLICK_DISTANCE: f64 = 5.0;
+// The most a single pinch event can shrink the map by. Shrinking by all of it has no zoom level.
+const MIN_PINCH_SCALE: f64 = 0.1;
+// How quickly a flung map slows down, as the fraction of its speed lost per second.
+const FRICTION: f64 = 0.95;
+const MIN_GLIDE_SPEED: f64 = 10.0;
+// If the mouse stopped this long before the button was let go, the map stays where it is.
+const GLIDE_DELAY: Duration = Duration::from_millis(50);
+
+// Where the viewer starts: an area fitted to the window, with the centre and zoom overridden if
+// they were asked for. It can't be turned into a camera until the window is open and its size is
+// known.
+pub struct StartView {
+    pub fit: (Position, Position),
+    pub center: Option<Position>,
+    pub zoom: Option<f64>,
+    pub bearing: f64,
+}
+
+impl StartView {
+    pub fn camera(&self, width: u32, height: u32) -> Camera {
+        let (min, max) = &self.fit;
+        let mut camera = Camera::looking_at(min, MIN_ZOOM);
+        camera.width = width;
+        camera.height = height;
+        camera.fit(min, max, MIN_ZOOM, MAX_ZOOM);
+
+        if let Some(center) = &self.center {
+            (camera.x, camera.y) = fractional_lat_lon_to_xyz(center.lat, center.long, 0);
+        }
+        if let Some(zoom) = self.zoom {
+            camera.zoom = zoom;
+        }
+        camera.bearing = self.bearing;
+
+        camera
+    }
+}
+
+#[derive(Default)]
+pub struct Navigation {
+    cursor: Option<(f64, f64)>,
+    dragging: bool,
+    // In pixels per second, smoothed over the last few movements of a drag.
+    velocity: (f64, f64),
+    last_move: Option<Instant>,
+    last_click: Option<(Instant, (f64, f64))>,
+}
+
+pub enum Key {
+    Left,
+    Right,
+    Up,
+    Down,
+    ZoomIn,
+    ZoomOut,
+}
+
+impl Navigation {
+    pub fn cursor_moved(&mut self, camera: &mut Camera, position: (f64, f64), now: Instant) {
+        if let (true, Some(last)) = (self.dragging, self.cursor) {
+            let delta = (position.0 - last.0, position.1 - last.1);
+            camera.pan(-delta.0, -delta.1);
+
+            let elapsed = self
+                .last_move
+                .map_or(0.0, |last_move| (now - last_move).as_secs_f64());
+            if elapsed > 0.0 {
+                let speed = (delta.0 / elapsed, delta.1 / elapsed);
+                self.velocity = (
+                    0.8 * speed.0 + 0.2 * self.velocity.0,
+                    0.8 * speed.1 + 0.2 * self.velocity.1,
+                );
+            }
+            self.last_move = Some(now);
+        }
+
+        self.cursor = Some(position);
+    }
+
+    pub fn cursor_left(&mut self) {
+        self.cursor = None;
+        self.dragging = false;
+    }
+
+    // Only the main button does anything.
+    pub fn button(&mut self, camera: &mut Camera, pressed: bool, now: Instant) {
+        if !pressed {
+            self.dragging = false;
+            let stopped = self
+                .last_move
+                .is_none_or(|last_move| now - last_move > GLIDE_DELAY);
+            if stopped {
+                self.velocity = (0.0, 0.0);
+            }
+            return;
+        }
+
+        self.dragging = true;
+        self.velocity = (0.0, 0.0);
+        self.last_move = None;
+
+        let Some(cursor) = self.cursor else {
+            return;
+        };
+        let double_click = self.last_click.is_some_and(|(time, position)| {
+            let distance = (cursor.0 - position.0).hypot(cursor.1 - position.1);
+            now - time < DOUBLE_CLICK_TIME && distance < DOUBLE_CLICK_DISTANCE
+        });
+        if double_click {
+            camera.zoom_around(1.0, cursor, MIN_ZOOM, MAX_ZOOM);
+            self.last_click = None;
+        } else {
+            self.last_click = Some((now, cursor));
+        }
+    }
+
+    // `levels` is positive to zoom in. Zooms around the cursor, or the middle of the window if the
+    // cursor isn't over it.
+    pub fn zoom(&mut self, camera: &mut Camera, levels: f64) {
+        let middle = (
+            f64::from(camera.width) / 2.0,
+            f64::from(camera.height) / 2.0,
+        );
+        let anchor = self.cursor.unwrap_or(middle);
+        camera.zoom_around(levels, anchor, MIN_ZOOM, MAX_ZOOM);
+    }
+
+    // `delta` is how much bigger things should get, so 1.0 would double in size.
+    pub fn pinch(&mut self, camera: &mut Camera, delta: f64) {
+        let scale = (1.0 + delta).max(MIN_PINCH_SCALE);
+        self.zoom(camera, scale.log2());
+    }
+
+    pub fn key(&mut self, camera: &mut Camera, key: Key) {
+        let middle = (
+            f64::from(camera.width) / 2.0,
+            f64::from(camera.height) / 2.0,
+        );
+
+        match key {
+            Key::Left => camera.pan(-KEY_PAN_PIXELS, 0.0),
+            Key::Right => camera.pan(KEY_PAN_PIXELS, 0.0),
+            Key::Up => camera.pan(0.0, -KEY_PAN_PIXELS),
+            Key::Down => camera.pan(0.0, KEY_PAN_PIXELS),
+            Key::ZoomIn => camera.zoom_around(1.0, middle, MIN_ZOOM, MAX_ZOOM),
+            Key::ZoomOut => camera.zoom_around(-1.0, middle, MIN_ZOOM, MAX_ZOOM),
+        }
+    }
+
+    // Called every frame, to keep a flung map gliding.
+    pub fn update(&mut self, camera: &mut Camera, delta_time: f64) {
+        if self.dragging {
+            return;
+        }
+
+        let (vx, vy) = self.velocity;
+        if vx.hypot(vy) < MIN_GLIDE_SPEED {
+            self.velocity = (0.0, 0.0);
+            return;
+        }
+
+        camera.pan(-vx * delta_time, -vy * delta_time);
+        let slowdown = (1.0 - FRICTION).powf(delta_time);
+        self.velocity = (vx * slowdown, vy * slowdown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera {
+            x: 0.5,
+            y: 0.5,
+            zoom: 4.0,
+            bearing: 0.0,
+            width: 800,
+            height: 600,
+        }
+    }
+
+    #[test]
+    fn test_start_view() {
+        let mut start = StartView {
+            fit: (
+                Position {
+                    lat: -40.0,
+                    long: 140.0,
+                },
+                Position {
+                    lat: -30.0,
+                    long: 150.0,
+                },
+            ),
+            center: None,
+            zoom: None,
+            bearing: 45.0,
+        };
+
+        let fitted = start.camera(800, 600);
+        assert!(fitted.zoom > 4.0 && fitted.zoom < 6.0);
+        assert_eq!(fitted.bearing, 45.0);
+        // A bigger window fits the same area at a higher zoom.
+        assert!(start.camera(1600, 1200).zoom > fitted.zoom);
+
+        start.zoom = Some(12.5);
+        let zoomed = start.camera(800, 600);
+        assert_eq!(
+            (zoomed.x, zoomed.y, zoomed.zoom),
+            (fitted.x, fitted.y, 12.5)
+        );
+
+        start.center = Some(Position {
+            lat: 0.0,
+            long: 0.0,
+        });
+        let centered = start.camera(800, 600);
+        assert_eq!((centered.x, centered.y, centered.zoom), (0.5, 0.5, 12.5));
+    }
+
+    #[test]
+    fn test_drag_and_glide() {
+        let mut camera = camera();
+        let mut navigation = Navigation::default();
+        let start = Instant::now();
+        let world_size = 512.0 * 16.0;
+
+        // Drag the map 100 pixels to the left, then let go while it's still moving.
+        navigation.cursor_moved(&mut camera, (400.0, 300.0), start);
+        navigation.button(&mut camera, true, start);
+        for i in 1..=10 {
+            let now = start + Duration::from_millis(10 * i);
+            navigation.cursor_moved(&mut camera, (400.0 - 10.0 * i as f64, 300.0), now);
+        }
+        navigation.button(&mut camera, false, start + Duration::from_millis(110));
+        assert!((camera.x - (0.5 + 100.0 / world_size)).abs() < 1e-12);
+        assert_eq!(camera.y, 0.5);
+
+        // It keeps going the same way, slowing down until it stops.
+        let x = camera.x;
+        navigation.update(&mut camera, 0.1);
+        let glided = camera.x - x;
+        assert!(glided > 0.0);
+        navigation.update(&mut camera, 0.1);
+        assert!(camera.x - x - glided < glided);
+        for _ in 0..100 {
+            navigation.update(&mut camera, 0.1);
+        }
+        let x = camera.x;
+        navigation.update(&mut camera, 0.1);
+        assert_eq!(camera.x, x);
+
+        // Holding still before letting go doesn't fling it.
+        navigation.button(&mut camera, true, start);
+        navigation.cursor_moved(
+            &mut camera,
+            (450.0, 300.0),
+            start + Duration::from_millis(10),
+        );
+        navigation.button(&mut camera, false, start + Duration::from_millis(500));
+        let x = camera.x;
+        navigation.update(&mut camera, 0.1);
+        assert_eq!(camera.x, x);
+    }
+
+    #[test]
+    fn test_zoom() {
+        let mut camera = camera();
+        let mut navigation = Navigation::default();
+        let start = Instant::now();
+
+        // Scrolling with the cursor in the middle just zooms.
+        navigation.cursor_moved(&mut camera, (400.0, 300.0), start);
+        navigation.zoom(&mut camera, 0.5);
+        assert_eq!((camera.x, camera.y, camera.zoom), (0.5, 0.5, 4.5));
+
+        // A double-click zooms in on where it was, and a third click doesn't zoom again.
+        navigation.cursor_moved(&mut camera, (600.0, 300.0), start);
+        for ms in [0, 100, 200] {
+            let now = start + Duration::from_millis(ms);
+            navigation.button(&mut camera, true, now);
+            navigation.button(&mut camera, false, now);
+        }
+        assert_eq!(camera.zoom, 5.5);
+        assert!(camera.x > 0.5);
+
+        // Slow clicks aren't a double-click.
+        let later = start + Duration::from_secs(1);
+        navigation.button(&mut camera, true, later);
+        navigation.button(&mut camera, true, later + Duration::from_secs(1));
+        assert_eq!(camera.zoom, 5.5);
+
+        navigation.key(&mut camera, Key::ZoomOut);
+        navigation.key(&mut camera, Key::ZoomOut);
+        assert_eq!(camera.zoom, 3.5);
+        for _ in 0..10 {
+            navigation.key(&mut camera, Key::ZoomOut);
+        }
+        assert_eq!(camera.zoom, MIN_ZOOM);
+
+        // Pinching out hard enough to shrink the map to nothing only goes so far.
+        navigation.pinch(&mut camera, 1.0);
+        assert_eq!(camera.zoom, 1.0);
+        navigation.pinch(&mut camera, -1.0);
+        assert_eq!(camera.zoom, MIN_ZOOM);
+        camera.zoom = 10.0;
+        navigation.pinch(&mut camera, -3.0);
+        assert!((camera.zoom - (10.0 + MIN_PINCH_SCALE.log2())).abs() < 1e-9);
+
+        let y = camera.y;
+        navigation.key(&mut camera, Key::Up);
+        assert!(camera.y < y);
+    }
+}
@@ -1,21 +1,228 @@
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, Bytes};
 use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
 
+use crate::range_reader::{self, MmapReader, RangeReader, SourceChanged};
+use crate::tile_cache::CacheStats;
+use crate::tile_source::{TileSetInfo, TileSource};
+
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
-use std::io::Error;
-use std::io::Read;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
 use std::str;
-use std::str::Utf8Error;
+use std::str::{FromStr, Utf8Error};
+use std::sync::{Arc, Mutex, RwLock};
 
 static EXPECTED_MAGIC: &str = "PMTiles";
 const EXPECTED_VERSION: u8 = 3;
 
-pub fn tile_to_mvt_reader(header: &Header, tile: &TileEntry, file: &Vec<u8>) -> mvt_reader::Reader {
-    let tile_data_start = (header.tile_data_offset + tile.offset) as usize;
-    let tile_data_end = tile_data_start + tile.length as usize;
-    let tile_data_bytes = decompress_range(file, tile_data_start, tile_data_end).unwrap();
+pub const HEADER_LENGTH: usize = 127;
+
+// Writers keep the header and root directory within the first 16 KiB, so that clients can read
+// both with a single request.
+pub const INITIAL_FETCH_LENGTH: u64 = 16384;
+
+// How many decoded leaf directories a reader keeps. Each covers a few thousand tiles, which is
+// plenty for panning around one area.
+const LEAF_CACHE_CAPACITY: usize = 64;
+
+// An opened archive. Holds onto the header, metadata and root directory so that looking up a tile
+// only has to read leaf directories and tile data from the underlying source.
+pub struct PmTilesReader<R: RangeReader = Box<dyn RangeReader>> {
+    source: R,
+    // Replaced wholesale if the source reports the archive changed underneath us.
+    archive: RwLock<Archive>,
+}
+
+#[derive(Clone)]
+struct Archive {
+    header: Arc<Header>,
+    metadata: Arc<Metadata>,
+    root_directory: Arc<TileEntries>,
+    // Belongs to the archive rather than the reader, so leaves from a stale archive go with it.
+    leaf_directories: Arc<DirectoryCache>,
+}
+
+impl PmTilesReader {
+    // Opens a local archive or, for http(s) URLs, a remote one.
+    pub fn open(path: &str) -> Result<Self, ParseError> {
+        Self::new(range_reader::open(path)?)
+    }
+}
+
+impl PmTilesReader<MmapReader> {
+    pub fn open_mmap(path: impl AsRef<Path>) -> Result<Self, ParseError> {
+        Self::new(MmapReader::open(path)?)
+    }
+}
+
+impl<R: RangeReader> PmTilesReader<R> {
+    pub fn new(source: R) -> Result<Self, ParseError> {
+        let archive = read_archive(&source)?;
+
+        Ok(PmTilesReader {
+            source,
+            archive: RwLock::new(archive),
+        })
+    }
+
+    pub fn header(&self) -> Arc<Header> {
+        self.archive.read().unwrap().header.clone()
+    }
+
+    pub fn metadata(&self) -> Arc<Metadata> {
+        self.archive.read().unwrap().metadata.clone()
+    }
+
+    pub fn leaf_cache_stats(&self) -> CacheStats {
+        self.archive.read().unwrap().leaf_directories.stats()
+    }
+
+    // Returns the decompressed tile, or None if the archive has no tile at these coordinates.
+    pub fn get_tile(&self, coord: TileCoord) -> Result<Option<Bytes>, ParseError> {
+        self.fetch_tile(coord, true)
+    }
+
+    // Returns the tile as it is stored, ie. still compressed with the header's tile compression.
+    pub fn get_tile_data(&self, coord: TileCoord) -> Result<Option<Bytes>, ParseError> {
+        self.fetch_tile(coord, false)
+    }
+
+    fn fetch_tile(&self, coord: TileCoord, decompress: bool) -> Result<Option<Bytes>, ParseError> {
+        let tile_id = TileId::try_from(coord)?;
+        let archive = self.archive.read().unwrap().clone();
+
+        match self.read_tile(&archive, tile_id, decompress) {
+            Err(ParseError::ArchiveChanged) => {
+                // Everything we know about the old archive is stale, so start again from the
+                // header. If it changes yet again, give up.
+                self.source.invalidate();
+                let archive = read_archive(&self.source)?;
+                *self.archive.write().unwrap() = archive.clone();
+
+                self.read_tile(&archive, tile_id, decompress)
+            }
+            result => result,
+        }
+    }
 
-    mvt_reader::Reader::new(tile_data_bytes).unwrap()
+    fn read_tile(
+        &self,
+        archive: &Archive,
+        tile_id: TileId,
+        decompress: bool,
+    ) -> Result<Option<Bytes>, ParseError> {
+        let header = &archive.header;
+        let Some(tile) = archive.root_directory.find_tile(
+            tile_id,
+            &self.source,
+            header,
+            &archive.leaf_directories,
+        )?
+        else {
+            return Ok(None);
+        };
+
+        let data = read_entry_data(&self.source, header, &tile)?;
+        if !decompress {
+            return Ok(Some(data));
+        }
+
+        let tile_data_bytes = header.tile_compression.decompress(&data)?;
+
+        Ok(Some(Bytes::from(tile_data_bytes)))
+    }
+
+    // Every tile entry in the archive in tile id order, with leaf directories expanded.
+    pub fn tile_entries(&self) -> Result<Vec<TileEntry>, ParseError> {
+        let archive = self.archive.read().unwrap().clone();
+
+        let mut entries = Vec::new();
+        archive.root_directory.collect_tile_entries(
+            &self.source,
+            &archive.header,
+            0,
+            &mut entries,
+        )?;
+
+        Ok(entries)
+    }
+
+    // Reads the tile an entry from `tile_entries` points to, as it is stored.
+    pub fn get_entry_data(&self, entry: &TileEntry) -> Result<Bytes, ParseError> {
+        read_entry_data(&self.source, &self.header(), entry)
+    }
+}
+
+impl<R: RangeReader> TileSource for PmTilesReader<R> {
+    fn info(&self) -> TileSetInfo {
+        let header = self.header();
+
+        TileSetInfo {
+            tile_type: header.tile_type,
+            min_zoom: header.min_zoom,
+            max_zoom: header.max_zoom,
+            min_position: header.min_position,
+            max_position: header.max_position,
+            center_zoom: header.center_zoom,
+            center_position: header.center_position,
+            internal_compression: Some(header.internal_compression),
+        }
+    }
+
+    fn metadata(&self) -> Arc<Metadata> {
+        PmTilesReader::metadata(self)
+    }
+
+    fn get_tile_data(&self, coord: TileCoord) -> Result<Option<(Bytes, Compression)>, ParseError> {
+        let compression = self.header().tile_compression;
+
+        Ok(PmTilesReader::get_tile_data(self, coord)?.map(|data| (data, compression)))
+    }
+
+    fn get_tile(&self, coord: TileCoord) -> Result<Option<Bytes>, ParseError> {
+        PmTilesReader::get_tile(self, coord)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.leaf_cache_stats())
+    }
+}
+
+fn read_archive(source: &dyn RangeReader) -> Result<Archive, ParseError> {
+    let initial = source.read_range(0, INITIAL_FETCH_LENGTH)?;
+    let header = parse_header(&mut initial.clone())?;
+
+    let prefetched = Prefetched { initial, source };
+    let metadata = parse_metadata(&prefetched, &header)?;
+    let root_directory = parse_root_directory(&prefetched, &header)?;
+
+    Ok(Archive {
+        header: Arc::new(header),
+        metadata: Arc::new(metadata),
+        root_directory: Arc::new(root_directory),
+        leaf_directories: Arc::new(DirectoryCache::new(LEAF_CACHE_CAPACITY)),
+    })
+}
+
+// Serves ranges out of the initial fetch where possible, only going back to the source for
+// sections which lie beyond it.
+struct Prefetched<'a> {
+    initial: Bytes,
+    source: &'a dyn RangeReader,
+}
+
+impl RangeReader for Prefetched<'_> {
+    fn read_range(&self, offset: u64, length: u64) -> std::io::Result<Bytes> {
+        if offset.saturating_add(length) <= self.initial.len() as u64 {
+            return self.initial.read_range(offset, length);
+        }
+
+        self.source.read_range(offset, length)
+    }
 }
 
 #[derive(Debug)]
@@ -26,59 +233,192 @@ pub enum ParseError {
     InvalidValue,
     IoError(std::io::Error),
     VarintOverflowError,
+    UnexpectedEof,
+    MalformedDirectory(&'static str),
+    UnknownCompression,
+    InvalidMetadata(serde_json::Error),
+    InvalidTile(mvt_reader::error::ParserError),
+    InvalidGeoJson(String),
+    InvalidStyle(String),
+    MbTiles(rusqlite::Error),
+    ArchiveChanged,
     TooHighZIndex,
+    TileOutOfRange(TileCoord),
 }
 
 impl From<std::io::Error> for ParseError {
     fn from(value: std::io::Error) -> Self {
+        if value.get_ref().is_some_and(|e| e.is::<SourceChanged>()) {
+            return ParseError::ArchiveChanged;
+        }
+
         ParseError::IoError(value)
     }
 }
 
-// TODO: make private
-pub fn decompress_range(file: &Vec<u8>, start: usize, end: usize) -> Result<Vec<u8>, Error> {
-    let compressed_bytes = &file[start..end];
+impl From<rusqlite::Error> for ParseError {
+    fn from(value: rusqlite::Error) -> Self {
+        ParseError::MbTiles(value)
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::InvalidMagic => write!(f, "not a PMTiles archive (bad magic)"),
+            ParseError::InvalidVersion => write!(f, "unsupported PMTiles version"),
+            ParseError::InvalidUtf8(e) => write!(f, "invalid utf-8: {e}"),
+            ParseError::InvalidValue => write!(f, "invalid enum value in header"),
+            ParseError::IoError(e) => write!(f, "io error: {e}"),
+            ParseError::VarintOverflowError => write!(f, "varint does not fit in a u64"),
+            ParseError::UnexpectedEof => write!(f, "unexpected end of data"),
+            ParseError::MalformedDirectory(reason) => write!(f, "malformed directory: {reason}"),
+            ParseError::UnknownCompression => write!(f, "unknown compression"),
+            ParseError::InvalidMetadata(e) => write!(f, "invalid metadata: {e}"),
+            ParseError::InvalidTile(e) => write!(f, "invalid vector tile: {e}"),
+            ParseError::InvalidGeoJson(reason) => write!(f, "invalid GeoJSON: {reason}"),
+            ParseError::InvalidStyle(reason) => write!(f, "invalid style: {reason}"),
+            ParseError::MbTiles(e) => write!(f, "MBTiles error: {e}"),
+            ParseError::ArchiveChanged => write!(f, "archive changed while it was being read"),
+            ParseError::TooHighZIndex => write!(f, "zoom level too high"),
+            ParseError::TileOutOfRange(coord) => {
+                write!(f, "{coord} is outside of zoom level {}", coord.z)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn read_entry_data(
+    source: &dyn RangeReader,
+    header: &Header,
+    entry: &TileEntry,
+) -> Result<Bytes, ParseError> {
+    if entry.offset.saturating_add(entry.length) > header.tile_data_length {
+        return Err(ParseError::MalformedDirectory(
+            "tile outside of tile data section",
+        ));
+    }
+
+    let offset = section_offset(header.tile_data_offset, entry.offset)?;
+    read_exact_range(source, offset, entry.length)
+}
+
+// Section offsets come from the header and entry offsets from directories, and a corrupt archive
+// can make them add up to more than fits in a u64.
+pub fn section_offset(section: u64, offset: u64) -> Result<u64, ParseError> {
+    section
+        .checked_add(offset)
+        .ok_or(ParseError::MalformedDirectory("offset overflow"))
+}
 
-    let mut gz = GzDecoder::new(compressed_bytes);
-    let mut bytes: Vec<u8> = Vec::new();
-    gz.read_to_end(&mut bytes)?;
+fn read_exact_range(
+    source: &dyn RangeReader,
+    offset: u64,
+    length: u64,
+) -> Result<Bytes, ParseError> {
+    let bytes = source.read_range(offset, length)?;
+    if (bytes.len() as u64) < length {
+        return Err(ParseError::UnexpectedEof);
+    }
 
     Ok(bytes)
 }
 
-pub fn parse_root_directory(file: &Vec<u8>, header: &Header) -> Result<TileEntries, ParseError> {
-    let root_directory_start = header.root_directory_offset as usize;
-    let root_directory_end = root_directory_start + header.root_directory_length as usize;
-    let root_directory_bytes = decompress_range(&file, root_directory_start, root_directory_end)?;
+fn decompress_range(
+    source: &dyn RangeReader,
+    offset: u64,
+    length: u64,
+    compression: Compression,
+) -> Result<Vec<u8>, ParseError> {
+    let compressed_bytes = read_exact_range(source, offset, length)?;
+
+    compression.decompress(&compressed_bytes)
+}
+
+pub fn parse_root_directory(
+    source: &dyn RangeReader,
+    header: &Header,
+) -> Result<TileEntries, ParseError> {
+    let root_directory_bytes = decompress_range(
+        source,
+        header.root_directory_offset,
+        header.root_directory_length,
+        header.internal_compression,
+    )?;
+
+    parse_directory(Bytes::from(root_directory_bytes))
+}
 
-    let mut bytes = Bytes::from(root_directory_bytes);
+pub fn parse_metadata(source: &dyn RangeReader, header: &Header) -> Result<Metadata, ParseError> {
+    if header.metadata_length == 0 {
+        return Ok(Metadata::default());
+    }
+
+    let metadata_bytes = decompress_range(
+        source,
+        header.metadata_offset,
+        header.metadata_length,
+        header.internal_compression,
+    )?;
+
+    serde_json::from_slice(&metadata_bytes).map_err(ParseError::InvalidMetadata)
+}
+
+// Leaf entries point into the leaf directories section, with offsets relative to its start.
+pub fn parse_leaf_directory(
+    source: &dyn RangeReader,
+    header: &Header,
+    leaf: &TileEntry,
+) -> Result<TileEntries, ParseError> {
+    if leaf.offset.saturating_add(leaf.length) > header.leaf_directories_length {
+        return Err(ParseError::MalformedDirectory(
+            "leaf directory outside of leaf directories section",
+        ));
+    }
 
+    let leaf_directory_bytes = decompress_range(
+        source,
+        section_offset(header.leaf_directories_offset, leaf.offset)?,
+        leaf.length,
+        header.internal_compression,
+    )?;
+
+    parse_directory(Bytes::from(leaf_directory_bytes))
+}
+
+pub fn parse_directory(mut bytes: Bytes) -> Result<TileEntries, ParseError> {
     let tile_num = parse_varint(&mut bytes)?;
 
+    // Every entry takes at least four bytes, which guards against huge allocations.
+    if tile_num > bytes.remaining() as u64 / 4 {
+        return Err(ParseError::MalformedDirectory(
+            "entry count exceeds directory size",
+        ));
+    }
+
     let mut tile_entries = vec![TileEntry::default(); tile_num as usize];
 
-    let mut last_id = 0;
+    let mut last_id: u64 = 0;
     for tile in tile_entries.iter_mut() {
         let id_delta = parse_varint(&mut bytes)?;
-        last_id = last_id + id_delta;
+        last_id = last_id
+            .checked_add(id_delta)
+            .ok_or(ParseError::MalformedDirectory("tile id overflow"))?;
 
         tile.id = last_id;
     }
 
     for tile in tile_entries.iter_mut() {
-        let run_length = parse_varint(&mut bytes)?;
-
-        // FIXME
-        assert!(
-            run_length != 0,
-            "Run length 0 indicates a leaf entry, which is not implemented"
-        );
-
-        tile.run_length = run_length;
+        tile.run_length = parse_varint(&mut bytes)?;
     }
 
     for tile in tile_entries.iter_mut() {
         let length = parse_varint(&mut bytes)?;
+        if length == 0 {
+            return Err(ParseError::MalformedDirectory("entry with zero length"));
+        }
 
         tile.length = length;
     }
@@ -90,7 +430,9 @@ pub fn parse_root_directory(file: &Vec<u8>, header: &Header) -> Result<TileEntri
         let value = parse_varint(&mut bytes)?;
 
         if value == 0 && i > 0 {
-            tile.offset = last_offset + last_len;
+            tile.offset = section_offset(last_offset, last_len)?;
+        } else if value == 0 {
+            return Err(ParseError::MalformedDirectory("first entry has no offset"));
         } else {
             tile.offset = value - 1;
         }
@@ -104,35 +446,107 @@ pub fn parse_root_directory(file: &Vec<u8>, header: &Header) -> Result<TileEntri
     })
 }
 
+// The JSON metadata section. Everything is optional, and keys we don't know about are kept in
+// `extra` so they survive being written back out.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribution: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vector_layers: Vec<VectorLayer>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tilestats: Option<serde_json::Value>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Metadata {
+    pub fn layer_ids(&self) -> Vec<String> {
+        self.vector_layers.iter().map(|l| l.id.clone()).collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct VectorLayer {
+    pub id: String,
+    // Field name to type, eg. "kind" => "String".
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minzoom: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxzoom: Option<u8>,
+}
+
 // PMTiles V3 Header.
 #[derive(Debug)]
 pub struct Header {
-    root_directory_offset: u64,
-    root_directory_length: u64,
-    metadata_offset: u64,
-    metadata_length: u64,
-    leaf_directories_offset: u64,
-    leaf_directories_length: u64,
+    pub root_directory_offset: u64,
+    pub root_directory_length: u64,
+    pub metadata_offset: u64,
+    pub metadata_length: u64,
+    pub leaf_directories_offset: u64,
+    pub leaf_directories_length: u64,
     pub tile_data_offset: u64,
     pub tile_data_length: u64,
-    number_of_addressed_tiles: u64,
-    number_of_tile_entires: u64,
-    number_of_tile_contents: u64,
-    clustered: Clustered,
-    internal_compression: Compression,
-    tile_compression: Compression,
-    tile_type: TileType,
-    min_zoom: u8,
-    max_zoom: u8,
-    min_position: Position,
-    max_position: Position,
+    pub number_of_addressed_tiles: u64,
+    pub number_of_tile_entires: u64,
+    pub number_of_tile_contents: u64,
+    pub clustered: Clustered,
+    pub internal_compression: Compression,
+    pub tile_compression: Compression,
+    pub tile_type: TileType,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    pub min_position: Position,
+    pub max_position: Position,
     pub center_zoom: u8,
     pub center_position: Position,
 }
 
+pub fn serialize_header(header: &Header) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LENGTH);
+
+    bytes.put_slice(EXPECTED_MAGIC.as_bytes());
+    bytes.put_u8(EXPECTED_VERSION);
+    bytes.put_u64_le(header.root_directory_offset);
+    bytes.put_u64_le(header.root_directory_length);
+    bytes.put_u64_le(header.metadata_offset);
+    bytes.put_u64_le(header.metadata_length);
+    bytes.put_u64_le(header.leaf_directories_offset);
+    bytes.put_u64_le(header.leaf_directories_length);
+    bytes.put_u64_le(header.tile_data_offset);
+    bytes.put_u64_le(header.tile_data_length);
+    bytes.put_u64_le(header.number_of_addressed_tiles);
+    bytes.put_u64_le(header.number_of_tile_entires);
+    bytes.put_u64_le(header.number_of_tile_contents);
+    bytes.put_u8(header.clustered.into());
+    bytes.put_u8(header.internal_compression.into());
+    bytes.put_u8(header.tile_compression.into());
+    bytes.put_u8(header.tile_type.into());
+    bytes.put_u8(header.min_zoom);
+    bytes.put_u8(header.max_zoom);
+    bytes.put_u64_le((&header.min_position).into());
+    bytes.put_u64_le((&header.max_position).into());
+    bytes.put_u8(header.center_zoom);
+    bytes.put_u64_le((&header.center_position).into());
+
+    bytes
+}
+
 pub fn parse_header(bytes: &mut Bytes) -> Result<Header, ParseError> {
+    if bytes.len() < HEADER_LENGTH {
+        return Err(ParseError::UnexpectedEof);
+    }
+
     let magic = bytes.split_to(EXPECTED_MAGIC.len()).to_vec();
-    let magic = str::from_utf8(&magic).unwrap();
+    let magic = str::from_utf8(&magic).map_err(ParseError::InvalidUtf8)?;
 
     if magic != EXPECTED_MAGIC {
         return Err(ParseError::InvalidMagic);
@@ -170,12 +584,21 @@ pub fn parse_header(bytes: &mut Bytes) -> Result<Header, ParseError> {
     Ok(header)
 }
 
-#[derive(Debug)]
-enum Clustered {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clustered {
     NotClustered,
     Clustered,
 }
 
+impl From<Clustered> for u8 {
+    fn from(value: Clustered) -> Self {
+        match value {
+            Clustered::NotClustered => 0,
+            Clustered::Clustered => 1,
+        }
+    }
+}
+
 impl TryFrom<u8> for Clustered {
     type Error = ParseError;
     fn try_from(value: u8) -> Result<Self, Self::Error> {
@@ -187,14 +610,42 @@ impl TryFrom<u8> for Clustered {
     }
 }
 
-#[derive(Debug)]
-enum TileType {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileType {
     Unknown,
-    MVT,
-    PNG,
-    JPEG,
+    Mvt,
+    Png,
+    Jpeg,
     WebP,
-    AVIF,
+    Avif,
+}
+
+impl std::fmt::Display for TileType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TileType::Unknown => "unknown",
+            TileType::Mvt => "mvt",
+            TileType::Png => "png",
+            TileType::Jpeg => "jpeg",
+            TileType::WebP => "webp",
+            TileType::Avif => "avif",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl From<TileType> for u8 {
+    fn from(value: TileType) -> Self {
+        match value {
+            TileType::Unknown => 0,
+            TileType::Mvt => 1,
+            TileType::Png => 2,
+            TileType::Jpeg => 3,
+            TileType::WebP => 4,
+            TileType::Avif => 5,
+        }
+    }
 }
 
 impl TryFrom<u8> for TileType {
@@ -202,18 +653,18 @@ impl TryFrom<u8> for TileType {
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
             0 => Ok(Self::Unknown),
-            1 => Ok(Self::MVT),
-            2 => Ok(Self::PNG),
-            3 => Ok(Self::JPEG),
+            1 => Ok(Self::Mvt),
+            2 => Ok(Self::Png),
+            3 => Ok(Self::Jpeg),
             4 => Ok(Self::WebP),
-            5 => Ok(Self::AVIF),
+            5 => Ok(Self::Avif),
             _ => Err(ParseError::InvalidValue),
         }
     }
 }
 
-#[derive(Debug)]
-enum Compression {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
     Unknown,
     None,
     GZip,
@@ -221,6 +672,76 @@ enum Compression {
     ZStd,
 }
 
+impl Compression {
+    // Used for directories and metadata (internal compression) as well as tile payloads.
+    pub fn decompress(self, compressed: &[u8]) -> Result<Vec<u8>, ParseError> {
+        let mut bytes: Vec<u8> = Vec::new();
+
+        match self {
+            Compression::Unknown => return Err(ParseError::UnknownCompression),
+            Compression::None => bytes.extend_from_slice(compressed),
+            Compression::GZip => {
+                GzDecoder::new(compressed).read_to_end(&mut bytes)?;
+            }
+            Compression::Brotli => {
+                brotli::Decompressor::new(compressed, 4096).read_to_end(&mut bytes)?;
+            }
+            Compression::ZStd => {
+                zstd::stream::Decoder::new(compressed)?.read_to_end(&mut bytes)?;
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, ParseError> {
+        match self {
+            Compression::Unknown => Err(ParseError::UnknownCompression),
+            Compression::None => Ok(data.to_vec()),
+            Compression::GZip => {
+                let mut gz = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                gz.write_all(data)?;
+                Ok(gz.finish()?)
+            }
+            Compression::Brotli => {
+                let mut bytes = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut bytes, 4096, 11, 22);
+                    writer.write_all(data)?;
+                }
+                Ok(bytes)
+            }
+            Compression::ZStd => Ok(zstd::stream::encode_all(data, 0)?),
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Compression::Unknown => "unknown",
+            Compression::None => "none",
+            Compression::GZip => "gzip",
+            Compression::Brotli => "brotli",
+            Compression::ZStd => "zstd",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl From<Compression> for u8 {
+    fn from(value: Compression) -> Self {
+        match value {
+            Compression::Unknown => 0x0,
+            Compression::None => 0x1,
+            Compression::GZip => 0x2,
+            Compression::Brotli => 0x3,
+            Compression::ZStd => 0x4,
+        }
+    }
+}
+
 impl TryFrom<u8> for Compression {
     type Error = ParseError;
     fn try_from(value: u8) -> Result<Self, Self::Error> {
@@ -247,18 +768,167 @@ pub struct TileEntries {
     pub entries: Vec<TileEntry>,
 }
 
+// The spec allows at most three levels of directories below the root.
+pub const MAX_DIRECTORY_DEPTH: u8 = 3;
+
 impl TileEntries {
-    pub fn find_tile(&self, id: TileId) -> Option<&TileEntry> {
-        self.entries.iter().find(|e| e.id == id.0)
+    pub fn find_tile(
+        &self,
+        id: TileId,
+        source: &dyn RangeReader,
+        header: &Header,
+        leaves: &DirectoryCache,
+    ) -> Result<Option<TileEntry>, ParseError> {
+        self.find_tile_at_depth(id, source, header, leaves, 0)
+    }
+
+    fn find_tile_at_depth(
+        &self,
+        id: TileId,
+        source: &dyn RangeReader,
+        header: &Header,
+        leaves: &DirectoryCache,
+        depth: u8,
+    ) -> Result<Option<TileEntry>, ParseError> {
+        // Entries are sorted by id, so the closest entry at or before the id is either a run of
+        // tiles which may contain it, or the leaf directory which covers it.
+        let index = self.entries.partition_point(|e| e.id <= id.0);
+        let Some(entry) = index.checked_sub(1).map(|i| &self.entries[i]) else {
+            return Ok(None);
+        };
+
+        if entry.run_length > 0 {
+            let in_run = id.0 - entry.id < entry.run_length;
+            return Ok(in_run.then(|| entry.clone()));
+        }
+
+        if depth >= MAX_DIRECTORY_DEPTH {
+            return Err(ParseError::MalformedDirectory(
+                "leaf directories nested too deeply",
+            ));
+        }
+
+        let leaf = match leaves.get(entry.offset) {
+            Some(leaf) => leaf,
+            None => {
+                let leaf = Arc::new(parse_leaf_directory(source, header, entry)?);
+                leaves.insert(entry.offset, leaf.clone());
+                leaf
+            }
+        };
+        leaf.find_tile_at_depth(id, source, header, leaves, depth + 1)
+    }
+
+    // Reads each leaf directory once, so doesn't bother with the cache.
+    fn collect_tile_entries(
+        &self,
+        source: &dyn RangeReader,
+        header: &Header,
+        depth: u8,
+        out: &mut Vec<TileEntry>,
+    ) -> Result<(), ParseError> {
+        for entry in &self.entries {
+            if entry.run_length > 0 {
+                out.push(entry.clone());
+                continue;
+            }
+
+            if depth >= MAX_DIRECTORY_DEPTH {
+                return Err(ParseError::MalformedDirectory(
+                    "leaf directories nested too deeply",
+                ));
+            }
+
+            let leaf = parse_leaf_directory(source, header, entry)?;
+            leaf.collect_tile_entries(source, header, depth + 1, out)?;
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+// Decoded leaf directories keyed by their offset in the leaf directories section, so that looking
+// up neighbouring tiles doesn't decompress and parse the same leaf each time. The least recently
+// used leaf is dropped once it's full. It only holds a handful, so a scan to find that is cheap.
+pub struct DirectoryCache {
+    capacity: usize,
+    inner: Mutex<DirectoryCacheInner>,
+}
+
+#[derive(Default)]
+struct DirectoryCacheInner {
+    leaves: HashMap<u64, (Arc<TileEntries>, u64)>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl DirectoryCache {
+    pub fn new(capacity: usize) -> Self {
+        DirectoryCache {
+            capacity,
+            inner: Mutex::new(DirectoryCacheInner::default()),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.inner.lock().unwrap().stats
+    }
+
+    fn get(&self, offset: u64) -> Option<Arc<TileEntries>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        match inner.leaves.get_mut(&offset) {
+            Some((leaf, last_used)) => {
+                *last_used = clock;
+                let leaf = leaf.clone();
+                inner.stats.hits += 1;
+                Some(leaf)
+            }
+            None => {
+                inner.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&self, offset: u64, leaf: Arc<TileEntries>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        if !inner.leaves.contains_key(&offset) && inner.leaves.len() >= self.capacity {
+            let oldest = inner
+                .leaves
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(offset, _)| *offset);
+            if let Some(oldest) = oldest {
+                inner.leaves.remove(&oldest);
+                inner.stats.evictions += 1;
+            }
+        }
+
+        inner.leaves.insert(offset, (leaf, clock));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Position {
     pub lat: f64,
     pub long: f64,
 }
 
+impl From<&Position> for u64 {
+    fn from(value: &Position) -> Self {
+        let long = (value.long * 10_000_000.0).round() as i32;
+        let lat = (value.lat * 10_000_000.0).round() as i32;
+
+        ((lat as u32 as u64) << 32) | (long as u32 as u64)
+    }
+}
+
 impl From<u64> for Position {
     fn from(value: u64) -> Self {
         let long = (value & 0xFFFF_FFFF) as i32;
@@ -273,10 +943,22 @@ impl From<u64> for Position {
 
 const VARINT_CONTINUATION_BIT_MASK: u8 = 0b10000000;
 
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= VARINT_CONTINUATION_BIT_MASK as u64 {
+        out.push((n as u8) | VARINT_CONTINUATION_BIT_MASK);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
 fn parse_varint(bytes: &mut bytes::Bytes) -> Result<u64, ParseError> {
     let mut n: u64 = 0;
 
     for i in 0.. {
+        if !bytes.has_remaining() {
+            return Err(ParseError::UnexpectedEof);
+        }
+
         let byte = bytes.get_u8();
         let value = (byte & !VARINT_CONTINUATION_BIT_MASK) as u64;
         n |= value
@@ -291,13 +973,47 @@ fn parse_varint(bytes: &mut bytes::Bytes) -> Result<u64, ParseError> {
     Ok(n)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TileCoord {
-    x: u32,
-    y: u32,
-    z: u8,
+    pub x: u32,
+    pub y: u32,
+    pub z: u8,
 }
 
-pub struct TileId(u64);
+impl std::fmt::Display for TileCoord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.z, self.x, self.y)
+    }
+}
+
+// Parses the usual "z/x/y" form.
+impl FromStr for TileCoord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('/').collect();
+        let [z, x, y] = parts[..] else {
+            return Err(format!("expected z/x/y, got {s:?}"));
+        };
+
+        let z: u8 = z.parse().map_err(|_| format!("invalid zoom {z:?}"))?;
+        let x: u32 = x.parse().map_err(|_| format!("invalid x {x:?}"))?;
+        let y: u32 = y.parse().map_err(|_| format!("invalid y {y:?}"))?;
+
+        if z >= MAX_Z {
+            return Err(format!("zoom {z} is too high"));
+        }
+        let tiles_across = 1u64 << z;
+        if u64::from(x) >= tiles_across || u64::from(y) >= tiles_across {
+            return Err(format!("{z}/{x}/{y} is outside of zoom level {z}"));
+        }
+
+        Ok(TileCoord { x, y, z })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileId(pub u64);
 
 impl TryFrom<TileCoord> for TileId {
     type Error = ParseError;
@@ -305,15 +1021,20 @@ impl TryFrom<TileCoord> for TileId {
     // implementation stolen/inspired by https://github.com/arma-place/pmtiles-rs, under MIT license
     fn try_from(value: TileCoord) -> Result<Self, Self::Error> {
         let TileCoord { x, y, z } = value;
-        if z > MAX_Z {
+        if z >= MAX_Z {
             return Err(ParseError::TooHighZIndex);
         }
+        // Past the edge, the Hilbert curve carries on into ids that belong to deeper zoom levels.
+        let tiles_across = 1u64 << z;
+        if u64::from(x) >= tiles_across || u64::from(y) >= tiles_across {
+            return Err(ParseError::TileOutOfRange(value));
+        }
 
         // FIXME: precompute this
-        let base_id: u64 = 1 + (1..z).map(|i| 4u64.pow(u32::from(i))).sum::<u64>();
+        // The number of tiles in all the zoom levels above this one.
+        let base_id: u64 = (0..z).map(|i| 4u64.pow(u32::from(i))).sum::<u64>();
 
-        // FIXME: should x, y just be u32?
-        let id = TileId(fast_hilbert::xy2h(x as u32, y as u32, z) + base_id);
+        let id = TileId(fast_hilbert::xy2h(x, y, z) + base_id);
 
         Ok(id)
     }
@@ -332,7 +1053,7 @@ impl TryFrom<TileId> for TileCoord {
 
         let z = find_z(id.0)?;
 
-        let base_id: u64 = 1 + (1..z).map(|i| 4u64.pow(u32::from(i))).sum::<u64>();
+        let base_id: u64 = (0..z).map(|i| 4u64.pow(u32::from(i))).sum::<u64>();
 
         let (x, y) = fast_hilbert::h2xy::<u32>(id.0 - base_id, z);
 
@@ -340,7 +1061,8 @@ impl TryFrom<TileId> for TileCoord {
     }
 }
 
-const MAX_Z: u8 = 32;
+// Tile ids only fit in a u64 up to zoom level 31.
+pub const MAX_Z: u8 = 32;
 
 fn find_z(id: u64) -> Result<u8, ParseError> {
     let mut z: u8 = 0;
@@ -363,34 +1085,477 @@ fn find_z(id: u64) -> Result<u8, ParseError> {
     Ok(z)
 }
 
-// From chatgpt
+// From chatgpt. The viewer works in fractional tiles now, so only tests want whole ones.
+#[cfg(test)]
 pub fn lat_lon_to_xyz(lat: f64, lon: f64, zoom: u8) -> TileCoord {
+    let (x, y) = fractional_lat_lon_to_xyz(lat, lon, zoom);
+
+    TileCoord {
+        x: x.floor() as u32,
+        y: y.floor() as u32,
+        z: zoom,
+    }
+}
+
+// Like lat_lon_to_xyz, but keeps where in the tile the point is.
+pub fn fractional_lat_lon_to_xyz(lat: f64, lon: f64, zoom: u8) -> (f64, f64) {
     let lat_rad = lat.to_radians();
     let n = 2f64.powi(zoom as i32);
 
-    let x = ((lon + 180.0) / 360.0 * n).floor() as u32;
-    let y = ((1.0 - (lat_rad.tan().asinh() / std::f64::consts::PI)) / 2.0 * n).floor() as u32;
+    let x = (lon + 180.0) / 360.0 * n;
+    let y = (1.0 - (lat_rad.tan().asinh() / std::f64::consts::PI)) / 2.0 * n;
 
-    TileCoord { x, y, z: zoom }
+    (x, y)
 }
 
 // From chatgpt
 pub fn xyz_to_lat_lon(x: u32, y: u32, zoom: u8) -> Position {
+    fractional_xyz_to_lat_lon(x as f64, y as f64, zoom)
+}
+
+// Like xyz_to_lat_lon, but for any point within a tile rather than just its corner.
+pub fn fractional_xyz_to_lat_lon(x: f64, y: f64, zoom: u8) -> Position {
     let n = 2f64.powi(zoom as i32);
-    let lon = x as f64 / n * 360.0 - 180.0;
+    let lon = x / n * 360.0 - 180.0;
 
-    let lat_rad = ((1.0 - 2.0 * (y as f64 / n)) * std::f64::consts::PI)
-        .sinh()
-        .atan();
+    let lat_rad = ((1.0 - 2.0 * (y / n)) * std::f64::consts::PI).sinh().atan();
     let lat = lat_rad.to_degrees();
 
     Position { lat, long: lon }
 }
 
+// Entries are written column by column, the inverse of parse_directory.
+fn serialize_directory(entries: &[TileEntry]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_varint(&mut bytes, entries.len() as u64);
+
+    let mut last_id = 0;
+    for entry in entries {
+        write_varint(&mut bytes, entry.id - last_id);
+        last_id = entry.id;
+    }
+
+    for entry in entries {
+        write_varint(&mut bytes, entry.run_length);
+    }
+
+    for entry in entries {
+        write_varint(&mut bytes, entry.length);
+    }
+
+    for (i, entry) in entries.iter().enumerate() {
+        // Zero means "straight after the previous entry", which is the common case for clustered
+        // archives and compresses very well.
+        let contiguous = i > 0 && {
+            let previous = &entries[i - 1];
+            entry.offset == previous.offset + previous.length
+        };
+
+        if contiguous {
+            write_varint(&mut bytes, 0);
+        } else {
+            write_varint(&mut bytes, entry.offset + 1);
+        }
+    }
+
+    bytes
+}
+
+const ROOT_DIRECTORY_BUDGET: usize = INITIAL_FETCH_LENGTH as usize - HEADER_LENGTH;
+
+// Returns the compressed root directory and leaf directories. Leaves are only used when the
+// entries don't fit into the root directory's share of the initial fetch, and then get bigger
+// until the root (which lists one entry per leaf) does fit.
+fn build_directories(
+    entries: &[TileEntry],
+    compression: Compression,
+) -> Result<(Vec<u8>, Vec<u8>), ParseError> {
+    let root = compression.compress(&serialize_directory(entries))?;
+    if root.len() <= ROOT_DIRECTORY_BUDGET {
+        return Ok((root, Vec::new()));
+    }
+
+    let mut leaf_size = 4096;
+    loop {
+        let mut leaves = Vec::new();
+        let mut root_entries = Vec::new();
+
+        for chunk in entries.chunks(leaf_size) {
+            let leaf = compression.compress(&serialize_directory(chunk))?;
+
+            root_entries.push(TileEntry {
+                id: chunk[0].id,
+                offset: leaves.len() as u64,
+                length: leaf.len() as u64,
+                run_length: 0,
+            });
+            leaves.extend_from_slice(&leaf);
+        }
+
+        let root = compression.compress(&serialize_directory(&root_entries))?;
+        if root.len() <= ROOT_DIRECTORY_BUDGET {
+            return Ok((root, leaves));
+        }
+
+        leaf_size *= 2;
+    }
+}
+
+// Builds an archive in memory and writes it out in one go. Tile data must already be compressed
+// with the tile compression given to `new`; identical tiles are only stored once.
+pub struct PmTilesWriter {
+    tile_type: TileType,
+    tile_compression: Compression,
+    internal_compression: Compression,
+    metadata: Metadata,
+    bounds: Option<(Position, Position)>,
+    center: Option<(u8, Position)>,
+    // Tile id to index into `contents`.
+    tiles: BTreeMap<u64, usize>,
+    contents: Vec<Bytes>,
+    content_index: HashMap<Bytes, usize>,
+}
+
+impl PmTilesWriter {
+    pub fn new(tile_type: TileType, tile_compression: Compression) -> Self {
+        PmTilesWriter {
+            tile_type,
+            tile_compression,
+            internal_compression: Compression::GZip,
+            metadata: Metadata::default(),
+            bounds: None,
+            center: None,
+            tiles: BTreeMap::new(),
+            contents: Vec::new(),
+            content_index: HashMap::new(),
+        }
+    }
+
+    // Directories and metadata are gzipped unless this says otherwise.
+    pub fn set_internal_compression(&mut self, compression: Compression) {
+        self.internal_compression = compression;
+    }
+
+    pub fn set_metadata(&mut self, metadata: Metadata) {
+        self.metadata = metadata;
+    }
+
+    // Defaults to the area covered by the tiles.
+    pub fn set_bounds(&mut self, min: Position, max: Position) {
+        self.bounds = Some((min, max));
+    }
+
+    // Defaults to the middle of the bounds, at the minimum zoom.
+    pub fn set_center(&mut self, zoom: u8, position: Position) {
+        self.center = Some((zoom, position));
+    }
+
+    // Adding a tile at the same coordinates twice replaces the first one.
+    pub fn add_tile(&mut self, coord: TileCoord, data: Bytes) -> Result<(), ParseError> {
+        let tile_id = TileId::try_from(coord)?;
+
+        let next_index = self.contents.len();
+        let index = *self.content_index.entry(data.clone()).or_insert(next_index);
+        if index == next_index {
+            self.contents.push(data);
+        }
+
+        self.tiles.insert(tile_id.0, index);
+
+        Ok(())
+    }
+
+    pub fn write_to(self, out: &mut impl Write) -> Result<(), ParseError> {
+        // Lay tile data out in tile id order, so the archive is clustered.
+        let mut content_offsets: Vec<Option<u64>> = vec![None; self.contents.len()];
+        let mut tile_data_order = Vec::new();
+        let mut tile_data_length = 0;
+
+        let mut entries: Vec<TileEntry> = Vec::new();
+        for (&id, &index) in &self.tiles {
+            // Consecutive ids with the same contents become a single run.
+            if let Some(last) = entries.last_mut()
+                && content_offsets[index] == Some(last.offset)
+                && last.id + last.run_length == id
+            {
+                last.run_length += 1;
+                continue;
+            }
+
+            let offset = *content_offsets[index].get_or_insert_with(|| {
+                tile_data_order.push(index);
+                tile_data_length += self.contents[index].len() as u64;
+                tile_data_length - self.contents[index].len() as u64
+            });
+
+            entries.push(TileEntry {
+                id,
+                offset,
+                length: self.contents[index].len() as u64,
+                run_length: 1,
+            });
+        }
+
+        let (root, leaves) = build_directories(&entries, self.internal_compression)?;
+        let metadata = serde_json::to_vec(&self.metadata).map_err(ParseError::InvalidMetadata)?;
+        let metadata = self.internal_compression.compress(&metadata)?;
+
+        let coords: Vec<TileCoord> = self
+            .tiles
+            .keys()
+            .map(|&id| TileCoord::try_from(TileId(id)))
+            .collect::<Result<_, _>>()?;
+        let min_zoom = coords.iter().map(|c| c.z).min().unwrap_or(0);
+        let max_zoom = coords.iter().map(|c| c.z).max().unwrap_or(0);
+
+        let (min_position, max_position) = self.bounds.unwrap_or_else(|| tile_bounds(&coords));
+        let (center_zoom, center_position) = self.center.unwrap_or_else(|| {
+            let center = Position {
+                lat: (min_position.lat + max_position.lat) / 2.0,
+                long: (min_position.long + max_position.long) / 2.0,
+            };
+            (min_zoom, center)
+        });
+
+        let root_directory_offset = HEADER_LENGTH as u64;
+        let metadata_offset = root_directory_offset + root.len() as u64;
+        let leaf_directories_offset = metadata_offset + metadata.len() as u64;
+        let tile_data_offset = leaf_directories_offset + leaves.len() as u64;
+
+        let header = Header {
+            root_directory_offset,
+            root_directory_length: root.len() as u64,
+            metadata_offset,
+            metadata_length: metadata.len() as u64,
+            leaf_directories_offset,
+            leaf_directories_length: leaves.len() as u64,
+            tile_data_offset,
+            tile_data_length,
+            number_of_addressed_tiles: self.tiles.len() as u64,
+            number_of_tile_entires: entries.len() as u64,
+            number_of_tile_contents: tile_data_order.len() as u64,
+            clustered: Clustered::Clustered,
+            internal_compression: self.internal_compression,
+            tile_compression: self.tile_compression,
+            tile_type: self.tile_type,
+            min_zoom,
+            max_zoom,
+            min_position,
+            max_position,
+            center_zoom,
+            center_position,
+        };
+
+        out.write_all(&serialize_header(&header))?;
+        out.write_all(&root)?;
+        out.write_all(&metadata)?;
+        out.write_all(&leaves)?;
+        for index in tile_data_order {
+            out.write_all(&self.contents[index])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_file(self, path: impl AsRef<Path>) -> Result<(), ParseError> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_to(&mut out)?;
+        out.flush()?;
+
+        Ok(())
+    }
+}
+
+// The area covered by a set of tiles, as (south west, north east).
+pub fn tile_bounds(coords: &[TileCoord]) -> (Position, Position) {
+    if coords.is_empty() {
+        return (Position::from(0), Position::from(0));
+    }
+
+    let mut min = Position {
+        lat: f64::MAX,
+        long: f64::MAX,
+    };
+    let mut max = Position {
+        lat: f64::MIN,
+        long: f64::MIN,
+    };
+
+    for coord in coords {
+        let north_west = xyz_to_lat_lon(coord.x, coord.y, coord.z);
+        let south_east = xyz_to_lat_lon(coord.x + 1, coord.y + 1, coord.z);
+
+        min.lat = min.lat.min(south_east.lat);
+        min.long = min.long.min(north_west.long);
+        max.lat = max.lat.max(north_west.lat);
+        max.long = max.long.max(south_east.long);
+    }
+
+    (min, max)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use flate2::Compression as GzCompression;
+
+    fn gzip_directory(entries: &[TileEntry]) -> Vec<u8> {
+        Compression::GZip
+            .compress(&serialize_directory(entries))
+            .unwrap()
+    }
+
+    fn test_header(root: &[u8], leaves: &[u8]) -> Header {
+        Header {
+            root_directory_offset: 0,
+            root_directory_length: root.len() as u64,
+            metadata_offset: 0,
+            metadata_length: 0,
+            leaf_directories_offset: root.len() as u64,
+            leaf_directories_length: leaves.len() as u64,
+            tile_data_offset: (root.len() + leaves.len()) as u64,
+            tile_data_length: 0,
+            number_of_addressed_tiles: 0,
+            number_of_tile_entires: 0,
+            number_of_tile_contents: 0,
+            clustered: Clustered::Clustered,
+            internal_compression: Compression::GZip,
+            tile_compression: Compression::GZip,
+            tile_type: TileType::Mvt,
+            min_zoom: 0,
+            max_zoom: 0,
+            min_position: Position::from(0),
+            max_position: Position::from(0),
+            center_zoom: 0,
+            center_position: Position::from(0),
+        }
+    }
+
+    fn entry(id: u64, offset: u64, length: u64, run_length: u64) -> TileEntry {
+        TileEntry {
+            id,
+            offset,
+            length,
+            run_length,
+        }
+    }
+
+    #[test]
+    fn test_find_tile_in_leaf_directory() {
+        let leaf_a = gzip_directory(&[entry(5, 0, 10, 1), entry(6, 10, 10, 1)]);
+        let leaf_b = gzip_directory(&[entry(40, 20, 7, 1)]);
+        let mut leaves = leaf_a.clone();
+        leaves.extend_from_slice(&leaf_b);
+
+        let root = gzip_directory(&[
+            entry(1, 0, 3, 1),
+            entry(5, 0, leaf_a.len() as u64, 0),
+            entry(40, leaf_a.len() as u64, leaf_b.len() as u64, 0),
+        ]);
+
+        let header = test_header(&root, &leaves);
+        let mut file = root;
+        file.extend_from_slice(&leaves);
+        let file = Bytes::from(file);
+
+        let entries = parse_root_directory(&file, &header).expect("Should parse root");
+        // Only room for one leaf, so that looking between them evicts.
+        let leaves = DirectoryCache::new(1);
+
+        let tile = entries
+            .find_tile(TileId(6), &file, &header, &leaves)
+            .unwrap();
+        assert_eq!(tile.map(|t| t.offset), Some(10));
+
+        let tile = entries
+            .find_tile(TileId(40), &file, &header, &leaves)
+            .unwrap();
+        assert_eq!(tile.map(|t| t.length), Some(7));
+
+        let tile = entries
+            .find_tile(TileId(1), &file, &header, &leaves)
+            .unwrap();
+        assert_eq!(tile.map(|t| t.length), Some(3));
+
+        assert!(
+            entries
+                .find_tile(TileId(7), &file, &header, &leaves)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            entries
+                .find_tile(TileId(0), &file, &header, &leaves)
+                .unwrap()
+                .is_none()
+        );
+
+        // Tile 7 brought leaf a back, so tile 5 is found without parsing it again.
+        let tile = entries
+            .find_tile(TileId(5), &file, &header, &leaves)
+            .unwrap();
+        assert_eq!(tile.map(|t| t.offset), Some(0));
+        assert_eq!(
+            leaves.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 3,
+                evictions: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_find_tile_run_length() {
+        let root = Bytes::from(gzip_directory(&[
+            entry(3, 0, 10, 1),
+            entry(10, 10, 5, 4),
+            entry(14, 15, 5, 1),
+        ]));
+        let header = test_header(&root, &[]);
+        let entries = parse_root_directory(&root, &header).unwrap();
+        let leaves = DirectoryCache::new(1);
+
+        let offset = |id| {
+            entries
+                .find_tile(TileId(id), &root, &header, &leaves)
+                .unwrap()
+                .map(|t| t.offset)
+        };
+
+        assert_eq!(offset(2), None);
+        assert_eq!(offset(3), Some(0));
+        assert_eq!(offset(4), None);
+        assert_eq!(offset(9), None);
+        // Ids 10 through 13 all share the same tile data.
+        assert_eq!(offset(10), Some(10));
+        assert_eq!(offset(12), Some(10));
+        assert_eq!(offset(13), Some(10));
+        assert_eq!(offset(14), Some(15));
+        assert_eq!(offset(15), None);
+    }
+
+    #[test]
+    fn test_malformed_directory() {
+        // Claims two entries, but only has data for part of one.
+        let mut gz = GzEncoder::new(Vec::new(), GzCompression::default());
+        gz.write_all(&[2, 1, 1]).unwrap();
+        let root = gz.finish().unwrap();
+
+        let header = test_header(&root, &[]);
+        assert!(parse_root_directory(&Bytes::from(root), &header).is_err());
+
+        let root = Bytes::from(gzip_directory(&[entry(1, 0, 3, 0)]));
+        let header = test_header(&root, &[]);
+        let entries = parse_root_directory(&root, &header).unwrap();
+        let leaves = DirectoryCache::new(1);
+        assert!(matches!(
+            entries.find_tile(TileId(1), &root, &header, &leaves),
+            Err(ParseError::MalformedDirectory(_))
+        ));
+    }
+
     #[test]
     fn test_parse_varint_1() {
         let data: Vec<u8> = vec![0b10010110, 0b00000001];
@@ -424,6 +1589,267 @@ mod tests {
         assert_eq!(s, x);
     }
 
+    #[test]
+    fn test_decompress() {
+        let data = b"hello world\n".repeat(10);
+
+        assert_eq!(Compression::None.decompress(&data).unwrap(), data);
+
+        let mut gz = GzEncoder::new(Vec::new(), GzCompression::default());
+        gz.write_all(&data).unwrap();
+        let gzipped = gz.finish().unwrap();
+        assert_eq!(Compression::GZip.decompress(&gzipped).unwrap(), data);
+
+        let mut brotlied = Vec::new();
+        {
+            let mut writer = brotli::CompressorWriter::new(&mut brotlied, 4096, 9, 22);
+            writer.write_all(&data).unwrap();
+        }
+        assert_eq!(Compression::Brotli.decompress(&brotlied).unwrap(), data);
+
+        let zstded = zstd::stream::encode_all(&data[..], 3).unwrap();
+        assert_eq!(Compression::ZStd.decompress(&zstded).unwrap(), data);
+
+        assert!(matches!(
+            Compression::Unknown.decompress(&data),
+            Err(ParseError::UnknownCompression)
+        ));
+        assert!(Compression::GZip.decompress(&data).is_err());
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let json = r#"{
+            "name": "Basemap",
+            "attribution": "OSM",
+            "vector_layers": [
+                {"id": "roads", "fields": {"kind": "String"}, "minzoom": 4, "maxzoom": 15},
+                {"id": "water", "fields": {}}
+            ],
+            "tilestats": {"layerCount": 2},
+            "planetiler:version": "0.9.0"
+        }"#;
+
+        let mut gz = GzEncoder::new(Vec::new(), GzCompression::default());
+        gz.write_all(json.as_bytes()).unwrap();
+        let file = Bytes::from(gz.finish().unwrap());
+
+        let mut header = test_header(&[], &[]);
+        header.metadata_length = file.len() as u64;
+
+        let metadata = parse_metadata(&file, &header).expect("Should parse metadata");
+        assert_eq!(metadata.name.as_deref(), Some("Basemap"));
+        assert_eq!(metadata.attribution.as_deref(), Some("OSM"));
+        assert_eq!(metadata.description, None);
+        assert_eq!(metadata.layer_ids(), vec!["roads", "water"]);
+        assert_eq!(metadata.vector_layers[0].minzoom, Some(4));
+        assert_eq!(metadata.vector_layers[0].fields["kind"], "String");
+        assert_eq!(metadata.vector_layers[1].maxzoom, None);
+        assert!(metadata.tilestats.is_some());
+        assert_eq!(metadata.extra["planetiler:version"], "0.9.0");
+    }
+
+    #[test]
+    fn test_reader_get_tile() {
+        let reader = PmTilesReader::open("toolangi.pmtiles").expect("Should open archive");
+
+        let header = reader.header();
+        let pos = &header.center_position;
+        let coord = lat_lon_to_xyz(pos.lat, pos.long, 11);
+
+        let tile = reader
+            .get_mvt(coord)
+            .expect("Should read tile")
+            .expect("Should have a tile at the center");
+        let layer_names = tile.get_layer_names().unwrap();
+        assert!(layer_names.iter().any(|l| l == "roads"));
+
+        // Nowhere near Toolangi.
+        let missing = reader.get_tile(TileCoord { x: 0, y: 0, z: 11 }).unwrap();
+        assert!(missing.is_none());
+
+        // Every source should give back the same tile.
+        let tile = reader.get_tile(coord).unwrap();
+        let mmap_reader = PmTilesReader::open_mmap("toolangi.pmtiles").unwrap();
+        assert_eq!(mmap_reader.get_tile(coord).unwrap(), tile);
+        let bytes = Bytes::from(std::fs::read("toolangi.pmtiles").unwrap());
+        let memory_reader = PmTilesReader::new(bytes).unwrap();
+        assert_eq!(memory_reader.get_tile(coord).unwrap(), tile);
+
+        assert!(matches!(
+            PmTilesReader::new(Bytes::from_static(b"PMTiles")),
+            Err(ParseError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_lengths_past_end_of_file() {
+        let mut bytes = std::fs::read("toolangi.pmtiles").unwrap();
+        // The metadata length, claiming a terabyte.
+        bytes[32..40].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        let path = std::env::temp_dir().join(format!("protography-long-{}", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+
+        let result = PmTilesReader::new(std::fs::File::open(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(ParseError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let reader = PmTilesReader::open("toolangi.pmtiles").unwrap();
+        let original = std::fs::read("toolangi.pmtiles").unwrap();
+
+        let bytes = serialize_header(&reader.header());
+        assert_eq!(bytes, original[..HEADER_LENGTH]);
+    }
+
+    fn read_back(writer: PmTilesWriter) -> PmTilesReader<Bytes> {
+        let mut out = Vec::new();
+        writer.write_to(&mut out).expect("Should write archive");
+        PmTilesReader::new(Bytes::from(out)).expect("Should read archive back")
+    }
+
+    #[test]
+    fn test_writer_round_trip() {
+        let mut writer = PmTilesWriter::new(TileType::Mvt, Compression::None);
+        writer.set_metadata(Metadata {
+            name: Some(String::from("test")),
+            ..Default::default()
+        });
+
+        let ocean = Bytes::from_static(b"ocean");
+        for x in 0..4 {
+            for y in 0..4 {
+                writer
+                    .add_tile(TileCoord { x, y, z: 2 }, ocean.clone())
+                    .unwrap();
+            }
+        }
+        writer
+            .add_tile(TileCoord { x: 0, y: 0, z: 0 }, Bytes::from_static(b"world"))
+            .unwrap();
+        writer
+            .add_tile(TileCoord { x: 1, y: 1, z: 1 }, Bytes::from_static(b"first"))
+            .unwrap();
+        writer
+            .add_tile(
+                TileCoord { x: 1, y: 1, z: 1 },
+                Bytes::from_static(b"replaced"),
+            )
+            .unwrap();
+        writer
+            .add_tile(
+                TileCoord { x: 3, y: 3, z: 2 },
+                Bytes::from_static(b"island"),
+            )
+            .unwrap();
+
+        let reader = read_back(writer);
+        let header = reader.header();
+
+        assert_eq!(reader.metadata().name.as_deref(), Some("test"));
+        assert_eq!(header.min_zoom, 0);
+        assert_eq!(header.max_zoom, 2);
+        assert_eq!(header.number_of_addressed_tiles, 18);
+        assert_eq!(header.number_of_tile_contents, 4);
+        assert!(header.number_of_tile_entires < header.number_of_addressed_tiles);
+        assert_eq!(header.clustered, Clustered::Clustered);
+        assert!((header.max_position.lat - 85.05).abs() < 0.01);
+        assert!((header.min_position.long + 180.0).abs() < 0.01);
+
+        let tile = |x, y, z| reader.get_tile(TileCoord { x, y, z }).unwrap();
+        assert_eq!(tile(0, 0, 0).as_deref(), Some(&b"world"[..]));
+        assert_eq!(tile(1, 1, 1).as_deref(), Some(&b"replaced"[..]));
+        assert_eq!(tile(0, 1, 1), None);
+        assert_eq!(tile(2, 1, 2).as_deref(), Some(&b"ocean"[..]));
+        assert_eq!(tile(3, 3, 2).as_deref(), Some(&b"island"[..]));
+        assert_eq!(tile(0, 0, 3), None);
+    }
+
+    #[test]
+    fn test_writer_rejects_tiles_outside_zoom_level() {
+        let mut writer = PmTilesWriter::new(TileType::Mvt, Compression::None);
+        let tile = Bytes::from_static(b"tile");
+
+        let past_edge = TileCoord { x: 4, y: 0, z: 2 };
+        assert!(matches!(
+            writer.add_tile(past_edge, tile.clone()),
+            Err(ParseError::TileOutOfRange(coord)) if coord == past_edge
+        ));
+        let past_bottom = TileCoord { x: 0, y: 2, z: 1 };
+        assert!(matches!(
+            writer.add_tile(past_bottom, tile.clone()),
+            Err(ParseError::TileOutOfRange(_))
+        ));
+
+        // Nothing was added, so the deeper tiles they would have aliased are still missing.
+        writer
+            .add_tile(TileCoord { x: 0, y: 0, z: 0 }, tile)
+            .unwrap();
+        let reader = read_back(writer);
+        assert_eq!(reader.header().number_of_addressed_tiles, 1);
+    }
+
+    #[test]
+    fn test_writer_leaf_directories() {
+        let mut writer = PmTilesWriter::new(TileType::Mvt, Compression::None);
+        writer.set_internal_compression(Compression::None);
+
+        // Every other tile at z8, each with different contents, so there are no runs and nothing
+        // gets deduplicated.
+        let coords: Vec<TileCoord> = (0..256)
+            .flat_map(|x| (0..128).map(move |y| TileCoord { x, y: y * 2, z: 8 }))
+            .collect();
+        for (i, coord) in coords.iter().enumerate() {
+            writer
+                .add_tile(*coord, Bytes::copy_from_slice(&(i as u64).to_le_bytes()))
+                .unwrap();
+        }
+
+        let reader = read_back(writer);
+        let header = reader.header();
+        assert!(header.leaf_directories_length > 0);
+        assert!(header.root_directory_length as usize <= ROOT_DIRECTORY_BUDGET);
+        assert_eq!(header.number_of_tile_entires, coords.len() as u64);
+
+        for (i, coord) in coords.iter().enumerate().step_by(97) {
+            let tile = reader.get_tile(*coord).unwrap().unwrap();
+            assert_eq!(tile[..], (i as u64).to_le_bytes());
+        }
+
+        // Going back over the same tiles doesn't parse any more leaves.
+        let stats = reader.leaf_cache_stats();
+        assert!(stats.misses > 0);
+        for coord in coords.iter().step_by(97) {
+            reader.get_tile(*coord).unwrap();
+        }
+        assert_eq!(reader.leaf_cache_stats().misses, stats.misses);
+        assert!(reader.leaf_cache_stats().hits > stats.hits);
+        let missing = TileCoord { x: 0, y: 1, z: 8 };
+        assert_eq!(reader.get_tile(missing).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_tile_coord() {
+        let coord: TileCoord = "12/3702/2509".parse().unwrap();
+        assert_eq!(
+            coord,
+            TileCoord {
+                x: 3702,
+                y: 2509,
+                z: 12
+            }
+        );
+        assert_eq!(coord.to_string(), "12/3702/2509");
+
+        assert!("12/3702".parse::<TileCoord>().is_err());
+        assert!("1/2/0".parse::<TileCoord>().is_err());
+        assert!("a/0/0".parse::<TileCoord>().is_err());
+        assert!("32/4294967295/0".parse::<TileCoord>().is_err());
+        assert!("31/2147483647/0".parse::<TileCoord>().is_ok());
+    }
+
     #[test]
     fn test_tile_xyz_from_id() {
         let tile_coord = TileCoord::try_from(TileId(18007234)).expect("Should be convertible");
@@ -443,5 +1869,26 @@ mod tests {
 
         let tile_id = TileId::try_from(tile_coord).expect("Should be convertible");
         assert_eq!(tile_id.0, 18007234);
+
+        let world = TileCoord { x: 0, y: 0, z: 0 };
+        assert_eq!(TileId::try_from(world).unwrap().0, 0);
+        let first = TileCoord { x: 0, y: 0, z: 1 };
+        assert_eq!(TileId::try_from(first).unwrap().0, 1);
+
+        let deepest = TileCoord {
+            x: u32::MAX >> 1,
+            y: u32::MAX >> 1,
+            z: 31,
+        };
+        assert!(TileId::try_from(deepest).is_ok());
+        let too_deep = TileCoord {
+            x: u32::MAX,
+            y: 0,
+            z: 32,
+        };
+        assert!(matches!(
+            TileId::try_from(too_deep),
+            Err(ParseError::TooHighZIndex)
+        ));
     }
 }
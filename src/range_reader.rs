@@ -0,0 +1,121 @@
+// Sources of bytes for archives. Archives can be many gigabytes, so rather than reading the whole
+// thing into memory the reader asks for just the ranges it needs (header, directories, tiles).
+
+use bytes::Bytes;
+use memmap2::Mmap;
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::http_reader::{HttpRangeReader, is_url};
+
+pub trait RangeReader: Send + Sync {
+    // Reads `length` bytes starting at `offset`. Fewer bytes are only returned when the range runs
+    // past the end of the source.
+    fn read_range(&self, offset: u64, length: u64) -> io::Result<Bytes>;
+
+    // Called before re-reading an archive that reported `SourceChanged`, so that sources which
+    // pin a version of the archive can let go of it.
+    fn invalidate(&self) {}
+}
+
+// Opens a local file or, for http(s) URLs, a remote one.
+pub fn open(path: &str) -> io::Result<Box<dyn RangeReader>> {
+    if is_url(path) {
+        return Ok(Box::new(HttpRangeReader::new(path)));
+    }
+
+    Ok(Box::new(File::open(path)?))
+}
+
+// Returned (wrapped in an io::Error) by sources that can tell the archive was replaced while it
+// was being read.
+#[derive(Debug)]
+pub struct SourceChanged;
+
+impl std::fmt::Display for SourceChanged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "archive changed while it was being read")
+    }
+}
+
+impl std::error::Error for SourceChanged {}
+
+// Lets callers pick a source at runtime, eg. a URL or a local path.
+impl RangeReader for Box<dyn RangeReader> {
+    fn read_range(&self, offset: u64, length: u64) -> io::Result<Bytes> {
+        (**self).read_range(offset, length)
+    }
+
+    fn invalidate(&self) {
+        (**self).invalidate()
+    }
+}
+
+impl RangeReader for File {
+    fn read_range(&self, offset: u64, length: u64) -> io::Result<Bytes> {
+        // Lengths come from the archive itself, so a corrupt one could ask for far more than the
+        // file has.
+        let available = self.metadata()?.len().saturating_sub(offset);
+        let mut buf = vec![0; length.min(available) as usize];
+        let mut filled = 0;
+
+        while filled < buf.len() {
+            match read_at(self, &mut buf[filled..], offset + filled as u64) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        buf.truncate(filled);
+        Ok(Bytes::from(buf))
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+// An in-memory buffer. Ranges are cheap slices of the same allocation.
+impl RangeReader for Bytes {
+    fn read_range(&self, offset: u64, length: u64) -> io::Result<Bytes> {
+        let start = (offset as usize).min(self.len());
+        let end = start.saturating_add(length as usize).min(self.len());
+
+        Ok(self.slice(start..end))
+    }
+}
+
+// A memory-mapped file, leaving it to the OS to page in the parts that are read.
+pub struct MmapReader {
+    bytes: Bytes,
+}
+
+impl MmapReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+
+        // SAFETY: the map is read-only. Like every mmap, it is undefined behaviour if another
+        // process truncates or rewrites the archive while it is open, which we accept here.
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        Ok(MmapReader {
+            bytes: Bytes::from_owner(mmap),
+        })
+    }
+}
+
+impl RangeReader for MmapReader {
+    fn read_range(&self, offset: u64, length: u64) -> io::Result<Bytes> {
+        self.bytes.read_range(offset, length)
+    }
+}
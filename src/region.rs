@@ -0,0 +1,373 @@
+// Areas of the map, given as a bounding box or GeoJSON polygons, and the tiles that cover them.
+
+use geo_types::{Coord, LineString, MultiPolygon, Polygon};
+
+use std::fs;
+use std::path::Path;
+
+use crate::pmtiles::{ParseError, Position, TileCoord, fractional_lat_lon_to_xyz};
+
+// Web mercator stops here; anything further north or south is squashed onto the edge tiles.
+const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+#[derive(Clone, Debug)]
+pub struct Region {
+    // In lon/lat.
+    polygons: MultiPolygon<f64>,
+}
+
+impl Region {
+    pub fn from_bbox(min: Position, max: Position) -> Self {
+        let ring = LineString::from(vec![
+            (min.long, min.lat),
+            (max.long, min.lat),
+            (max.long, max.lat),
+            (min.long, max.lat),
+            (min.long, min.lat),
+        ]);
+
+        Region {
+            polygons: MultiPolygon(vec![Polygon::new(ring, vec![])]),
+        }
+    }
+
+    // Reads every Polygon and MultiPolygon out of a GeoJSON file, be it a bare geometry, a
+    // Feature or a FeatureCollection.
+    pub fn from_geojson_file(path: impl AsRef<Path>) -> Result<Self, ParseError> {
+        let json = fs::read(path)?;
+        let value: serde_json::Value =
+            serde_json::from_slice(&json).map_err(|e| ParseError::InvalidGeoJson(e.to_string()))?;
+
+        let mut polygons = Vec::new();
+        collect_polygons(&value, &mut polygons).map_err(ParseError::InvalidGeoJson)?;
+        if polygons.is_empty() {
+            return Err(ParseError::InvalidGeoJson(String::from(
+                "no polygons found",
+            )));
+        }
+
+        Ok(Region {
+            polygons: MultiPolygon(polygons),
+        })
+    }
+
+    // The smallest box around the region, as (south west, north east).
+    pub fn bounds(&self) -> (Position, Position) {
+        let mut min = Position {
+            lat: f64::MAX,
+            long: f64::MAX,
+        };
+        let mut max = Position {
+            lat: f64::MIN,
+            long: f64::MIN,
+        };
+
+        for polygon in &self.polygons {
+            for coord in polygon.exterior().coords() {
+                min.lat = min.lat.min(coord.y);
+                min.long = min.long.min(coord.x);
+                max.lat = max.lat.max(coord.y);
+                max.long = max.long.max(coord.x);
+            }
+        }
+
+        (min, max)
+    }
+
+    // Every tile at this zoom level that overlaps the region, in row order.
+    pub fn tiles(&self, zoom: u8) -> Vec<TileCoord> {
+        let last_tile = (1u64 << zoom) - 1;
+        // Tile x/y coordinates are at most 2^32 - 1, so all of these fit in a u32.
+        let to_tile = |v: f64| (v.floor().max(0.0) as u64).min(last_tile) as u32;
+
+        // Work in tile coordinates, where each tile is a unit square.
+        let polygons: Vec<Vec<Vec<Coord<f64>>>> = self
+            .polygons
+            .iter()
+            .map(|polygon| {
+                std::iter::once(polygon.exterior())
+                    .chain(polygon.interiors())
+                    .map(|ring| {
+                        ring.coords()
+                            .map(|c| {
+                                let lat = c.y.clamp(-MAX_LATITUDE, MAX_LATITUDE);
+                                let (x, y) = fractional_lat_lon_to_xyz(lat, c.x, zoom);
+                                Coord { x, y }
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut tiles = Vec::new();
+        for rings in &polygons {
+            let exterior = &rings[0];
+            let min_x = to_tile(exterior.iter().map(|c| c.x).fold(f64::MAX, f64::min));
+            let max_x = to_tile(exterior.iter().map(|c| c.x).fold(f64::MIN, f64::max));
+            let min_y = to_tile(exterior.iter().map(|c| c.y).fold(f64::MAX, f64::min));
+            let max_y = to_tile(exterior.iter().map(|c| c.y).fold(f64::MIN, f64::max));
+
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    if polygon_touches_tile(rings, f64::from(x), f64::from(y)) {
+                        tiles.push(TileCoord { x, y, z: zoom });
+                    }
+                }
+            }
+        }
+
+        // Polygons in a MultiPolygon can share tiles.
+        if polygons.len() > 1 {
+            tiles.sort_by_key(|t| (t.y, t.x));
+            tiles.dedup();
+        }
+
+        tiles
+    }
+}
+
+// Parses "min_lon,min_lat,max_lon,max_lat", the same order as a GeoJSON bbox.
+pub fn parse_bbox(s: &str) -> Result<Region, String> {
+    let values: Vec<f64> = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid number in {s:?}"))?;
+
+    let [min_lon, min_lat, max_lon, max_lat] = values[..] else {
+        return Err(format!(
+            "expected min_lon,min_lat,max_lon,max_lat, got {s:?}"
+        ));
+    };
+
+    if !(-180.0..=180.0).contains(&min_lon) || !(-180.0..=180.0).contains(&max_lon) {
+        return Err(String::from("longitudes must be between -180 and 180"));
+    }
+    if !(-90.0..=90.0).contains(&min_lat) || !(-90.0..=90.0).contains(&max_lat) {
+        return Err(String::from("latitudes must be between -90 and 90"));
+    }
+    if min_lon > max_lon || min_lat > max_lat {
+        return Err(String::from("the minimums must come before the maximums"));
+    }
+
+    Ok(Region::from_bbox(
+        Position {
+            lat: min_lat,
+            long: min_lon,
+        },
+        Position {
+            lat: max_lat,
+            long: max_lon,
+        },
+    ))
+}
+
+fn collect_polygons(value: &serde_json::Value, out: &mut Vec<Polygon<f64>>) -> Result<(), String> {
+    let list = |key: &str| {
+        value[key]
+            .as_array()
+            .ok_or_else(|| format!("expected a {key:?} array"))
+    };
+
+    match value["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in list("features")? {
+                collect_polygons(feature, out)?;
+            }
+        }
+        Some("Feature") => collect_polygons(&value["geometry"], out)?,
+        Some("GeometryCollection") => {
+            for geometry in list("geometries")? {
+                collect_polygons(geometry, out)?;
+            }
+        }
+        Some("Polygon") => out.push(parse_polygon(&value["coordinates"])?),
+        Some("MultiPolygon") => {
+            for polygon in list("coordinates")? {
+                out.push(parse_polygon(polygon)?);
+            }
+        }
+        Some(other) => return Err(format!("a {other} doesn't cover an area")),
+        None => return Err(String::from("missing \"type\"")),
+    }
+
+    Ok(())
+}
+
+fn parse_polygon(value: &serde_json::Value) -> Result<Polygon<f64>, String> {
+    let mut rings = value
+        .as_array()
+        .ok_or("polygon coordinates must be an array of rings")?
+        .iter()
+        .map(parse_ring)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if rings.is_empty() {
+        return Err(String::from("polygon has no rings"));
+    }
+    let exterior = rings.remove(0);
+
+    Ok(Polygon::new(exterior, rings))
+}
+
+fn parse_ring(value: &serde_json::Value) -> Result<LineString<f64>, String> {
+    let positions = value
+        .as_array()
+        .ok_or("ring must be an array of positions")?;
+
+    let coords = positions
+        .iter()
+        .map(|position| match position.as_array().map(Vec::as_slice) {
+            Some([lon, lat, ..]) => match (lon.as_f64(), lat.as_f64()) {
+                (Some(x), Some(y)) => Ok(Coord { x, y }),
+                _ => Err(format!("invalid position {position}")),
+            },
+            _ => Err(format!("invalid position {position}")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if coords.len() < 4 {
+        return Err(String::from("rings need at least four positions"));
+    }
+
+    Ok(LineString(coords))
+}
+
+// Whether the polygon (exterior ring then holes) overlaps the unit square at (x, y). Either an edge
+// crosses into the square, or the square is entirely inside or outside the polygon and its middle
+// tells us which.
+fn polygon_touches_tile(rings: &[Vec<Coord<f64>>], x: f64, y: f64) -> bool {
+    let edges = || rings.iter().flat_map(|ring| ring.windows(2));
+
+    if edges().any(|edge| segment_touches_square(edge[0], edge[1], x, y)) {
+        return true;
+    }
+
+    // Even-odd ray casting, which treats holes as outside.
+    let middle = Coord {
+        x: x + 0.5,
+        y: y + 0.5,
+    };
+    let crossings = edges()
+        .filter(|edge| {
+            let (a, b) = (edge[0], edge[1]);
+            (a.y > middle.y) != (b.y > middle.y)
+                && middle.x < a.x + (middle.y - a.y) / (b.y - a.y) * (b.x - a.x)
+        })
+        .count();
+
+    crossings % 2 == 1
+}
+
+// Liang-Barsky: clip the segment to the square and see if anything is left.
+fn segment_touches_square(a: Coord<f64>, b: Coord<f64>, x: f64, y: f64) -> bool {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let mut t0: f64 = 0.0;
+    let mut t1: f64 = 1.0;
+
+    for (p, q) in [
+        (-dx, a.x - x),
+        (dx, x + 1.0 - a.x),
+        (-dy, a.y - y),
+        (dy, y + 1.0 - a.y),
+    ] {
+        if p == 0.0 {
+            // Parallel to this side of the square, and on the wrong side of it.
+            if q < 0.0 {
+                return false;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+
+    t0 <= t1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bbox_tiles() {
+        let region = parse_bbox("-180,-90,180,90").unwrap();
+        assert_eq!(region.tiles(0), vec![TileCoord { x: 0, y: 0, z: 0 }]);
+        assert_eq!(region.tiles(2).len(), 16);
+
+        // A box inside a single tile.
+        let region = parse_bbox("145.4,-37.6,145.5,-37.5").unwrap();
+        assert_eq!(
+            region.tiles(8),
+            vec![TileCoord {
+                x: 231,
+                y: 156,
+                z: 8
+            }]
+        );
+
+        assert!(parse_bbox("1,2,3").is_err());
+        assert!(parse_bbox("10,0,0,10").is_err());
+        assert!(parse_bbox("0,0,200,10").is_err());
+    }
+
+    #[test]
+    fn test_polygon_tiles() {
+        let region = Region::from_geojson_file("toolangi.geojson").unwrap();
+        let (min, max) = region.bounds();
+        let bbox = Region::from_bbox(min, max);
+
+        // The bundled region is a rectangle, so it covers the same tiles as its bounding box.
+        for zoom in [0, 10, 14] {
+            assert_eq!(region.tiles(zoom), bbox.tiles(zoom));
+        }
+        assert!(region.tiles(14).len() > 1);
+
+        // A diamond skips the tiles in the corners of its bounding box, and a square hole well
+        // inside it skips the tiles entirely within the hole.
+        let mut diamond = serde_json::json!({
+            "type": "Polygon",
+            "coordinates": [
+                [[0.0, -40.0], [40.0, 0.0], [0.0, 40.0], [-40.0, 0.0], [0.0, -40.0]],
+            ],
+        });
+        let mut polygons = Vec::new();
+        collect_polygons(&diamond, &mut polygons).unwrap();
+        let region = Region {
+            polygons: MultiPolygon(polygons),
+        };
+        let tiles = region.tiles(4);
+        assert!(!tiles.contains(&TileCoord { x: 6, y: 6, z: 4 }));
+        assert!(tiles.contains(&TileCoord { x: 7, y: 6, z: 4 }));
+        assert!(region.tiles(5).contains(&TileCoord { x: 16, y: 16, z: 5 }));
+
+        diamond["coordinates"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!([
+                [-15.0, -15.0],
+                [15.0, -15.0],
+                [15.0, 15.0],
+                [-15.0, 15.0],
+                [-15.0, -15.0]
+            ]));
+        let mut polygons = Vec::new();
+        collect_polygons(&diamond, &mut polygons).unwrap();
+        let region = Region {
+            polygons: MultiPolygon(polygons),
+        };
+        let tiles = region.tiles(5);
+        // Both of these lie within 11.25 degrees of the middle.
+        assert!(!tiles.contains(&TileCoord { x: 16, y: 16, z: 5 }));
+        assert!(!tiles.contains(&TileCoord { x: 15, y: 15, z: 5 }));
+        // This one crosses the edge of the hole.
+        assert!(tiles.contains(&TileCoord { x: 17, y: 16, z: 5 }));
+        assert!(region.tiles(4).contains(&TileCoord { x: 7, y: 6, z: 4 }));
+
+        let mut polygons = Vec::new();
+        let point = serde_json::json!({ "type": "Point", "coordinates": [0.0, 0.0] });
+        assert!(collect_polygons(&point, &mut polygons).is_err());
+    }
+}
@@ -3,19 +3,21 @@
 
 use std::sync::Arc;
 use std::time::Instant;
-use vello::kurbo::{Affine, Circle, Ellipse, Line, RoundedRect, Stroke};
 use vello::peniko::color::palette;
 use vello::util::{RenderContext, RenderSurface};
 use vello::{AaConfig, Renderer, RendererOptions, Scene};
 use winit::application::ApplicationHandler;
 use winit::dpi::LogicalSize;
-use winit::event::WindowEvent;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
 use winit::event_loop::ActiveEventLoop;
-use winit::window::{self, Window};
+use winit::keyboard::{Key, NamedKey};
+use winit::window::Window;
 
 use vello::wgpu;
 
 use crate::map_renderer::{Camera, MapRenderer, RenderTargetInfo};
+use crate::navigation::{self, Navigation, StartView};
+use crate::tile_loader::LoadedTile;
 
 #[derive(Debug)]
 pub enum RenderState {
@@ -49,10 +51,18 @@ pub struct SimpleVelloApp {
 
     pub camera: Camera,
 
+    pub navigation: Navigation,
+
+    /// Where to put the camera once the window is open, taken the first time it is
+    pub start: Option<StartView>,
+
+    /// The window's size when it's created, in logical pixels
+    pub window_size: (u32, u32),
+
     pub last_frame_time: Instant,
 }
 
-impl ApplicationHandler for SimpleVelloApp {
+impl ApplicationHandler<LoadedTile> for SimpleVelloApp {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let RenderState::Suspended(cached_window) = &mut self.state else {
             return;
@@ -61,12 +71,18 @@ impl ApplicationHandler for SimpleVelloApp {
         // Get the winit window cached in a previous Suspended event or else create a new window
         let window = cached_window
             .take()
-            .unwrap_or_else(|| create_winit_window(event_loop));
+            .unwrap_or_else(|| create_winit_window(event_loop, self.window_size));
 
         window.focus_window();
 
         // Create a vello Surface
         let size = window.inner_size();
+
+        // Now the size of the window is known the map can be fitted to it
+        if let Some(start) = self.start.take() {
+            self.camera = start.camera(size.width, size.height);
+        }
+
         let surface_future = self.context.create_surface(
             window.clone(),
             size.width,
@@ -90,6 +106,11 @@ impl ApplicationHandler for SimpleVelloApp {
         };
     }
 
+    // Tiles come in from the loader threads through the event loop.
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, loaded: LoadedTile) {
+        self.map_renderer.tile_loaded(loaded);
+    }
+
     fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
         if let RenderState::Active { window, .. } = &self.state {
             self.state = RenderState::Suspended(Some(window.clone()));
@@ -114,7 +135,19 @@ impl ApplicationHandler for SimpleVelloApp {
 
         match event {
             // Exit the event loop when a close is requested (e.g. window's close button is pressed)
-            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::CloseRequested => {
+                let cache = self.map_renderer.cache();
+                println!(
+                    "tile cache: {}, {} tiles in {:.1} MB",
+                    cache.stats(),
+                    cache.len(),
+                    cache.used() as f64 / (1024.0 * 1024.0)
+                );
+                if let Some(stats) = self.map_renderer.source().cache_stats() {
+                    println!("directory cache: {stats}");
+                }
+                event_loop.exit();
+            }
 
             // Resize the surface when the window is resized
             WindowEvent::Resized(size) => {
@@ -127,6 +160,49 @@ impl ApplicationHandler for SimpleVelloApp {
                 }
             }
 
+            WindowEvent::CursorMoved { position, .. } => {
+                self.navigation.cursor_moved(
+                    &mut self.camera,
+                    (position.x, position.y),
+                    Instant::now(),
+                );
+            }
+            WindowEvent::CursorLeft { .. } => self.navigation.cursor_left(),
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let pressed = state == ElementState::Pressed;
+                self.navigation
+                    .button(&mut self.camera, pressed, Instant::now());
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                // A notch of a mouse wheel is half a zoom level. Trackpads scroll by pixels, and a
+                // lot more often.
+                let levels = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => f64::from(y) / 2.0,
+                    MouseScrollDelta::PixelDelta(position) => position.y / 250.0,
+                };
+                self.navigation.zoom(&mut self.camera, levels);
+            }
+            WindowEvent::PinchGesture { delta, .. } => {
+                self.navigation.pinch(&mut self.camera, delta);
+            }
+            WindowEvent::KeyboardInput { event, .. } if event.state == ElementState::Pressed => {
+                let key = match event.logical_key.as_ref() {
+                    Key::Named(NamedKey::ArrowLeft) => navigation::Key::Left,
+                    Key::Named(NamedKey::ArrowRight) => navigation::Key::Right,
+                    Key::Named(NamedKey::ArrowUp) => navigation::Key::Up,
+                    Key::Named(NamedKey::ArrowDown) => navigation::Key::Down,
+                    // = is + without shift on most keyboards.
+                    Key::Character("+" | "=") => navigation::Key::ZoomIn,
+                    Key::Character("-") => navigation::Key::ZoomOut,
+                    _ => return,
+                };
+                self.navigation.key(&mut self.camera, key);
+            }
+
             // This is where all the rendering happens
             WindowEvent::RedrawRequested => {
                 if !*valid_surface {
@@ -145,20 +221,17 @@ impl ApplicationHandler for SimpleVelloApp {
                 // the same Scene is reused so that the underlying memory allocation can also be reused.
                 self.scene.reset();
 
-                self.camera.x += 100.0 * delta_time;
+                self.navigation.update(&mut self.camera, delta_time);
 
                 let target_info = RenderTargetInfo {
                     width: surface.config.width,
                     height: surface.config.height,
                 };
-
-                let transform =
-                    Affine::IDENTITY.with_translation((self.camera.x, self.camera.y).into());
-
-                println!("{:#?}", self.camera.x);
+                self.camera.width = target_info.width;
+                self.camera.height = target_info.height;
 
                 self.map_renderer
-                    .render_to_scene(&mut self.scene, &target_info, transform);
+                    .render_to_scene(&mut self.scene, &target_info, &self.camera);
 
                 // Get a handle to the device
                 let device_handle = &self.context.devices[surface.dev_id];
@@ -214,9 +287,9 @@ impl ApplicationHandler for SimpleVelloApp {
 }
 
 /// Helper function that creates a Winit window and returns it (wrapped in an Arc for sharing between threads)
-fn create_winit_window(event_loop: &ActiveEventLoop) -> Arc<Window> {
+fn create_winit_window(event_loop: &ActiveEventLoop, (width, height): (u32, u32)) -> Arc<Window> {
     let attr = Window::default_attributes()
-        .with_inner_size(LogicalSize::new(1024, 1024))
+        .with_inner_size(LogicalSize::new(width, height))
         .with_resizable(true)
         .with_title("Vello Shapes");
     Arc::new(event_loop.create_window(attr).unwrap())
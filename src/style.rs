@@ -0,0 +1,1012 @@
+// MapLibre (or Mapbox GL) style JSON, saying which layers of the tiles to draw, in what order and
+// in what colours. Only vector tiles get drawn, so the style's sources are ignored and its layers
+// are matched up with the tiles' layers by their source-layer.
+// See https://maplibre.org/maplibre-style-spec/
+
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use vello::kurbo::{Cap, Join};
+use vello::peniko::Color;
+use vello::peniko::color::parse_color;
+
+use std::fs;
+use std::path::Path;
+
+use crate::expression::{Context, Expression, Value as ExpressionValue};
+use crate::pmtiles::ParseError;
+
+pub struct Style {
+    pub layers: Vec<StyleLayer>,
+}
+
+pub struct StyleLayer {
+    // Which of the tiles' layers to draw. None draws all of them, and background layers don't
+    // need one at all.
+    pub source_layer: Option<String>,
+    // The layer is drawn from min_zoom up to, but not including, max_zoom.
+    pub min_zoom: f64,
+    pub max_zoom: f64,
+    // Features have to pass this to be drawn. Background layers don't have features, so it's
+    // ignored for them.
+    pub filter: Option<Expression>,
+    pub paint: Paint,
+}
+
+impl StyleLayer {
+    pub fn visible_at(&self, zoom: f64) -> bool {
+        zoom >= self.min_zoom && zoom < self.max_zoom
+    }
+
+    pub fn draws(&self, layer_name: &str) -> bool {
+        self.source_layer
+            .as_ref()
+            .is_none_or(|name| name == layer_name)
+    }
+
+    // Anything other than true, like a filter that asks for a missing property, fails it.
+    pub fn passes_filter(&self, context: &Context) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.evaluate(context) == ExpressionValue::Bool(true))
+    }
+}
+
+// How a layer is drawn, going by its type.
+pub enum Paint {
+    Background(BackgroundPaint),
+    Fill(FillPaint),
+    Line(LinePaint),
+    Circle(CirclePaint),
+    Symbol(SymbolPaint),
+}
+
+pub struct BackgroundPaint {
+    pub color: Property<Color>,
+    pub opacity: Property<f64>,
+}
+
+pub struct FillPaint {
+    pub color: Property<Color>,
+    pub opacity: Property<f64>,
+    // A one pixel outline, if there is one.
+    pub outline_color: Option<Property<Color>>,
+}
+
+pub struct LinePaint {
+    pub color: Property<Color>,
+    pub opacity: Property<f64>,
+    pub width: Property<f64>,
+    // Dash and gap lengths, in multiples of the line's width. Empty for a solid line.
+    pub dashes: Property<Vec<f64>>,
+    pub cap: Cap,
+    pub join: Join,
+}
+
+pub struct CirclePaint {
+    pub color: Property<Color>,
+    pub opacity: Property<f64>,
+    pub radius: Property<f64>,
+    pub stroke_color: Property<Color>,
+    pub stroke_opacity: Property<f64>,
+    pub stroke_width: Property<f64>,
+}
+
+pub struct SymbolPaint {
+    pub color: Property<Color>,
+    pub opacity: Property<f64>,
+}
+
+// A paint property's value, which can change with the zoom or from one feature to the next.
+// Whenever an expression doesn't come out as the right type of value, the property's default is
+// used instead.
+pub enum Property<T> {
+    Constant(T),
+    Expression(Expression, T),
+}
+
+impl<T: PropertyType> Property<T> {
+    pub fn evaluate(&self, context: &Context) -> T {
+        match self {
+            Property::Constant(value) => value.clone(),
+            Property::Expression(expression, default) => {
+                T::from_value(&expression.evaluate(context)).unwrap_or_else(|| default.clone())
+            }
+        }
+    }
+}
+
+impl Property<Color> {
+    pub fn with_opacity(&self, opacity: &Property<f64>, context: &Context) -> Color {
+        let opacity = opacity.evaluate(context).clamp(0.0, 1.0);
+        self.evaluate(context).multiply_alpha(opacity as f32)
+    }
+}
+
+pub trait PropertyType: Clone {
+    // For error messages, eg. "a number".
+    const DESCRIPTION: &str;
+
+    fn from_value(value: &ExpressionValue) -> Option<Self>;
+
+    // Gives the expression a chance to get its values into shape before it's evaluated.
+    fn prepare(expression: Expression) -> Expression {
+        expression
+    }
+}
+
+impl PropertyType for f64 {
+    const DESCRIPTION: &str = "a number";
+
+    fn from_value(value: &ExpressionValue) -> Option<Self> {
+        value.as_number()
+    }
+}
+
+impl PropertyType for Color {
+    const DESCRIPTION: &str = "a CSS colour";
+
+    fn from_value(value: &ExpressionValue) -> Option<Self> {
+        value.to_color()
+    }
+
+    fn prepare(expression: Expression) -> Expression {
+        expression.expect_color()
+    }
+}
+
+impl PropertyType for Vec<f64> {
+    const DESCRIPTION: &str = "an array of numbers";
+
+    fn from_value(value: &ExpressionValue) -> Option<Self> {
+        match value {
+            ExpressionValue::Array(values) => {
+                values.iter().map(ExpressionValue::as_number).collect()
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StyleJson {
+    layers: Vec<LayerJson>,
+}
+
+#[derive(Deserialize)]
+struct LayerJson {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "source-layer")]
+    source_layer: Option<String>,
+    minzoom: Option<f64>,
+    maxzoom: Option<f64>,
+    filter: Option<Value>,
+    #[serde(default)]
+    layout: Map<String, Value>,
+    #[serde(default)]
+    paint: Map<String, Value>,
+}
+
+impl Style {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ParseError> {
+        Style::parse(&fs::read_to_string(path)?)
+    }
+
+    // Layers of a type that can't be drawn (raster, hillshade, fill-extrusion, heatmap) are left
+    // out, as are hidden ones.
+    pub fn parse(json: &str) -> Result<Self, ParseError> {
+        let style: StyleJson =
+            serde_json::from_str(json).map_err(|e| ParseError::InvalidStyle(e.to_string()))?;
+
+        let mut layers = Vec::new();
+        for layer in style.layers {
+            let layout = Properties::new(&layer.id, &layer.layout);
+            if layout.string("visibility", "visible")? == "none" {
+                continue;
+            }
+
+            let Some(paint) = Paint::parse(
+                &layer.kind,
+                &layout,
+                &Properties::new(&layer.id, &layer.paint),
+            )?
+            else {
+                continue;
+            };
+            let filter = layer
+                .filter
+                .as_ref()
+                .map(parse_filter)
+                .transpose()
+                .map_err(|message| {
+                    ParseError::InvalidStyle(format!(
+                        "layer {:?}: filter is invalid: {message}",
+                        layer.id
+                    ))
+                })?;
+
+            layers.push(StyleLayer {
+                source_layer: layer.source_layer,
+                min_zoom: layer.minzoom.unwrap_or(f64::NEG_INFINITY),
+                max_zoom: layer.maxzoom.unwrap_or(f64::INFINITY),
+                filter,
+                paint,
+            });
+        }
+
+        Ok(Style { layers })
+    }
+
+    // What the viewer drew before it had styles: polygons filled in green, then lines in purple
+    // on top. With no layer names given, every layer is drawn.
+    pub fn fallback(source_layers: &[String]) -> Self {
+        let names: Vec<Option<&String>> = if source_layers.is_empty() {
+            vec![None]
+        } else {
+            source_layers.iter().map(Some).collect()
+        };
+
+        let fills = names.iter().map(|name| StyleLayer {
+            source_layer: name.cloned(),
+            min_zoom: f64::NEG_INFINITY,
+            max_zoom: f64::INFINITY,
+            filter: None,
+            paint: Paint::Fill(FillPaint {
+                color: Property::Constant(Color::new([0.2, 0.7, 0.5, 0.5])),
+                opacity: Property::Constant(1.0),
+                outline_color: Some(Property::Constant(Color::new([0.0, 0.5, 0.0, 1.0]))),
+            }),
+        });
+        let lines = names.iter().map(|name| StyleLayer {
+            source_layer: name.cloned(),
+            min_zoom: f64::NEG_INFINITY,
+            max_zoom: f64::INFINITY,
+            filter: None,
+            paint: Paint::Line(LinePaint {
+                color: Property::Constant(Color::new([0.7, 0.6, 1.0, 1.0])),
+                opacity: Property::Constant(1.0),
+                width: Property::Constant(6.0),
+                dashes: Property::Constant(Vec::new()),
+                cap: Cap::Butt,
+                join: Join::Miter,
+            }),
+        });
+
+        Style {
+            layers: fills.chain(lines).collect(),
+        }
+    }
+}
+
+impl Paint {
+    // None for layer types that aren't drawn.
+    fn parse(
+        kind: &str,
+        layout: &Properties,
+        paint: &Properties,
+    ) -> Result<Option<Self>, ParseError> {
+        let black = Color::new([0.0, 0.0, 0.0, 1.0]);
+
+        let paint = match kind {
+            "background" => Paint::Background(BackgroundPaint {
+                color: paint.property("background-color", black)?,
+                opacity: paint.property("background-opacity", 1.0)?,
+            }),
+            "fill" => Paint::Fill(FillPaint {
+                color: paint.property("fill-color", black)?,
+                opacity: paint.property("fill-opacity", 1.0)?,
+                outline_color: paint.optional_property("fill-outline-color", Color::TRANSPARENT)?,
+            }),
+            "line" => Paint::Line(LinePaint {
+                color: paint.property("line-color", black)?,
+                opacity: paint.property("line-opacity", 1.0)?,
+                width: paint.property("line-width", 1.0)?,
+                dashes: paint.property("line-dasharray", Vec::new())?,
+                cap: match layout.string("line-cap", "butt")? {
+                    "round" => Cap::Round,
+                    "square" => Cap::Square,
+                    _ => Cap::Butt,
+                },
+                join: match layout.string("line-join", "miter")? {
+                    "round" => Join::Round,
+                    "bevel" => Join::Bevel,
+                    _ => Join::Miter,
+                },
+            }),
+            "circle" => Paint::Circle(CirclePaint {
+                color: paint.property("circle-color", black)?,
+                opacity: paint.property("circle-opacity", 1.0)?,
+                radius: paint.property("circle-radius", 5.0)?,
+                stroke_color: paint.property("circle-stroke-color", black)?,
+                stroke_opacity: paint.property("circle-stroke-opacity", 1.0)?,
+                stroke_width: paint.property("circle-stroke-width", 0.0)?,
+            }),
+            "symbol" => Paint::Symbol(SymbolPaint {
+                color: paint.property("text-color", black)?,
+                opacity: paint.property("text-opacity", 1.0)?,
+            }),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(paint))
+    }
+}
+
+// A layer's layout or paint properties.
+struct Properties<'a> {
+    layer: &'a str,
+    values: &'a Map<String, Value>,
+}
+
+impl<'a> Properties<'a> {
+    fn new(layer: &'a str, values: &'a Map<String, Value>) -> Self {
+        Properties { layer, values }
+    }
+
+    fn error(&self, name: &str, message: &str) -> ParseError {
+        ParseError::InvalidStyle(format!("layer {:?}: {name} {message}", self.layer))
+    }
+
+    fn property<T: PropertyType>(&self, name: &str, default: T) -> Result<Property<T>, ParseError> {
+        Ok(self
+            .optional_property(name, default.clone())?
+            .unwrap_or(Property::Constant(default)))
+    }
+
+    // None if the property isn't there. A plain value of the wrong type is an error, but an
+    // expression is only known to be wrong once it's evaluated.
+    fn optional_property<T: PropertyType>(
+        &self,
+        name: &str,
+        default: T,
+    ) -> Result<Option<Property<T>>, ParseError> {
+        let Some(json) = self.values.get(name) else {
+            return Ok(None);
+        };
+        let expression = parse_property(json)
+            .map_err(|message| self.error(name, &format!("is invalid: {message}")))?;
+
+        match T::prepare(expression) {
+            Expression::Literal(value) => T::from_value(&value)
+                .map(|value| Some(Property::Constant(value)))
+                .ok_or_else(|| self.error(name, &format!("should be {}", T::DESCRIPTION))),
+            expression => Ok(Some(Property::Expression(expression, default))),
+        }
+    }
+
+    // Layout properties that aren't plain strings get the default.
+    fn string(&self, name: &str, default: &'a str) -> Result<&'a str, ParseError> {
+        match self.values.get(name) {
+            None | Some(Value::Array(_) | Value::Object(_)) => Ok(default),
+            Some(Value::String(s)) => Ok(s),
+            Some(_) => Err(self.error(name, "should be a string")),
+        }
+    }
+}
+
+// A property can be a plain value, including an array of numbers for line-dasharray, an
+// expression, or a function from before there were expressions.
+fn parse_property(json: &Value) -> Result<Expression, String> {
+    match json {
+        Value::Array(values) if !values.first().is_some_and(Value::is_string) => {
+            Ok(Expression::Literal(ExpressionValue::from_json(json)))
+        }
+        Value::Object(function) => parse_function(function),
+        _ => Expression::parse(json),
+    }
+}
+
+// Turns a function, eg. {"base": 1.5, "stops": [[12, 1], [18, 4]]}, into the expression that
+// does the same thing. Functions of both the zoom and a property aren't supported.
+fn parse_function(function: &Map<String, Value>) -> Result<Expression, String> {
+    let input = match function.get("property") {
+        Some(Value::String(name)) => json!(["get", name]),
+        Some(_) => return Err(String::from("the function's property has to be a string")),
+        None => json!(["zoom"]),
+    };
+    let stops = match function.get("stops") {
+        Some(Value::Array(stops)) => stops
+            .iter()
+            .map(|stop| match stop.as_array().map(Vec::as_slice) {
+                Some([Value::Object(_), _]) => Err(String::from(
+                    "functions of both the zoom and a property aren't supported",
+                )),
+                Some([input, output]) => Ok((input, json!(["literal", output]))),
+                _ => Err(String::from(
+                    "the function's stops have to be [input, output] pairs",
+                )),
+            })
+            .collect::<Result<Vec<_>, String>>()?,
+        Some(_) => return Err(String::from("the function's stops have to be an array")),
+        None => Vec::new(),
+    };
+
+    // Numbers and colours are interpolated between by default, and anything else steps.
+    let interpolated = stops.first().is_some_and(|(_, output)| match &output[1] {
+        Value::Number(_) | Value::Array(_) => true,
+        Value::String(s) => parse_color(s).is_ok(),
+        _ => false,
+    });
+    let default_kind = if interpolated {
+        "exponential"
+    } else {
+        "interval"
+    };
+    let kind = function
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or(default_kind);
+    let mut default = function
+        .get("default")
+        .map(|default| json!(["literal", default]));
+
+    let mut expression = match kind {
+        "identity" => input,
+        "exponential" | "interval" if stops.is_empty() => {
+            return Err(String::from("the function needs at least one stop"));
+        }
+        "exponential" => {
+            let base = function.get("base").cloned().unwrap_or(json!(1));
+            let operator = match function.get("colorSpace").and_then(Value::as_str) {
+                Some("lab") => "interpolate-lab",
+                Some("hcl") => "interpolate-hcl",
+                _ => "interpolate",
+            };
+            let mut expression = vec![json!(operator), json!(["exponential", base]), input];
+            for (input, output) in stops {
+                expression.extend([input.clone(), output]);
+            }
+            Value::Array(expression)
+        }
+        "interval" => {
+            let mut expression = vec![json!("step"), input, stops[0].1.clone()];
+            for (input, output) in stops {
+                expression.extend([input.clone(), output]);
+            }
+            Value::Array(expression)
+        }
+        "categorical" => {
+            let mut expression = vec![json!("match"), input];
+            for (input, output) in stops {
+                expression.extend([input.clone(), output]);
+            }
+            // The default is the fallback, so there's no need to coalesce it later.
+            expression.push(default.take().unwrap_or(Value::Null));
+            Value::Array(expression)
+        }
+        _ => return Err(format!("unknown function type {kind:?}")),
+    };
+    if let Some(default) = default {
+        expression = json!(["coalesce", expression, default]);
+    }
+
+    Expression::parse(&expression)
+}
+
+// A layer's filter is either an expression, or in the older syntax that expressions replaced, eg.
+// ["==", "kind", "highway"], in which case it's turned into the equivalent expression.
+fn parse_filter(json: &Value) -> Result<Expression, String> {
+    if is_expression_filter(json) {
+        Expression::parse(json)
+    } else {
+        Expression::parse(&convert_filter(json)?)
+    }
+}
+
+// Some filters are valid either way, like ["has", "name"], so this follows MapLibre's rules for
+// telling them apart.
+fn is_expression_filter(json: &Value) -> bool {
+    let Some((Value::String(operator), args)) = json.as_array().and_then(|f| f.split_first())
+    else {
+        return json.is_boolean();
+    };
+
+    match operator.as_str() {
+        "has" => args
+            .first()
+            .is_some_and(|key| key != "$id" && key != "$type"),
+        "in" => args.len() >= 2 && (!args[0].is_string() || args[1].is_array()),
+        "!in" | "!has" | "none" => false,
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => {
+            args.len() != 2 || args[0].is_array() || args[1].is_array()
+        }
+        "all" | "any" => args
+            .iter()
+            .all(|filter| filter.is_boolean() || is_expression_filter(filter)),
+        _ => true,
+    }
+}
+
+// Rewrites an old-style filter as an expression. Property names starting with $ are the
+// feature's geometry type and id.
+fn convert_filter(json: &Value) -> Result<Value, String> {
+    let invalid = || format!("{json} isn't a valid filter");
+    if json.is_boolean() {
+        return Ok(json.clone());
+    }
+    let Some((Value::String(operator), args)) = json.as_array().and_then(|f| f.split_first())
+    else {
+        return Err(invalid());
+    };
+    let key = |json: &Value| match json.as_str() {
+        Some("$type") => Ok(json!(["geometry-type"])),
+        Some("$id") => Ok(json!(["id"])),
+        Some(key) => Ok(json!(["get", key])),
+        None => Err(invalid()),
+    };
+    let convert_all = |filters: &[Value]| {
+        filters
+            .iter()
+            .map(convert_filter)
+            .collect::<Result<Vec<_>, String>>()
+    };
+
+    let expression = match (operator.as_str(), args) {
+        ("has", [name]) => match name.as_str() {
+            Some("$type") => json!(true),
+            Some("$id") => json!(["!=", ["id"], null]),
+            Some(name) => json!(["has", name]),
+            None => return Err(invalid()),
+        },
+        ("!has", [name]) => json!(["!", convert_filter(&json!(["has", name]))?]),
+        ("==" | "!=" | "<" | "<=" | ">" | ">=", [name, value]) => {
+            json!([operator, key(name)?, ["literal", value]])
+        }
+        ("in", [name, values @ ..]) => json!(["in", key(name)?, ["literal", values]]),
+        ("!in", [name, values @ ..]) => json!(["!", ["in", key(name)?, ["literal", values]]]),
+        ("all" | "any", filters) => {
+            let mut expression = vec![json!(operator)];
+            expression.extend(convert_all(filters)?);
+            Value::Array(expression)
+        }
+        ("none", filters) => {
+            let mut any = vec![json!("any")];
+            any.extend(convert_all(filters)?);
+            json!(["!", any])
+        }
+        _ => return Err(invalid()),
+    };
+
+    Ok(expression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STYLE: &str = r##"{
+        "version": 8,
+        "sources": {
+            "protomaps": { "type": "vector", "url": "pmtiles://toolangi.pmtiles" }
+        },
+        "layers": [
+            {
+                "id": "background",
+                "type": "background",
+                "paint": { "background-color": "#f8f4f0" }
+            },
+            {
+                "id": "water",
+                "type": "fill",
+                "source": "protomaps",
+                "source-layer": "water",
+                "paint": { "fill-color": "rgb(128, 160, 255)", "fill-opacity": 0.5 }
+            },
+            {
+                "id": "hills",
+                "type": "hillshade",
+                "source": "dem"
+            },
+            {
+                "id": "paths",
+                "type": "line",
+                "source": "protomaps",
+                "source-layer": "roads",
+                "minzoom": 12,
+                "layout": { "line-cap": "round", "line-join": "bevel" },
+                "paint": {
+                    "line-color": "hsl(0, 100%, 50%)",
+                    "line-width": ["interpolate", ["linear"], ["zoom"], 12, 1, 18, 4],
+                    "line-dasharray": [2, 1]
+                }
+            },
+            {
+                "id": "hidden",
+                "type": "line",
+                "source-layer": "roads",
+                "layout": { "visibility": "none" }
+            },
+            {
+                "id": "peaks",
+                "type": "circle",
+                "source-layer": "pois",
+                "maxzoom": 14,
+                "paint": { "circle-radius": 4, "circle-stroke-width": 1, "circle-stroke-color": "white" }
+            },
+            {
+                "id": "places",
+                "type": "symbol",
+                "source-layer": "places",
+                "layout": { "text-field": "{name}" },
+                "paint": { "text-color": "#333" }
+            }
+        ]
+    }"##;
+
+    fn rgba(color: Color) -> [f32; 4] {
+        color.components.map(|c| (c * 1000.0).round() / 1000.0)
+    }
+
+    fn at_zoom(zoom: f64) -> Context<'static> {
+        Context {
+            zoom,
+            feature: None,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let style = Style::parse(STYLE).unwrap();
+        let source_layers: Vec<Option<&str>> = style
+            .layers
+            .iter()
+            .map(|layer| layer.source_layer.as_deref())
+            .collect();
+        // The hillshade and hidden layers are left out.
+        assert_eq!(
+            source_layers,
+            [
+                None,
+                Some("water"),
+                Some("roads"),
+                Some("pois"),
+                Some("places")
+            ]
+        );
+        let context = at_zoom(15.0);
+
+        let Paint::Background(background) = &style.layers[0].paint else {
+            panic!("not a background layer");
+        };
+        assert_eq!(
+            rgba(background.color.evaluate(&context)),
+            [0.973, 0.957, 0.941, 1.0]
+        );
+
+        let water = &style.layers[1];
+        assert!(water.draws("water") && !water.draws("roads"));
+        let Paint::Fill(fill) = &water.paint else {
+            panic!("not a fill layer");
+        };
+        assert_eq!(
+            rgba(fill.color.with_opacity(&fill.opacity, &context)),
+            [0.502, 0.627, 1.0, 0.5]
+        );
+        assert!(fill.outline_color.is_none());
+
+        let paths = &style.layers[2];
+        assert!(!paths.visible_at(11.9) && paths.visible_at(12.0));
+        let Paint::Line(line) = &paths.paint else {
+            panic!("not a line layer");
+        };
+        assert_eq!(rgba(line.color.evaluate(&context)), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(line.width.evaluate(&context), 2.5);
+        assert_eq!(line.width.evaluate(&at_zoom(20.0)), 4.0);
+        assert_eq!((line.cap, line.join), (Cap::Round, Join::Bevel));
+        assert_eq!(line.dashes.evaluate(&context), [2.0, 1.0]);
+
+        let peaks = &style.layers[3];
+        assert!(peaks.visible_at(0.0) && !peaks.visible_at(14.0));
+        let Paint::Circle(circle) = &peaks.paint else {
+            panic!("not a circle layer");
+        };
+        assert_eq!(
+            (
+                circle.radius.evaluate(&context),
+                circle.stroke_width.evaluate(&context)
+            ),
+            (4.0, 1.0)
+        );
+        assert_eq!(
+            rgba(circle.stroke_color.evaluate(&context)),
+            [1.0, 1.0, 1.0, 1.0]
+        );
+
+        let Paint::Symbol(symbol) = &style.layers[4].paint else {
+            panic!("not a symbol layer");
+        };
+        assert_eq!(rgba(symbol.color.evaluate(&context)), [0.2, 0.2, 0.2, 1.0]);
+    }
+
+    fn line_paint(paint: Value) -> LinePaint {
+        let style = json!({
+            "layers": [{ "id": "roads", "type": "line", "source-layer": "roads", "paint": paint }]
+        });
+        let mut style = Style::parse(&style.to_string()).unwrap();
+        match style.layers.remove(0).paint {
+            Paint::Line(line) => line,
+            _ => panic!("not a line layer"),
+        }
+    }
+
+    #[test]
+    fn test_expressions() {
+        use mvt_reader::feature::{Feature, Value as PropertyValue};
+
+        let line = line_paint(json!({
+            "line-color": ["match", ["get", "kind"], "highway", "#ff0000", "path", "brown", "gray"],
+            "line-width": ["*", ["get", "lanes"], 2],
+            "line-opacity": ["step", ["zoom"], 0.5, 12, 1]
+        }));
+
+        let road = |properties: Vec<(&str, PropertyValue)>| Feature {
+            geometry: geo_types::Geometry::LineString(vec![(0.0, 0.0), (1.0, 1.0)].into()),
+            id: None,
+            properties: Some(
+                properties
+                    .into_iter()
+                    .map(|(key, value)| (String::from(key), value))
+                    .collect(),
+            ),
+        };
+        let highway = road(vec![
+            ("kind", PropertyValue::String(String::from("highway"))),
+            ("lanes", PropertyValue::UInt(3)),
+        ]);
+        let context = Context {
+            zoom: 10.0,
+            feature: Some(&highway),
+        };
+        assert_eq!(
+            rgba(line.color.with_opacity(&line.opacity, &context)),
+            [1.0, 0.0, 0.0, 0.5]
+        );
+        assert_eq!(line.width.evaluate(&context), 6.0);
+
+        // A feature without the properties gets the fallback colour and the default width.
+        let path = road(vec![]);
+        let context = Context {
+            zoom: 14.0,
+            feature: Some(&path),
+        };
+        assert_eq!(
+            rgba(line.color.with_opacity(&line.opacity, &context)),
+            [0.502, 0.502, 0.502, 1.0]
+        );
+        assert_eq!(line.width.evaluate(&context), 1.0);
+    }
+
+    #[test]
+    fn test_functions() {
+        // From the spec: a zoom function with a base, and property functions of each type.
+        let line = line_paint(json!({
+            "line-width": { "base": 2, "stops": [[10, 1], [12, 4]] },
+            "line-color": { "stops": [[10, "#000000"], [20, "#ffffff"]] },
+            "line-opacity": {
+                "property": "kind",
+                "type": "categorical",
+                "stops": [["highway", 1], ["path", 0.25]],
+                "default": 0.5
+            },
+            "line-dasharray": { "stops": [[10, [1, 1]], [14, [2, 1]]], "type": "interval" }
+        }));
+
+        assert_eq!(line.width.evaluate(&at_zoom(11.0)), 2.0);
+        assert_eq!(line.width.evaluate(&at_zoom(4.0)), 1.0);
+        assert_eq!(
+            rgba(line.color.evaluate(&at_zoom(15.0))),
+            [0.5, 0.5, 0.5, 1.0]
+        );
+        // Without a feature there's no kind, so it's the function's default.
+        assert_eq!(line.opacity.evaluate(&at_zoom(15.0)), 0.5);
+        assert_eq!(line.dashes.evaluate(&at_zoom(13.9)), [1.0, 1.0]);
+        assert_eq!(line.dashes.evaluate(&at_zoom(14.0)), [2.0, 1.0]);
+
+        let width = |function: Value| {
+            line_paint(json!({ "line-width": function }))
+                .width
+                .evaluate(&at_zoom(15.0))
+        };
+        assert_eq!(width(json!({ "type": "identity" })), 15.0);
+        assert_eq!(
+            width(json!({ "property": "lanes", "stops": [[1, 1], [4, 8]], "default": 3 })),
+            3.0
+        );
+    }
+
+    #[test]
+    fn test_invalid_style() {
+        let error = |json: &str| Style::parse(json).err().unwrap().to_string();
+        let error_in_paint = |paint: Value| {
+            let style = json!({ "layers": [{ "id": "roads", "type": "line", "paint": paint }] });
+            error(&style.to_string())
+        };
+
+        assert!(error("{}").contains("missing field `layers`"));
+        assert_eq!(
+            error_in_paint(json!({"line-width": "wide"})),
+            "invalid style: layer \"roads\": line-width should be a number"
+        );
+        assert_eq!(
+            error_in_paint(json!({"line-color": "wet"})),
+            "invalid style: layer \"roads\": line-color should be a CSS colour"
+        );
+        assert_eq!(
+            error_in_paint(json!({"line-dasharray": [1, "2"]})),
+            "invalid style: layer \"roads\": line-dasharray should be an array of numbers"
+        );
+        assert_eq!(
+            error_in_paint(json!({"line-width": ["number-format", 1, {}]})),
+            "invalid style: layer \"roads\": line-width is invalid: unsupported expression \"number-format\""
+        );
+        assert_eq!(
+            error_in_paint(json!({"line-width": {"stops": [[{"zoom": 1, "value": 1}, 1]]}})),
+            "invalid style: layer \"roads\": line-width is invalid: functions of both the zoom and a property aren't supported"
+        );
+        assert_eq!(
+            error_in_paint(json!({"line-width": {"type": "smooth", "stops": [[1, 1]]}})),
+            "invalid style: layer \"roads\": line-width is invalid: unknown function type \"smooth\""
+        );
+    }
+
+    fn filter(json: Value) -> Expression {
+        parse_filter(&json).unwrap()
+    }
+
+    #[test]
+    fn test_legacy_filters() {
+        // Old-style filters come out the same as the expressions they're short for.
+        let same = |legacy: Value, expression: Value| {
+            assert!(!is_expression_filter(&legacy), "{legacy} isn't legacy");
+            assert!(
+                is_expression_filter(&expression),
+                "{expression} isn't an expression"
+            );
+            assert_eq!(filter(legacy), filter(expression));
+        };
+
+        same(
+            json!(["==", "kind", "highway"]),
+            json!(["==", ["get", "kind"], "highway"]),
+        );
+        same(json!(["<", "rank", 5]), json!(["<", ["get", "rank"], 5]));
+        same(
+            json!(["==", "$type", "Polygon"]),
+            json!(["==", ["geometry-type"], "Polygon"]),
+        );
+        same(json!(["!=", "$id", 3]), json!(["!=", ["id"], 3]));
+        same(json!(["!has", "name"]), json!(["!", ["has", "name"]]));
+        same(json!(["has", "$type"]), json!(true));
+        same(
+            json!(["in", "kind", "path", "minor_road"]),
+            json!(["in", ["get", "kind"], ["literal", ["path", "minor_road"]]]),
+        );
+        same(
+            json!(["!in", "kind", "path"]),
+            json!(["!", ["in", ["get", "kind"], ["literal", ["path"]]]]),
+        );
+        same(
+            json!(["all", ["==", "kind", "path"], ["!has", "name"]]),
+            json!([
+                "all",
+                ["==", ["get", "kind"], "path"],
+                ["!", ["has", "name"]]
+            ]),
+        );
+        same(
+            json!(["none", ["==", "kind", "path"], ["==", "kind", "track"]]),
+            json!([
+                "!",
+                [
+                    "any",
+                    ["==", ["get", "kind"], "path"],
+                    ["==", ["get", "kind"], "track"]
+                ]
+            ]),
+        );
+
+        // These look the same either way.
+        assert!(is_expression_filter(&json!(["has", "name"])));
+        assert!(is_expression_filter(&json!([
+            "any",
+            true,
+            ["get", "oneway"]
+        ])));
+        assert!(is_expression_filter(&json!(false)));
+
+        assert_eq!(
+            parse_filter(&json!(["==", "kind"])).unwrap_err(),
+            "\"==\" takes 2 arguments, not 1"
+        );
+        assert_eq!(
+            parse_filter(&json!(["!in", 3, "path"])).unwrap_err(),
+            "[\"!in\",3,\"path\"] isn't a valid filter"
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        use crate::pmtiles::{PmTilesReader, TileCoord};
+        use crate::tile_cache::DecodedTile;
+
+        let archive = PmTilesReader::open("toolangi.pmtiles").unwrap();
+        let coord = TileCoord {
+            x: 7406,
+            y: 5018,
+            z: 13,
+        };
+        let tile = DecodedTile::load(&archive, coord).unwrap();
+
+        // Counts the features in the tile a layer with the filter would draw.
+        let count = |source_layer: &str, filter: Value| {
+            let style = json!({
+                "layers": [
+                    { "id": "test", "type": "line", "source-layer": source_layer, "filter": filter }
+                ]
+            });
+            let style = Style::parse(&style.to_string()).unwrap();
+            let layer = &style.layers[0];
+            tile.layers
+                .iter()
+                .filter(|tile_layer| layer.draws(&tile_layer.name))
+                .flat_map(|tile_layer| &tile_layer.features)
+                .filter(|feature| {
+                    layer.passes_filter(&Context {
+                        zoom: 13.0,
+                        feature: Some(feature),
+                    })
+                })
+                .count()
+        };
+
+        assert_eq!(count("roads", json!(true)), 10);
+        assert_eq!(count("roads", json!(false)), 0);
+        assert_eq!(count("roads", json!(["==", "kind", "major_road"])), 3);
+        assert_eq!(
+            count("roads", json!(["==", ["get", "kind"], "minor_road"])),
+            4
+        );
+        assert_eq!(
+            count("roads", json!(["!in", "kind", "major_road", "minor_road"])),
+            3
+        );
+        assert_eq!(
+            count(
+                "roads",
+                json!([
+                    "match",
+                    ["get", "kind"],
+                    ["major_road", "path"],
+                    true,
+                    false
+                ])
+            ),
+            6
+        );
+        assert_eq!(count("landuse", json!(["==", "$type", "Polygon"])), 11);
+        assert_eq!(
+            count("landuse", json!(["==", ["geometry-type"], "Point"])),
+            0
+        );
+        // A filter that doesn't come out as true or false filters everything out.
+        assert_eq!(count("roads", json!(["get", "kind"])), 0);
+    }
+
+    #[test]
+    fn test_fallback() {
+        let style = Style::fallback(&[String::from("landuse"), String::from("roads")]);
+        let source_layers: Vec<&str> = style
+            .layers
+            .iter()
+            .filter_map(|layer| layer.source_layer.as_deref())
+            .collect();
+        assert_eq!(source_layers, ["landuse", "roads", "landuse", "roads"]);
+        assert!(matches!(style.layers[1].paint, Paint::Fill(_)));
+        assert!(matches!(style.layers[2].paint, Paint::Line(_)));
+
+        let style = Style::fallback(&[]);
+        assert_eq!(style.layers.len(), 2);
+        assert!(style.layers.iter().all(|layer| layer.draws("anything")));
+    }
+}
@@ -0,0 +1,329 @@
+// Decoded tiles kept around between frames, so panning back and forth doesn't decompress and
+// parse the same tiles over and over. The least recently used tiles are dropped once the cache is
+// over its budget, except for the ones on screen.
+
+use geo_types::Geometry;
+use mvt_reader::Reader as MvtTile;
+use mvt_reader::feature::{Feature, Value};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use crate::pmtiles::{ParseError, TileCoord, TileId};
+use crate::tile_source::TileSource;
+
+pub struct DecodedLayer {
+    pub name: String,
+    pub features: Vec<Feature>,
+}
+
+// Every layer of a tile, with its features already decoded. A tile the source doesn't have is
+// cached as one with no layers.
+#[derive(Default)]
+pub struct DecodedTile {
+    pub layers: Vec<DecodedLayer>,
+}
+
+impl DecodedTile {
+    pub fn load(source: &dyn TileSource, coord: TileCoord) -> Result<Self, ParseError> {
+        match source.get_mvt(coord)? {
+            Some(mvt) => DecodedTile::decode(&mvt),
+            None => Ok(DecodedTile::default()),
+        }
+    }
+
+    pub fn decode(tile: &MvtTile) -> Result<Self, ParseError> {
+        let layers = tile
+            .get_layer_metadata()
+            .map_err(ParseError::InvalidTile)?
+            .into_iter()
+            .map(|layer| {
+                Ok(DecodedLayer {
+                    features: tile
+                        .get_features(layer.layer_index)
+                        .map_err(ParseError::InvalidTile)?,
+                    name: layer.name,
+                })
+            })
+            .collect::<Result<_, ParseError>>()?;
+
+        Ok(DecodedTile { layers })
+    }
+
+    // Roughly how much memory the tile takes up: its coordinates, properties and the structs
+    // holding them, but not allocator overhead or spare capacity.
+    pub fn estimated_size(&self) -> usize {
+        let mut size = size_of::<DecodedTile>();
+
+        for layer in &self.layers {
+            size += size_of::<DecodedLayer>() + layer.name.len();
+
+            for feature in &layer.features {
+                size += size_of::<Feature>() + geometry_size(&feature.geometry);
+
+                for (key, value) in feature.properties.iter().flatten() {
+                    size += size_of::<(String, Value)>() + key.len();
+                    if let Value::String(s) = value {
+                        size += s.len();
+                    }
+                }
+            }
+        }
+
+        size
+    }
+}
+
+fn geometry_size(geometry: &Geometry<f32>) -> usize {
+    let coord = size_of::<geo_types::Coord<f32>>();
+
+    match geometry {
+        Geometry::Point(_) => coord,
+        Geometry::MultiPoint(points) => points.0.len() * coord,
+        Geometry::LineString(line) => line.0.len() * coord,
+        Geometry::MultiLineString(lines) => lines.iter().map(|l| l.0.len() * coord).sum(),
+        Geometry::Polygon(polygon) => polygon_size(polygon),
+        Geometry::MultiPolygon(polygons) => polygons.iter().map(polygon_size).sum(),
+        Geometry::GeometryCollection(collection) => collection.iter().map(geometry_size).sum(),
+        // MVT only has points, lines and polygons.
+        _ => 0,
+    }
+}
+
+fn polygon_size(polygon: &geo_types::Polygon<f32>) -> usize {
+    let rings = std::iter::once(polygon.exterior()).chain(polygon.interiors());
+    rings
+        .map(|ring| ring.0.len() * size_of::<geo_types::Coord<f32>>())
+        .sum()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses, {} evictions",
+            self.hits, self.misses, self.evictions
+        )
+    }
+}
+
+struct CacheEntry {
+    tile: Arc<DecodedTile>,
+    size: usize,
+    last_used: u64,
+}
+
+pub struct TileCache {
+    // In bytes, going by DecodedTile::estimated_size.
+    budget: usize,
+    used: usize,
+    entries: HashMap<TileId, CacheEntry>,
+    // Each entry's last_used, oldest first, to find what to evict.
+    recency: BTreeMap<u64, TileId>,
+    clock: u64,
+    // Tiles on screen, which stay cached even if that puts the cache over budget.
+    pinned: HashSet<TileId>,
+    stats: CacheStats,
+}
+
+impl TileCache {
+    pub fn new(budget: usize) -> Self {
+        TileCache {
+            budget,
+            used: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            pinned: HashSet::new(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&mut self, id: TileId) -> Option<Arc<DecodedTile>> {
+        let Some(entry) = self.entries.get_mut(&id) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+
+        self.recency.remove(&entry.last_used);
+        self.clock += 1;
+        entry.last_used = self.clock;
+        self.recency.insert(self.clock, id);
+
+        Some(entry.tile.clone())
+    }
+
+    // Like get, but for looking around for a stand-in tile, so it doesn't count as a hit or miss
+    // or make the tile any less likely to be evicted.
+    pub fn peek(&self, id: TileId) -> Option<Arc<DecodedTile>> {
+        self.entries.get(&id).map(|entry| entry.tile.clone())
+    }
+
+    pub fn insert(&mut self, id: TileId, tile: Arc<DecodedTile>) {
+        self.remove(id);
+
+        self.clock += 1;
+        let size = tile.estimated_size();
+        let entry = CacheEntry {
+            tile,
+            size,
+            last_used: self.clock,
+        };
+        self.used += size;
+        self.entries.insert(id, entry);
+        self.recency.insert(self.clock, id);
+
+        self.evict();
+    }
+
+    // Replaces the set of tiles that mustn't be evicted. Tiles that were pinned before can be
+    // evicted again straight away if the cache is over budget.
+    pub fn set_visible(&mut self, ids: impl IntoIterator<Item = TileId>) {
+        self.pinned = ids.into_iter().collect();
+        self.evict();
+    }
+
+    fn remove(&mut self, id: TileId) {
+        if let Some(entry) = self.entries.remove(&id) {
+            self.recency.remove(&entry.last_used);
+            self.used -= entry.size;
+        }
+    }
+
+    fn evict(&mut self) {
+        let mut excess = self.used.saturating_sub(self.budget);
+        if excess == 0 {
+            return;
+        }
+
+        let mut evicted = Vec::new();
+        for id in self.recency.values() {
+            if excess == 0 {
+                break;
+            }
+            if !self.pinned.contains(id) {
+                excess = excess.saturating_sub(self.entries[id].size);
+                evicted.push(*id);
+            }
+        }
+
+        for id in evicted {
+            self.remove(id);
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::PmTilesReader;
+
+    use geo_types::{LineString, coord};
+
+    fn tile(points: usize) -> Arc<DecodedTile> {
+        let line: LineString<f32> = (0..points)
+            .map(|i| coord! { x: i as f32, y: 0.0 })
+            .collect();
+        let feature = Feature {
+            geometry: Geometry::LineString(line),
+            id: None,
+            properties: None,
+        };
+        Arc::new(DecodedTile {
+            layers: vec![DecodedLayer {
+                name: String::from("roads"),
+                features: vec![feature],
+            }],
+        })
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let size = tile(100).estimated_size();
+        let mut cache = TileCache::new(size * 3);
+
+        for id in 0..3 {
+            cache.insert(TileId(id), tile(100));
+        }
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.used(), size * 3);
+
+        // Using tile 0 makes tile 1 the oldest, so that's the one to go.
+        assert!(cache.get(TileId(0)).is_some());
+        cache.insert(TileId(3), tile(100));
+        assert!(cache.get(TileId(1)).is_none());
+        assert!(cache.get(TileId(0)).is_some());
+        assert_eq!(cache.len(), 3);
+
+        // A big tile pushes out as many as it needs to.
+        cache.insert(TileId(4), tile(250));
+        assert_eq!(cache.len(), 1);
+        assert!(cache.used() <= size * 3);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert_eq!(stats.evictions, 4);
+    }
+
+    #[test]
+    fn test_visible_tiles_stay() {
+        let size = tile(100).estimated_size();
+        let mut cache = TileCache::new(size * 2);
+
+        cache.insert(TileId(0), tile(100));
+        cache.insert(TileId(1), tile(100));
+        cache.set_visible([TileId(0), TileId(1), TileId(2)]);
+
+        // Everything is on screen, so the cache has to go over budget.
+        cache.insert(TileId(2), tile(100));
+        assert_eq!(cache.len(), 3);
+
+        // Until the view moves on.
+        cache.set_visible([TileId(2)]);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(TileId(0)).is_none());
+        assert!(cache.get(TileId(2)).is_some());
+    }
+
+    #[test]
+    fn test_load() {
+        let archive = PmTilesReader::open_mmap("toolangi.pmtiles").unwrap();
+
+        let coord = TileCoord {
+            x: 7406,
+            y: 5018,
+            z: 13,
+        };
+        let tile = DecodedTile::load(&archive, coord).unwrap();
+        assert!(tile.layers.iter().any(|layer| layer.name == "roads"));
+        assert!(tile.layers.iter().any(|layer| !layer.features.is_empty()));
+
+        // Tiles the source doesn't have come back empty.
+        let missing = DecodedTile::load(&archive, TileCoord { x: 0, y: 0, z: 13 }).unwrap();
+        assert!(missing.layers.is_empty());
+        assert!(missing.estimated_size() < tile.estimated_size());
+    }
+}
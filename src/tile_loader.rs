@@ -0,0 +1,228 @@
+// Loads tiles on a pool of worker threads, so the viewer never waits on IO or decoding. The
+// viewer says which tiles it's missing each frame; anything it stops asking for is dropped from
+// the queue, and if a worker had already started on it, the result is thrown away.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::pmtiles::TileCoord;
+use crate::tile_cache::DecodedTile;
+use crate::tile_source::TileSource;
+
+pub struct LoadedTile {
+    pub coord: TileCoord,
+    // Some of the errors inside a ParseError can't be sent between threads, so only the message
+    // makes it across.
+    pub tile: Result<DecodedTile, String>,
+}
+
+pub struct TileLoader {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct Queue {
+    // Waiting for a worker, in the order they were asked for.
+    pending: VecDeque<TileCoord>,
+    in_flight: HashSet<TileCoord>,
+    // Everything in the last request. Finished tiles which aren't in here any more are dropped.
+    wanted: HashSet<TileCoord>,
+    shutdown: bool,
+}
+
+impl TileLoader {
+    // `deliver` is called on the worker threads, once for each tile that's still wanted when it
+    // finishes loading.
+    pub fn new(
+        source: Arc<dyn TileSource>,
+        threads: usize,
+        deliver: impl Fn(LoadedTile) + Send + Sync + 'static,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            available: Condvar::new(),
+        });
+        let deliver = Arc::new(deliver);
+
+        let workers = (0..threads.max(1))
+            .map(|i| {
+                let shared = shared.clone();
+                let source = source.clone();
+                let deliver = deliver.clone();
+                thread::Builder::new()
+                    .name(format!("tile loader {i}"))
+                    .spawn(move || work(&shared, &*source, &*deliver))
+                    .expect("failed to spawn tile loader thread")
+            })
+            .collect();
+
+        TileLoader { shared, workers }
+    }
+
+    // Replaces whatever was asked for before. Tiles already queued or loading aren't asked for
+    // twice, and queued tiles that aren't in `coords` are cancelled.
+    pub fn request(&self, coords: impl IntoIterator<Item = TileCoord>) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        let coords: Vec<TileCoord> = coords.into_iter().collect();
+        queue.wanted = coords.iter().copied().collect();
+
+        let Queue {
+            pending,
+            in_flight,
+            wanted,
+            ..
+        } = &mut *queue;
+        pending.retain(|coord| wanted.contains(coord));
+        for coord in coords {
+            if !in_flight.contains(&coord) && !pending.contains(&coord) {
+                pending.push_back(coord);
+                self.shared.available.notify_one();
+            }
+        }
+    }
+}
+
+impl Drop for TileLoader {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().shutdown = true;
+        self.shared.available.notify_all();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(shared: &Shared, source: &dyn TileSource, deliver: &dyn Fn(LoadedTile)) {
+    loop {
+        let coord = {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                if queue.shutdown {
+                    return;
+                }
+                if let Some(coord) = queue.pending.pop_front() {
+                    queue.in_flight.insert(coord);
+                    break coord;
+                }
+                queue = shared.available.wait(queue).unwrap();
+            }
+        };
+
+        let tile = DecodedTile::load(source, coord).map_err(|e| e.to_string());
+
+        let still_wanted = {
+            let mut queue = shared.queue.lock().unwrap();
+            queue.in_flight.remove(&coord);
+            queue.wanted.contains(&coord)
+        };
+        if still_wanted {
+            deliver(LoadedTile { coord, tile });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::{Compression, Metadata, ParseError, PmTilesReader};
+    use crate::tile_source::TileSetInfo;
+
+    use bytes::Bytes;
+
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // Says which tile it was asked for, then holds on to it until the test lets it go, so the
+    // test decides exactly when each load finishes.
+    struct GatedSource {
+        archive: PmTilesReader,
+        started: Mutex<mpsc::Sender<TileCoord>>,
+        gate: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl TileSource for GatedSource {
+        fn info(&self) -> TileSetInfo {
+            self.archive.info()
+        }
+
+        fn metadata(&self) -> Arc<Metadata> {
+            self.archive.metadata()
+        }
+
+        fn get_tile_data(
+            &self,
+            coord: TileCoord,
+        ) -> Result<Option<(Bytes, Compression)>, ParseError> {
+            self.started.lock().unwrap().send(coord).unwrap();
+            // Only times out if the test has already failed, rather than leaving it hanging.
+            let _ = self.gate.lock().unwrap().recv_timeout(TIMEOUT);
+            TileSource::get_tile_data(&self.archive, coord)
+        }
+    }
+
+    #[test]
+    fn test_loader() {
+        let (started_sender, started) = mpsc::channel();
+        let (gate, gate_receiver) = mpsc::channel();
+        let source = Arc::new(GatedSource {
+            archive: PmTilesReader::open("toolangi.pmtiles").unwrap(),
+            started: Mutex::new(started_sender),
+            gate: Mutex::new(gate_receiver),
+        });
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let loader = TileLoader::new(source, 1, move |tile| {
+            sender.lock().unwrap().send(tile).unwrap();
+        });
+
+        // Ask for a column of tiles, then scroll away while the first one is loading.
+        let column: Vec<TileCoord> = (0..10)
+            .map(|y| TileCoord {
+                x: 7406,
+                y: 5010 + y,
+                z: 13,
+            })
+            .collect();
+        loader.request(column.iter().copied());
+        assert_eq!(started.recv_timeout(TIMEOUT).unwrap(), column[0]);
+        let coord = TileCoord {
+            x: 7406,
+            y: 5018,
+            z: 13,
+        };
+        loader.request([coord]);
+        gate.send(()).unwrap();
+
+        // The first tile finished, but nobody wants it any more, and the rest of the column was
+        // cancelled before it started.
+        assert_eq!(started.recv_timeout(TIMEOUT).unwrap(), coord);
+        gate.send(()).unwrap();
+        let loaded = receiver.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(loaded.coord, coord);
+        assert!(!loaded.tile.unwrap().layers.is_empty());
+
+        // Asking again for a tile that's still loading doesn't load it twice.
+        let missing = TileCoord { x: 0, y: 0, z: 13 };
+        loader.request([missing]);
+        assert_eq!(started.recv_timeout(TIMEOUT).unwrap(), missing);
+        loader.request([missing]);
+        gate.send(()).unwrap();
+        let loaded = receiver.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(loaded.coord, missing);
+        assert!(loaded.tile.unwrap().layers.is_empty());
+
+        drop(loader);
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(started.try_iter().collect::<Vec<_>>(), Vec::new());
+    }
+}
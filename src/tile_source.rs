@@ -0,0 +1,310 @@
+// Where tiles come from. The viewer and the commands go through `TileSource`, so a PMTiles archive,
+// an MBTiles database and a folder of `{z}/{x}/{y}.pbf` files all behave the same.
+
+use bytes::Bytes;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::http_reader::is_url;
+use crate::mbtiles::{MbTilesReader, is_mbtiles};
+use crate::pmtiles::{
+    Compression, Metadata, ParseError, PmTilesReader, Position, TileCoord, TileType, tile_bounds,
+};
+use crate::tile_cache::CacheStats;
+
+// The parts of a PMTiles header that make sense for any kind of source.
+#[derive(Clone, Debug)]
+pub struct TileSetInfo {
+    pub tile_type: TileType,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    pub min_position: Position,
+    pub max_position: Position,
+    pub center_zoom: u8,
+    pub center_position: Position,
+    // How the directories and metadata are compressed, for sources that have any (PMTiles).
+    pub internal_compression: Option<Compression>,
+}
+
+pub trait TileSource: Send + Sync {
+    fn info(&self) -> TileSetInfo;
+
+    fn metadata(&self) -> Arc<Metadata>;
+
+    // Returns the tile as it is stored and how it is compressed, or None if there is no tile at
+    // these coordinates.
+    fn get_tile_data(&self, coord: TileCoord) -> Result<Option<(Bytes, Compression)>, ParseError>;
+
+    // Returns the decompressed tile.
+    fn get_tile(&self, coord: TileCoord) -> Result<Option<Bytes>, ParseError> {
+        let Some((data, compression)) = self.get_tile_data(coord)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(Bytes::from(compression.decompress(&data)?)))
+    }
+
+    fn get_mvt(&self, coord: TileCoord) -> Result<Option<mvt_reader::Reader>, ParseError> {
+        let Some(bytes) = self.get_tile(coord)? else {
+            return Ok(None);
+        };
+
+        let reader = mvt_reader::Reader::new(bytes.to_vec()).map_err(ParseError::InvalidTile)?;
+
+        Ok(Some(reader))
+    }
+
+    // How well the source's own caching is doing, for sources which have any.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+}
+
+// Opens a directory of tiles, an MBTiles database or, failing those, a PMTiles archive, which may
+// be an http(s) URL.
+pub fn open(path: &str) -> Result<Box<dyn TileSource>, ParseError> {
+    if is_directory(path) {
+        Ok(Box::new(DirectorySource::open(path)?))
+    } else if is_mbtiles(path) {
+        Ok(Box::new(MbTilesReader::open(path)?))
+    } else if is_url(path) {
+        Ok(Box::new(PmTilesReader::open(path)?))
+    } else {
+        Ok(Box::new(PmTilesReader::open_mmap(path)?))
+    }
+}
+
+pub fn is_directory(path: &str) -> bool {
+    !is_url(path) && Path::new(path).is_dir()
+}
+
+// MBTiles and tile directories don't say how tiles are compressed. Vector tiles are gzipped by
+// convention, but not always, so look for gzip's magic number.
+pub fn detect_compression(data: &[u8]) -> Compression {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        Compression::GZip
+    } else {
+        Compression::None
+    }
+}
+
+// From a file extension, or an MBTiles `format`.
+pub fn tile_type_from_name(name: &str) -> TileType {
+    match name {
+        "pbf" | "mvt" => TileType::Mvt,
+        "png" => TileType::Png,
+        "jpg" | "jpeg" => TileType::Jpeg,
+        "webp" => TileType::WebP,
+        "avif" => TileType::Avif,
+        _ => TileType::Unknown,
+    }
+}
+
+// A folder of `{z}/{x}/{y}.{ext}` files, optionally with a TileJSON `metadata.json` alongside.
+pub struct DirectorySource {
+    root: PathBuf,
+    extension: String,
+    info: TileSetInfo,
+    metadata: Arc<Metadata>,
+}
+
+impl DirectorySource {
+    pub fn open(root: impl AsRef<Path>) -> Result<Self, ParseError> {
+        let root = root.as_ref().to_path_buf();
+
+        let zooms = numbered_entries(&root)?;
+        let (Some(&min_zoom), Some(&max_zoom)) = (zooms.iter().min(), zooms.iter().max()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no {{z}}/{{x}}/{{y}} tiles in {}", root.display()),
+            )
+            .into());
+        };
+        let min_zoom = u8::try_from(min_zoom).map_err(|_| ParseError::TooHighZIndex)?;
+        let max_zoom = u8::try_from(max_zoom).map_err(|_| ParseError::TooHighZIndex)?;
+
+        // The lowest zoom level has the fewest tiles, so it's the cheapest to look through for the
+        // extent of the tiles and what kind they are.
+        let mut coords = Vec::new();
+        let mut extension = None;
+        let min_zoom_dir = root.join(min_zoom.to_string());
+        for x in numbered_entries(&min_zoom_dir)? {
+            for entry in fs::read_dir(min_zoom_dir.join(x.to_string()))? {
+                let path = entry?.path();
+                let y = path
+                    .file_stem()
+                    .and_then(|s| s.to_str()?.parse::<u32>().ok());
+                let ext = path.extension().and_then(|s| s.to_str());
+
+                if let (Some(y), Some(ext)) = (y, ext) {
+                    coords.push(TileCoord { x, y, z: min_zoom });
+                    extension.get_or_insert_with(|| ext.to_owned());
+                }
+            }
+        }
+        let extension = extension.unwrap_or_else(|| String::from("pbf"));
+
+        let metadata: Metadata = match fs::read(root.join("metadata.json")) {
+            Ok(json) => serde_json::from_slice(&json).map_err(ParseError::InvalidMetadata)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Metadata::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        // TileJSON keeps bounds and center as arrays, [west, south, east, north] and
+        // [lon, lat, zoom].
+        let numbers = |key: &str| -> Option<Vec<f64>> {
+            let values = metadata.extra.get(key)?.as_array()?;
+            values.iter().map(|v| v.as_f64()).collect()
+        };
+        let (min_position, max_position) = match numbers("bounds").as_deref() {
+            Some(&[west, south, east, north]) => (
+                Position {
+                    lat: south,
+                    long: west,
+                },
+                Position {
+                    lat: north,
+                    long: east,
+                },
+            ),
+            _ => tile_bounds(&coords),
+        };
+        let (center_zoom, center_position) = match numbers("center").as_deref() {
+            Some(&[long, lat, zoom]) => (zoom as u8, Position { lat, long }),
+            _ => (
+                min_zoom,
+                Position {
+                    lat: (min_position.lat + max_position.lat) / 2.0,
+                    long: (min_position.long + max_position.long) / 2.0,
+                },
+            ),
+        };
+
+        let info = TileSetInfo {
+            tile_type: tile_type_from_name(&extension),
+            min_zoom,
+            max_zoom,
+            min_position,
+            max_position,
+            center_zoom,
+            center_position,
+            internal_compression: None,
+        };
+
+        Ok(DirectorySource {
+            root,
+            extension,
+            info,
+            metadata: Arc::new(metadata),
+        })
+    }
+}
+
+// The entries in a directory which are named with a number, eg. zoom levels or columns.
+fn numbered_entries(dir: &Path) -> Result<Vec<u32>, ParseError> {
+    let mut numbers = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if let Some(n) = entry.file_name().to_str().and_then(|s| s.parse().ok())
+            && entry.file_type()?.is_dir()
+        {
+            numbers.push(n);
+        }
+    }
+
+    Ok(numbers)
+}
+
+impl TileSource for DirectorySource {
+    fn info(&self) -> TileSetInfo {
+        self.info.clone()
+    }
+
+    fn metadata(&self) -> Arc<Metadata> {
+        self.metadata.clone()
+    }
+
+    fn get_tile_data(&self, coord: TileCoord) -> Result<Option<(Bytes, Compression)>, ParseError> {
+        let path = self
+            .root
+            .join(coord.z.to_string())
+            .join(coord.x.to_string())
+            .join(format!("{}.{}", coord.y, self.extension));
+
+        match fs::read(path) {
+            Ok(data) => {
+                let compression = detect_compression(&data);
+                Ok(Some((Bytes::from(data), compression)))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_source() {
+        let archive = PmTilesReader::open_mmap("toolangi.pmtiles").unwrap();
+        let root = std::env::temp_dir().join(format!("protography-tiles-{}", std::process::id()));
+
+        // Lay some of the archive out as files, gzipped or not.
+        let coords: Vec<TileCoord> = archive
+            .tile_entries()
+            .unwrap()
+            .iter()
+            .map(|entry| TileCoord::try_from(crate::pmtiles::TileId(entry.id)).unwrap())
+            .filter(|coord| (3..=10).contains(&coord.z))
+            .collect();
+        for (i, coord) in coords.iter().enumerate() {
+            let dir = root.join(coord.z.to_string()).join(coord.x.to_string());
+            fs::create_dir_all(&dir).unwrap();
+
+            let (data, _) = TileSource::get_tile_data(&archive, *coord)
+                .unwrap()
+                .unwrap();
+            let data = if i % 2 == 0 {
+                data
+            } else {
+                Bytes::from(Compression::GZip.decompress(&data).unwrap())
+            };
+            fs::write(dir.join(format!("{}.pbf", coord.y)), data).unwrap();
+        }
+
+        let source = open(root.to_str().unwrap()).unwrap();
+        let info = source.info();
+        assert_eq!(info.tile_type, TileType::Mvt);
+        assert_eq!((info.min_zoom, info.max_zoom), (3, 10));
+        assert!(info.min_position.long <= 145.39 && info.max_position.long >= 145.56);
+        assert!(source.metadata().vector_layers.is_empty());
+
+        for coord in &coords {
+            assert_eq!(
+                source.get_tile(*coord).unwrap(),
+                TileSource::get_tile(&archive, *coord).unwrap()
+            );
+        }
+        assert!(source.get_mvt(coords[0]).unwrap().is_some());
+        assert!(
+            source
+                .get_tile(TileCoord { x: 0, y: 0, z: 3 })
+                .unwrap()
+                .is_none()
+        );
+
+        // TileJSON metadata is picked up, bounds and all.
+        let tilejson = r#"{"name": "test", "bounds": [145.0, -38.0, 146.0, -37.0]}"#;
+        fs::write(root.join("metadata.json"), tilejson).unwrap();
+        let source = open(root.to_str().unwrap()).unwrap();
+        assert_eq!(source.metadata().name.as_deref(), Some("test"));
+        assert_eq!(source.info().min_position.long, 145.0);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}